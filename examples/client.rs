@@ -5,13 +5,13 @@ use metrics_exporter_scope::{protocol, ClientSettings};
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut client = TcpStream::connect("127.0.0.1:5001")?;
     let version = protocol::read_version(&client)?;
-    if version != protocol::VERSION {
+    if !protocol::is_version_supported(version) {
         return Err("Incompatible version".into());
     }
     let settings = ClientSettings::new(Duration::from_millis(100));
     protocol::write_client_settings(&mut client, &settings)?;
     loop {
-        match protocol::read_packet(&mut client) {
+        match protocol::read_packet_versioned(&mut client, version) {
             Ok(packet) => {
                 dbg!(&packet);
             }
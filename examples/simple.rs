@@ -1,5 +1,5 @@
-use metrics::gauge;
-use metrics_exporter_scope::ScopeBuilder;
+use metrics::{describe_gauge, gauge, Unit};
+use metrics_exporter_scope::{emit_event, ScopeBuilder};
 use rtsc::time::interval;
 use std::time::Duration;
 
@@ -20,6 +20,8 @@ impl RoundTo for f64 {
 fn main() {
     // build scope recorder
     ScopeBuilder::new().install().unwrap();
+    // units are displayed by the client on the Y axis
+    describe_gauge!("~elapsed", Unit::Seconds, "time since start");
     // generate some metrics
     for (i, _) in interval(Duration::from_millis(10)).enumerate() {
         gauge!("~i%1000").set((i % 1000) as f64); // to scope, default plot, default color
@@ -32,6 +34,10 @@ fn main() {
         gauge!("~i_cos2", "plot" => "trig2", "color" => "cyan")
             .set((i as f64 / 180.0).cos().round_to(3)); // to scope
         gauge!("~i%100", "plot" => "counts", "color" => "#336699").set((i % 100) as f64); // to scope
+        gauge!("~elapsed").set(i as f64 / 100.0); // to scope, with the unit
         gauge!("iteration").set(i as f64); // ignored
+        if i % 500 == 0 {
+            emit_event("cycle", format!("cycle {}", i / 500)); // to scope timeline
+        }
     }
 }
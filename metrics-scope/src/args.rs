@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::time::Duration;
 
 use clap::{
     builder::{TypedValueParser, ValueParserFactory},
@@ -7,8 +8,45 @@ use clap::{
 
 #[derive(Parser)]
 pub struct Args {
-    #[clap(help = "HOST[:PORT], the default port is 5001")]
+    #[clap(
+        help = "HOST[:PORT], the default port is 5001 (or unix:PATH for a Unix socket, FILE with --follow/--replay/--offline, or fifo:PATH for a named pipe)"
+    )]
     pub source: String,
+    #[clap(
+        long,
+        help = "Follow a growing recording file (given as the source) instead of connecting to a server"
+    )]
+    pub follow: bool,
+    #[clap(
+        long,
+        conflicts_with = "follow",
+        help = "Replay a recording file (given as the source) at the recorded pace"
+    )]
+    pub replay: bool,
+    #[clap(
+        long,
+        conflicts_with_all = ["follow", "replay"],
+        help = "Load a whole recording file (given as the source) and browse it with the seek bar and the playback controls"
+    )]
+    pub offline: bool,
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["follow", "replay", "offline"],
+        help = "Record the received stream to a file, which can be viewed later with --replay or --follow"
+    )]
+    pub record: Option<std::path::PathBuf>,
+    #[clap(
+        long,
+        help = "Replay speed (with --replay or --offline), e.g. 4 plays 4x faster, 0.25 - 4x slower",
+        default_value = "1"
+    )]
+    pub speed: f64,
+    #[clap(
+        long,
+        help = "Check predefined settings against metrics of the source and exit (with non-zero code if any refer to missing plots or metrics)"
+    )]
+    pub check: bool,
     #[clap(
         short = 's',
         long,
@@ -23,8 +61,96 @@ pub struct Args {
         default_value = "10"
     )]
     pub timeout: u64,
+    #[clap(
+        long,
+        value_name = "SECS",
+        help = "Show connection diagnostics instead of the splash if there is no data in this time",
+        default_value = "5"
+    )]
+    pub connect_timeout: f64,
+    #[clap(
+        long,
+        value_name = "SECS",
+        help = "Max delay between reconnect attempts (the delay starts from 1 second and doubles after every failed attempt)",
+        default_value = "30"
+    )]
+    pub reconnect_max_delay: f64,
+    #[clap(
+        long,
+        value_enum,
+        help = "Data retention: rolling (the max time window) or all (since connect, capped by the memory budget)",
+        default_value = "rolling"
+    )]
+    pub retain: Retain,
+    #[clap(
+        long,
+        value_name = "MIB",
+        help = "Memory budget for the retained data in MiB (with --retain all)",
+        default_value = "512"
+    )]
+    pub retain_memory: usize,
+    #[clap(
+        long,
+        help = "Snapshot timestamp unit, coarser units save bandwidth",
+        default_value = "ns"
+    )]
+    pub timestamp_unit: TimestampUnit,
+    #[clap(
+        long,
+        value_name = "SECS",
+        help = "Adaptive sampling: flat metrics are sent less frequently, down to once per the interval"
+    )]
+    pub adaptive_max_interval: Option<f64>,
+    #[clap(
+        long,
+        help = "Adaptive sampling: changes, which do not exceed the threshold, are considered flat",
+        default_value = "0"
+    )]
+    pub adaptive_threshold: f64,
+    #[clap(
+        long,
+        value_name = "KB_S",
+        help = "Ask the server to keep the stream within the bandwidth budget (KB/s), by sending changed metrics only and less often"
+    )]
+    pub bandwidth_limit: Option<f64>,
+    #[clap(
+        long,
+        help = "Ask the server to send changed metrics only (delta snapshots), the last values of the rest are held"
+    )]
+    pub delta: bool,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Ask the server to send snapshots in batches of N, which cuts the overhead at fast sampling rates but delays the data by up to N-1 sampling intervals",
+        default_value = "1"
+    )]
+    pub batch: u32,
+    #[cfg(feature = "compression")]
+    #[clap(long, help = "Ask the server to compress packets with zstd")]
+    pub compress: bool,
+    #[clap(long, help = "Shade gaps (missing data) in charts")]
+    pub show_gaps: bool,
+    #[clap(
+        long,
+        value_name = "COLOR",
+        help = "Gap shading color (a color name, #RRGGBB or #RGB)"
+    )]
+    pub gap_color: Option<String>,
+    #[clap(long, help = "Mark the most recent value of each metric with a dot")]
+    pub markers: bool,
+    #[clap(
+        long,
+        value_name = "SEED",
+        help = "Derive automatic metric colors from metric names with the seed, so they are stable across sessions and machines"
+    )]
+    pub color_seed: Option<u64>,
     #[clap(long, help = "Hide legend")]
     pub hide_legend: bool,
+    #[clap(
+        long,
+        help = "Show the rich legend (current value, min, max) instead of the default one"
+    )]
+    pub rich_legend: bool,
     #[clap(
         short = 'w',
         long,
@@ -36,12 +162,71 @@ pub struct Args {
     pub chart_cols: f32,
     #[clap(long, help = "Chart aspect ratio", default_value = "2")]
     pub chart_aspect: f32,
+    #[clap(long, help = "Draw all metrics in a single plot, ignoring plot labels")]
+    pub single_plot: bool,
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Show only metrics with names (original or transformed) matching the expression"
+    )]
+    pub filter: Option<regex::Regex>,
+    #[clap(
+        long,
+        value_name = "REGEX",
+        help = "Ask the server to send only metrics with original names matching the expression"
+    )]
+    pub server_filter: Option<regex::Regex>,
+    #[clap(
+        long,
+        value_name = "TOKEN",
+        help = "Authentication token, required by the server"
+    )]
+    pub token: Option<String>,
+    #[clap(
+        long,
+        value_name = "GLOB",
+        value_delimiter = ',',
+        help = "Ask the server to send only metrics with original names matching any of the glob patterns (comma-separated, * and ? wildcards)"
+    )]
+    pub metrics: Vec<String>,
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Ask the server to send at most N metrics (the ones with the highest priority label values)"
+    )]
+    pub max_metrics: Option<usize>,
+    #[clap(
+        long = "metric-interval",
+        value_name = "INTERVAL",
+        help = "Ask the server to send the metric at own interval with an optional phase, to stagger the load (metric=SECS or metric=SECS@PHASE_SECS)"
+    )]
+    pub metric_interval: Vec<MetricInterval>,
+    #[clap(
+        long,
+        help = "Warn before rendering more metrics than this",
+        default_value = "100"
+    )]
+    pub max_series: usize,
     #[clap(long, help = "Override system colors")]
     pub theme: Option<Theme>,
+    #[clap(
+        long,
+        value_enum,
+        help = "Line interpolation between samples (display only), can be overridden per plot",
+        default_value = "linear"
+    )]
+    pub interpolation: Interpolation,
+    #[clap(
+        long,
+        value_enum,
+        help = "Plot order: name (alphabetical) or first-seen (as plots appear, metrics of the same info packet are still sorted by name)",
+        default_value = "name"
+    )]
+    pub plot_order: PlotOrder,
     #[clap(
         long = "y-range",
         value_name = "RANGE",
-        help = "Predefined Y-range (plot=[min],[max])"
+        help = "Predefined Y-range (plot=[min],[max][,log]), log - a logarithmic Y-axis"
     )]
     pub predefined_y_range: Vec<PredefinedYRange>,
     #[clap(
@@ -50,12 +235,68 @@ pub struct Args {
         help = "Predefined SMA (plot/metric=window or metric=window)"
     )]
     pub predefined_sma: Vec<PredefinedSma>,
+    #[clap(
+        long = "rate",
+        value_name = "RATE",
+        help = "Predefined rate of change, per second (plot/metric[=replace|overlay] or metric[=replace|overlay]), replace - plot the rate instead of the values (default), overlay - alongside"
+    )]
+    pub predefined_rate: Vec<PredefinedRate>,
     #[clap(
         long = "trigger",
         value_name = "TRIGGER",
         help = "Predefined Trigger (plot/metric=[below],[above] or metric=[below],[above])"
     )]
     pub predefined_trigger: Vec<PredefinedTrigger>,
+    #[clap(
+        long = "sane-range",
+        value_name = "RANGE",
+        help = "Reject values outside the range as garbage (plot=[min],[max], plot/metric=[min],[max] or metric=[min],[max])"
+    )]
+    pub sane_range: Vec<PredefinedSaneRange>,
+    #[clap(
+        long = "name-transform",
+        value_name = "s/REGEX/REPLACEMENT/[g]",
+        help = "Rename incoming metrics with a regular expression replacement"
+    )]
+    pub name_transform: Option<NameTransform>,
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        help = "Run a command when a trigger fires ({metric}, {value}, {direction} and {t} are substituted)"
+    )]
+    pub on_trigger: Option<TriggerCommand>,
+    #[clap(
+        long,
+        value_name = "SECS",
+        help = "Pause automatically in the given time after the first data arrives"
+    )]
+    pub autopause_after: Option<f64>,
+    #[clap(
+        long,
+        value_name = "SECS",
+        help = "Resume automatically after a trigger pause (0 - stay paused until resumed manually)",
+        default_value = "0"
+    )]
+    pub trigger_hold: f64,
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Write per-metric statistics of the buffered data on exit to a file (to stdout if no file is given)"
+    )]
+    pub summary_on_exit: Option<Option<String>>,
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Dashboard config (TOML: y-range, sma, rate, trigger, sane-range and colors), overrides the options, reloaded on changes"
+    )]
+    pub config: Option<std::path::PathBuf>,
+    #[cfg(feature = "parquet")]
+    #[clap(
+        long,
+        value_name = "FILE",
+        help = "Capture the received data to a Parquet file (written periodically and on exit)"
+    )]
+    pub parquet: Option<std::path::PathBuf>,
 }
 
 pub trait ToPlotConfigMap {
@@ -65,12 +306,13 @@ pub trait ToPlotConfigMap {
 impl ToPlotConfigMap for Vec<PredefinedYRange> {
     fn to_plot_config_map(&self) -> BTreeMap<String, PlotConfig> {
         let mut map = BTreeMap::new();
-        for PredefinedYRange { key, min, max } in self {
+        for PredefinedYRange { key, min, max, log } in self {
             map.insert(
                 key.to_owned(),
                 PlotConfig {
                     min: *min,
                     max: *max,
+                    log: *log,
                 },
             );
         }
@@ -92,6 +334,20 @@ impl ToSmaMap for Vec<PredefinedSma> {
     }
 }
 
+pub trait ToRateMap {
+    fn to_rate_map(&self) -> BTreeMap<String, Rate>;
+}
+
+impl ToRateMap for Vec<PredefinedRate> {
+    fn to_rate_map(&self) -> BTreeMap<String, Rate> {
+        let mut map = BTreeMap::new();
+        for PredefinedRate { key, rate } in self {
+            map.insert(key.to_owned(), *rate);
+        }
+        map
+    }
+}
+
 pub trait ToTriggerMap {
     fn to_trigger_map(&self) -> BTreeMap<String, TriggerConfig>;
 }
@@ -112,11 +368,32 @@ impl ToTriggerMap for Vec<PredefinedTrigger> {
     }
 }
 
+pub trait ToSaneRangeMap {
+    fn to_sane_range_map(&self) -> BTreeMap<String, SaneRange>;
+}
+
+impl ToSaneRangeMap for Vec<PredefinedSaneRange> {
+    fn to_sane_range_map(&self) -> BTreeMap<String, SaneRange> {
+        let mut map = BTreeMap::new();
+        for PredefinedSaneRange { key, min, max } in self {
+            map.insert(
+                key.to_owned(),
+                SaneRange {
+                    min: *min,
+                    max: *max,
+                },
+            );
+        }
+        map
+    }
+}
+
 #[derive(Clone)]
 pub struct PredefinedYRange {
     key: String,
     min: Option<f64>,
     max: Option<f64>,
+    log: bool,
 }
 
 impl ValueParserFactory for PredefinedYRange {
@@ -152,7 +429,7 @@ impl TypedValueParser for PredefinedYRangeParser {
                 "Invalid Y-range - no value",
             )
         })?;
-        let mut value_sp = value_str.splitn(2, ',');
+        let mut value_sp = value_str.splitn(3, ',');
         let min_str = value_sp.next().unwrap();
         let max_str = value_sp.next().ok_or_else(|| {
             clap::error::Error::raw(
@@ -160,6 +437,16 @@ impl TypedValueParser for PredefinedYRangeParser {
                 "Invalid Y-range - no max value",
             )
         })?;
+        let log = match value_sp.next() {
+            None => false,
+            Some("log") => true,
+            Some(_) => {
+                return Err(clap::error::Error::raw(
+                    clap::error::ErrorKind::ValueValidation,
+                    "Invalid Y-range - the axis type must be log",
+                ));
+            }
+        };
         let min = if min_str.is_empty() {
             None
         } else {
@@ -184,6 +471,7 @@ impl TypedValueParser for PredefinedYRangeParser {
             key: key.to_owned(),
             min,
             max,
+            log,
         })
     }
 }
@@ -240,6 +528,56 @@ impl TypedValueParser for PredefinedSmaParser {
     }
 }
 
+#[derive(Clone)]
+pub struct PredefinedRate {
+    key: String,
+    rate: Rate,
+}
+
+impl ValueParserFactory for PredefinedRate {
+    type Parser = PredefinedRateParser;
+    fn value_parser() -> Self::Parser {
+        PredefinedRateParser
+    }
+}
+
+#[derive(Clone)]
+pub struct PredefinedRateParser;
+
+impl TypedValueParser for PredefinedRateParser {
+    type Value = PredefinedRate;
+
+    fn parse_ref(
+        &self,
+        _cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let v = value.to_str().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid rate string",
+            )
+        })?;
+        let mut sp = v.splitn(2, '=');
+        let key = sp.next().unwrap();
+        let rate = if let Some(rate_str) = sp.next() {
+            Rate::from_str(rate_str, true).map_err(|_| {
+                clap::error::Error::raw(
+                    clap::error::ErrorKind::ValueValidation,
+                    "Invalid rate - must be off, replace or overlay",
+                )
+            })?
+        } else {
+            Rate::Replace
+        };
+        Ok(PredefinedRate {
+            key: key.to_owned(),
+            rate,
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct PredefinedTrigger {
     key: String,
@@ -316,6 +654,238 @@ impl TypedValueParser for PredefinedTriggerParser {
     }
 }
 
+#[derive(Clone)]
+pub struct PredefinedSaneRange {
+    key: String,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl ValueParserFactory for PredefinedSaneRange {
+    type Parser = PredefinedSaneRangeParser;
+    fn value_parser() -> Self::Parser {
+        PredefinedSaneRangeParser
+    }
+}
+
+#[derive(Clone)]
+pub struct PredefinedSaneRangeParser;
+
+impl TypedValueParser for PredefinedSaneRangeParser {
+    type Value = PredefinedSaneRange;
+
+    fn parse_ref(
+        &self,
+        _cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let v = value.to_str().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid sane range string",
+            )
+        })?;
+        let mut sp = v.splitn(2, '=');
+        let key = sp.next().unwrap();
+        let value_str = sp.next().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid sane range - no value",
+            )
+        })?;
+        let mut value_sp = value_str.splitn(2, ',');
+        let min_str = value_sp.next().unwrap();
+        let max_str = value_sp.next().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid sane range - no max value",
+            )
+        })?;
+        let min = if min_str.is_empty() {
+            None
+        } else {
+            Some(min_str.parse().map_err(|_| {
+                clap::error::Error::raw(
+                    clap::error::ErrorKind::ValueValidation,
+                    "Invalid sane range - min must be a float",
+                )
+            })?)
+        };
+        let max = if max_str.is_empty() {
+            None
+        } else {
+            Some(max_str.parse().map_err(|_| {
+                clap::error::Error::raw(
+                    clap::error::ErrorKind::ValueValidation,
+                    "Invalid sane range - max must be a float",
+                )
+            })?)
+        };
+        Ok(PredefinedSaneRange {
+            key: key.to_owned(),
+            min,
+            max,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricInterval {
+    pub name: String,
+    pub interval: Duration,
+    pub phase: Duration,
+}
+
+impl ValueParserFactory for MetricInterval {
+    type Parser = MetricIntervalParser;
+    fn value_parser() -> Self::Parser {
+        MetricIntervalParser
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricIntervalParser;
+
+impl TypedValueParser for MetricIntervalParser {
+    type Value = MetricInterval;
+
+    fn parse_ref(
+        &self,
+        _cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let v = value.to_str().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid metric interval string",
+            )
+        })?;
+        let mut sp = v.splitn(2, '=');
+        let name = sp.next().unwrap();
+        let value_str = sp.next().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid metric interval - no value",
+            )
+        })?;
+        let mut value_sp = value_str.splitn(2, '@');
+        let parse_secs = |s: &str, what: &str| {
+            s.parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| {
+                    clap::error::Error::raw(
+                        clap::error::ErrorKind::ValueValidation,
+                        format!(
+                            "Invalid metric interval - {} must be a non-negative float",
+                            what
+                        ),
+                    )
+                })
+        };
+        let interval = parse_secs(value_sp.next().unwrap(), "interval")?;
+        if interval.is_zero() {
+            return Err(clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid metric interval - interval must be positive",
+            ));
+        }
+        let phase = value_sp
+            .next()
+            .map(|phase| parse_secs(phase, "phase"))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(MetricInterval {
+            name: name.to_owned(),
+            interval,
+            phase,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct NameTransform {
+    regex: regex::Regex,
+    replacement: String,
+    global: bool,
+}
+
+impl NameTransform {
+    pub fn apply(&self, name: &str) -> String {
+        if self.global {
+            self.regex.replace_all(name, self.replacement.as_str())
+        } else {
+            self.regex.replace(name, self.replacement.as_str())
+        }
+        .into_owned()
+    }
+}
+
+impl ValueParserFactory for NameTransform {
+    type Parser = NameTransformParser;
+    fn value_parser() -> Self::Parser {
+        NameTransformParser
+    }
+}
+
+#[derive(Clone)]
+pub struct NameTransformParser;
+
+impl TypedValueParser for NameTransformParser {
+    type Value = NameTransform;
+
+    fn parse_ref(
+        &self,
+        _cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let v = value.to_str().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid name transform string",
+            )
+        })?;
+        let mut chars = v.chars();
+        let (Some('s'), Some(delimiter)) = (chars.next(), chars.next()) else {
+            return Err(clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid name transform - must be in s/REGEX/REPLACEMENT/ format",
+            ));
+        };
+        let sp: Vec<&str> = chars.as_str().split(delimiter).collect();
+        let [regex_str, replacement, flags] = sp[..] else {
+            return Err(clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid name transform - must be in s/REGEX/REPLACEMENT/ format",
+            ));
+        };
+        let global = match flags {
+            "" => false,
+            "g" => true,
+            _ => {
+                return Err(clap::error::Error::raw(
+                    clap::error::ErrorKind::ValueValidation,
+                    "Invalid name transform - the only supported flag is g",
+                ));
+            }
+        };
+        let regex = regex::Regex::new(regex_str).map_err(|e| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                format!("Invalid name transform regex: {}", e),
+            )
+        })?;
+        Ok(NameTransform {
+            regex,
+            replacement: replacement.to_owned(),
+            global,
+        })
+    }
+}
+
 #[derive(ValueEnum, Clone)]
 pub enum Theme {
     #[clap(name = "dark")]
@@ -324,12 +894,155 @@ pub enum Theme {
     Light,
 }
 
+#[derive(Clone)]
+pub struct TriggerCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl TriggerCommand {
+    /// Substitutes `{name}` placeholders in the command arguments. The command is not run by a
+    /// shell, so the substituted values can not inject extra commands
+    pub fn command(&self, vars: &[(&str, String)]) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.program);
+        for arg in &self.args {
+            let mut arg = arg.clone();
+            for (name, value) in vars {
+                arg = arg.replace(&format!("{{{}}}", name), value);
+            }
+            command.arg(arg);
+        }
+        command
+    }
+}
+
+impl ValueParserFactory for TriggerCommand {
+    type Parser = TriggerCommandParser;
+    fn value_parser() -> Self::Parser {
+        TriggerCommandParser
+    }
+}
+
+#[derive(Clone)]
+pub struct TriggerCommandParser;
+
+impl TypedValueParser for TriggerCommandParser {
+    type Value = TriggerCommand;
+
+    fn parse_ref(
+        &self,
+        _cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let v = value.to_str().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid trigger command string",
+            )
+        })?;
+        let mut sp = shlex::split(v)
+            .ok_or_else(|| {
+                clap::error::Error::raw(
+                    clap::error::ErrorKind::ValueValidation,
+                    "Invalid trigger command - unbalanced quotes",
+                )
+            })?
+            .into_iter();
+        let program = sp.next().ok_or_else(|| {
+            clap::error::Error::raw(
+                clap::error::ErrorKind::ValueValidation,
+                "Invalid trigger command - empty command",
+            )
+        })?;
+        Ok(TriggerCommand {
+            program,
+            args: sp.collect(),
+        })
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq)]
+pub enum Interpolation {
+    #[clap(name = "linear")]
+    Linear,
+    #[clap(name = "step")]
+    Step,
+    #[clap(name = "spline")]
+    Spline,
+}
+
+/// The rate of change (per second) transform of a metric, display only
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq)]
+pub enum Rate {
+    #[clap(name = "off")]
+    Off,
+    #[clap(name = "replace")]
+    Replace,
+    #[clap(name = "overlay")]
+    Overlay,
+}
+
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq)]
+pub enum PlotOrder {
+    #[clap(name = "name")]
+    Name,
+    #[clap(name = "first-seen")]
+    FirstSeen,
+}
+
+#[derive(ValueEnum, Clone, Copy, Eq, PartialEq)]
+pub enum Retain {
+    #[clap(name = "rolling")]
+    Rolling,
+    #[clap(name = "all")]
+    All,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum TimestampUnit {
+    #[clap(name = "ns")]
+    Nanos,
+    #[clap(name = "us")]
+    Micros,
+    #[clap(name = "ms")]
+    Millis,
+}
+
+impl From<TimestampUnit> for metrics_exporter_scope::TimestampUnit {
+    fn from(unit: TimestampUnit) -> Self {
+        match unit {
+            TimestampUnit::Nanos => metrics_exporter_scope::TimestampUnit::Nanos,
+            TimestampUnit::Micros => metrics_exporter_scope::TimestampUnit::Micros,
+            TimestampUnit::Millis => metrics_exporter_scope::TimestampUnit::Millis,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct TriggerConfig {
     pub below: Option<f64>,
     pub above: Option<f64>,
 }
 
+#[derive(Clone, Copy)]
+pub struct SaneRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl SaneRange {
+    /// NaN values are gaps already and are always accepted
+    pub fn contains(&self, value: f64) -> bool {
+        value.is_nan()
+            || (self.min.map_or(true, |min| value >= min)
+                && self.max.map_or(true, |max| value <= max))
+    }
+}
+
+#[derive(Clone, Copy)]
 pub struct PlotConfig {
     pub min: Option<f64>,
     pub max: Option<f64>,
+    pub log: bool,
 }
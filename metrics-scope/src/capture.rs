@@ -0,0 +1,116 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Float64Array, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use metrics_exporter_scope::Snapshot;
+use parking_lot::Mutex;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+const CHUNK_ROWS: usize = 65_536;
+
+/// Columnar buffer of the received stream: a timestamp column and one column per metric. Metrics
+/// which appear later are backfilled with nulls
+#[derive(Default, Clone)]
+struct Columns {
+    t: Vec<f64>,
+    metrics: BTreeMap<String, Vec<Option<f64>>>,
+}
+
+impl Columns {
+    fn push(&mut self, snapshot: &Snapshot) {
+        let rows = self.t.len();
+        for (name, value) in snapshot.data() {
+            if let Some(column) = self.metrics.get_mut(name) {
+                column.push(Some(*value));
+            } else {
+                let mut column = vec![None; rows];
+                column.push(Some(*value));
+                self.metrics.insert(name.clone(), column);
+            }
+        }
+        self.t.push(snapshot.ts().as_secs_f64());
+        for column in self.metrics.values_mut() {
+            column.resize(self.t.len(), None);
+        }
+    }
+    fn write_parquet(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let mut fields = vec![Field::new("t", DataType::Float64, false)];
+        for name in self.metrics.keys() {
+            fields.push(Field::new(name, DataType::Float64, true));
+        }
+        let schema = Arc::new(Schema::new(fields));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        // write to a temporary file first, so a periodic flush never leaves a truncated capture
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let mut writer =
+            ArrowWriter::try_new(File::create(&tmp_path)?, schema.clone(), Some(props))?;
+        for start in (0..self.t.len()).step_by(CHUNK_ROWS) {
+            let end = cmp::min(start + CHUNK_ROWS, self.t.len());
+            let mut columns: Vec<ArrayRef> =
+                vec![Arc::new(Float64Array::from(self.t[start..end].to_vec()))];
+            for column in self.metrics.values() {
+                columns.push(Arc::new(Float64Array::from(column[start..end].to_vec())));
+            }
+            writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+        writer.close()?;
+        // the data must be on the disk before the previous capture is replaced
+        File::open(&tmp_path)?.sync_all()?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Captures snapshots received by the client to a Parquet file
+#[derive(Clone)]
+pub struct Capture {
+    columns: Arc<Mutex<Columns>>,
+    path: Arc<PathBuf>,
+    // the file is written from the periodic, the reader and the signal handler threads
+    write_lock: Arc<Mutex<()>>,
+    // set if there is data, not written to the file yet
+    pending: Arc<AtomicBool>,
+}
+
+impl Capture {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            columns: <_>::default(),
+            path: Arc::new(path),
+            write_lock: <_>::default(),
+            // an empty file is written even if nothing is received
+            pending: Arc::new(AtomicBool::new(true)),
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn push(&self, snapshot: &Snapshot) {
+        self.columns.lock().push(snapshot);
+        self.pending.store(true, Ordering::Release);
+    }
+    /// Writes all the data captured so far and syncs it to the disk, replacing the file
+    pub fn flush(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let _write_lock = self.write_lock.lock();
+        if !self.pending.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // do not block the reader while encoding
+        let columns = self.columns.lock().clone();
+        let result = columns.write_parquet(&self.path);
+        if result.is_err() {
+            self.pending.store(true, Ordering::Release);
+        }
+        result
+    }
+}
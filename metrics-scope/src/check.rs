@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use metrics_exporter_scope::Info;
+
+use crate::args::{
+    Args, ToPlotConfigMap as _, ToRateMap as _, ToSaneRangeMap as _, ToSmaMap as _,
+    ToTriggerMap as _,
+};
+
+/// Plot names and metric tags (`plot/metric` or `metric`) predefined settings can refer to
+#[derive(Default)]
+struct Known {
+    plots: BTreeSet<String>,
+    tags: BTreeSet<String>,
+}
+
+impl Known {
+    fn new(args: &Args, info: &Info) -> Self {
+        let mut known = Self::default();
+        for (raw_name, m) in info.metrics() {
+            let name = args
+                .name_transform
+                .as_ref()
+                .map_or_else(|| raw_name.clone(), |t| t.apply(raw_name));
+            if args
+                .filter
+                .as_ref()
+                .map_or(false, |f| !f.is_match(raw_name) && !f.is_match(&name))
+            {
+                continue;
+            }
+            // predefined settings may refer either to the raw or to the transformed name
+            for n in [raw_name, &name] {
+                if let Some(plot) = m.labels().get("plot") {
+                    known.plots.insert(plot.clone());
+                    known.tags.insert(format!("{}/{}", plot, n));
+                } else {
+                    known.plots.insert(n.clone());
+                    known.tags.insert(n.clone());
+                }
+            }
+        }
+        known
+    }
+}
+
+/// Reports predefined settings which refer to plots and metrics, not provided by the source.
+/// Returns `false` if there are any
+pub fn check(args: &Args, info: &Info) -> bool {
+    let known = Known::new(args, info);
+    let mut unresolved = Vec::new();
+    for plot in args.predefined_y_range.to_plot_config_map().keys() {
+        if !known.plots.contains(plot) {
+            unresolved.push(format!("--y-range: no such plot: {}", plot));
+        }
+    }
+    for tag in args.predefined_sma.to_sma_map().keys() {
+        if !known.tags.contains(tag) {
+            unresolved.push(format!("--sma: no such metric: {}", tag));
+        }
+    }
+    for tag in args.predefined_rate.to_rate_map().keys() {
+        if !known.tags.contains(tag) {
+            unresolved.push(format!("--rate: no such metric: {}", tag));
+        }
+    }
+    for tag in args.predefined_trigger.to_trigger_map().keys() {
+        if !known.tags.contains(tag) {
+            unresolved.push(format!("--trigger: no such metric: {}", tag));
+        }
+    }
+    for key in args.sane_range.to_sane_range_map().keys() {
+        if !known.plots.contains(key) && !known.tags.contains(key) {
+            unresolved.push(format!("--sane-range: no such plot or metric: {}", key));
+        }
+    }
+    println!(
+        "{} metrics, {} plots",
+        info.metrics().len(),
+        known.plots.len()
+    );
+    for u in &unresolved {
+        println!("{}", u);
+    }
+    if unresolved.is_empty() {
+        println!("OK");
+    }
+    unresolved.is_empty()
+}
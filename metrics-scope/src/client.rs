@@ -1,41 +1,896 @@
-use std::net::ToSocketAddrs;
-use std::time::Duration;
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{net::TcpStream, thread};
 
-use metrics_exporter_scope::{protocol, ClientSettings};
+use atomic_float::AtomicF64;
+use bma_ts::Monotonic;
+use metrics_exporter_scope::{protocol, ClientSettings, Info, Packet, TimestampUnit};
+use parking_lot::Mutex;
+use tracing::{error, info, warn};
 
+#[cfg(feature = "parquet")]
+use crate::capture::Capture;
 use crate::{Event, EventSender};
 
+const ERROR_LOG_INTERVAL: Duration = Duration::from_secs(30);
+const READER_RESTART_DELAY: Duration = Duration::from_secs(5);
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Max random addition to reconnect delays (a fraction of the delay), so clients, which have lost
+/// the same server, do not reconnect in lockstep
+const RECONNECT_JITTER: f64 = 0.2;
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Shorter replay delays are accumulated, so at high speeds packets are sent in batches
+const REPLAY_MIN_SLEEP: Duration = Duration::from_millis(20);
+const REPLAY_MIN_SPEED: f64 = 0.01;
+
+/// Logs repeated errors once per [`ERROR_LOG_INTERVAL`], with a count of suppressed ones
+#[derive(Default)]
+struct ErrorLog {
+    last: Option<(String, Instant)>,
+    suppressed: usize,
+}
+
+impl ErrorLog {
+    fn report(&mut self, error: &str) {
+        if let Some((ref last_error, logged_at)) = self.last {
+            if last_error == error && logged_at.elapsed() < ERROR_LOG_INTERVAL {
+                self.suppressed += 1;
+                return;
+            }
+        }
+        if self.suppressed > 0 {
+            warn!(error, suppressed = self.suppressed, "connection error");
+        } else {
+            warn!(error, "connection error");
+        }
+        self.last = Some((error.to_owned(), Instant::now()));
+        self.suppressed = 0;
+    }
+    fn reset(&mut self) {
+        self.last = None;
+        self.suppressed = 0;
+    }
+}
+
+/// Reconnect delays, doubled after every failed attempt up to the max one
+struct Backoff {
+    delay: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new(max: Duration) -> Self {
+        Self {
+            delay: RECONNECT_BASE_DELAY.min(max),
+            max,
+        }
+    }
+    /// Returns the delay before the next attempt, with jitter
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        delay.mul_f64(1.0 + RECONNECT_JITTER * random_fraction())
+    }
+    fn reset(&mut self) {
+        self.delay = RECONNECT_BASE_DELAY.min(self.max);
+    }
+}
+
+/// A random number in 0..=1, good enough for jitter (the hasher keys are random per instance)
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 32;
+    f64::from(u32::try_from(bits).unwrap_or_default()) / f64::from(u32::MAX)
+}
+
+/// The last reader error with the connection details, displayed while there is no data
+#[derive(Default, Clone)]
+pub struct Diagnostics {
+    pub error: String,
+    pub resolved_addr: Option<SocketAddr>,
+    pub server_version: Option<u16>,
+    /// The handshake has been completed before the error
+    pub connected: bool,
+    pub fatal: bool,
+}
+
+/// Data source: a remote server (TCP or a Unix socket), a growing recording file, a named pipe, a recording file,
+/// replayed at the given speed (can be changed at runtime) or a recording file, loaded at once
+/// for the offline viewer
+pub enum Source {
+    Remote(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+    File(PathBuf),
+    Fifo(PathBuf),
+    Replay(PathBuf, Arc<AtomicF64>),
+    Offline(PathBuf),
+}
+
+impl Source {
+    /// A server address (`HOST:PORT`) or a Unix socket path (`unix:PATH`, Unix only)
+    pub fn server(source: &str) -> Self {
+        #[cfg(unix)]
+        if let Some(path) = source.strip_prefix("unix:") {
+            return Source::Unix(path.into());
+        }
+        Source::Remote(source.to_owned())
+    }
+    /// Server connections (TCP or Unix socket), which are reconnected and can be recorded
+    pub fn is_remote(&self) -> bool {
+        match self {
+            Source::Remote(_) => true,
+            #[cfg(unix)]
+            Source::Unix(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// How the read packets are delivered to the UI
+#[derive(Clone, Copy)]
+enum Pace<'a> {
+    /// As soon as read, the UI may drop outdated snapshots
+    Live,
+    /// Paced by the snapshot timestamps at the given speed
+    Replay(&'a AtomicF64),
+    /// As fast as the UI takes them, none is dropped
+    Load,
+    /// Loaded until the flag is set (the followed file has been read up to its initial size), live
+    /// after
+    Follow(&'a AtomicBool),
+}
+
+/// Records the received stream as-is (the signature, the version and the packets), so the file
+/// can be replayed later. Sessions after reconnects are appended to the same file, the version
+/// header is written once
+#[derive(Clone)]
+pub struct Recording {
+    // the file is written by the reader and flushed from the main and the signal handler threads
+    file: Arc<Mutex<RecordingFile>>,
+}
+
+impl Recording {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            file: Arc::new(Mutex::new(RecordingFile {
+                path,
+                file: None,
+                version: None,
+                failed: false,
+            })),
+        }
+    }
+    fn start(&self, version: u16) {
+        self.file.lock().start(version);
+    }
+    fn write(&self, data: &[u8]) {
+        self.file.lock().write(data);
+    }
+    /// Writes the buffered data and syncs the file to the disk, so the recording is complete up
+    /// to the last received packet
+    pub fn flush(&self) {
+        self.file.lock().flush();
+    }
+}
+
+struct RecordingFile {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    version: Option<u16>,
+    failed: bool,
+}
+
+impl RecordingFile {
+    /// Starts recording a new session, the file is created on the first one
+    fn start(&mut self, version: u16) {
+        if self.failed {
+            return;
+        }
+        if let Some(recorded_version) = self.version {
+            if recorded_version != version {
+                warn!(
+                    path = %self.path.display(),
+                    recorded_version,
+                    version,
+                    "the server version has been changed, recording stopped"
+                );
+                self.stop();
+            }
+            return;
+        }
+        let result = File::create(&self.path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            // version 1 servers do not send the signature
+            if version > 1 {
+                file.write_all(&protocol::MAGIC)?;
+            }
+            file.write_all(&version.to_le_bytes())?;
+            Ok(file)
+        });
+        match result {
+            Ok(file) => {
+                info!(path = %self.path.display(), "recording the stream");
+                self.file = Some(file);
+                self.version = Some(version);
+            }
+            Err(e) => self.fail(&e),
+        }
+    }
+    fn write(&mut self, data: &[u8]) {
+        if let Some(ref mut file) = self.file {
+            if let Err(e) = file.write_all(data) {
+                self.fail(&e);
+            }
+        }
+    }
+    fn flush(&mut self) {
+        if let Some(ref mut file) = self.file {
+            if let Err(e) = file.flush().and_then(|()| file.get_ref().sync_all()) {
+                self.fail(&e);
+            }
+        }
+    }
+    fn fail(&mut self, error: &io::Error) {
+        error!(path = %self.path.display(), error = %error, "recording failed");
+        self.stop();
+    }
+    fn stop(&mut self) {
+        if let Some(mut file) = self.file.take() {
+            file.flush().and_then(|()| file.get_ref().sync_all()).ok();
+        }
+        self.failed = true;
+    }
+}
+
+/// Writes everything read from the stream to the recording
+struct RecordingReader<'a, R> {
+    stream: R,
+    recording: &'a Recording,
+}
+
+impl<R: Read> Read for RecordingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        self.recording.write(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// The effective client settings, which are replayed on every (re)connect. The settings can be
+/// sent once per connection only, so changing them makes the reader reconnect
+pub struct ReaderSettings {
+    settings: Mutex<ClientSettings>,
+    changed: AtomicBool,
+    token: Option<String>,
+    reconnect_max_delay: Duration,
+}
+
+impl ReaderSettings {
+    pub fn new(settings: ClientSettings) -> Self {
+        Self {
+            settings: Mutex::new(settings),
+            changed: AtomicBool::new(false),
+            token: None,
+            reconnect_max_delay: RECONNECT_MAX_DELAY,
+        }
+    }
+    /// The authentication token, sent before the settings on every connection
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+    /// The max delay between reconnect attempts, the delay starts from 1 second and doubles
+    /// after every failed attempt
+    pub fn with_reconnect_max_delay(mut self, reconnect_max_delay: Duration) -> Self {
+        self.reconnect_max_delay = reconnect_max_delay;
+        self
+    }
+    pub fn set_sampling_interval(&self, sampling_interval: Duration) {
+        let mut settings = self.settings.lock();
+        *settings = settings.clone().with_sampling_interval(sampling_interval);
+        self.changed.store(true, Ordering::Release);
+    }
+    /// Takes the current settings for a new connection
+    fn current(&self) -> ClientSettings {
+        self.changed.store(false, Ordering::Release);
+        self.settings.lock().clone()
+    }
+    fn is_changed(&self) -> bool {
+        self.changed.load(Ordering::Acquire)
+    }
+}
+
+fn check_version(version: u16) -> Result<(), metrics_exporter_scope::Error> {
+    if protocol::is_version_supported(version) {
+        Ok(())
+    } else {
+        Err(metrics_exporter_scope::Error::Protocol(format!(
+            "unsupported version: {}",
+            version
+        )))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn read_remote(
     addr: &str,
     tx: &EventSender,
-    sampling_interval: Duration,
+    settings: &ReaderSettings,
     timeout: Duration,
+    error_log: &mut ErrorLog,
+    diagnostics: &mut Diagnostics,
+    recording: Option<&Recording>,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let addr = addr.to_socket_addrs()?.next().ok_or("Invalid address")?;
-    let mut client = TcpStream::connect_timeout(&addr, timeout)?;
+    diagnostics.resolved_addr = Some(addr);
+    let client = TcpStream::connect_timeout(&addr, timeout)?;
     client.set_nodelay(true)?;
     client.set_read_timeout(Some(timeout))?;
-    let version = protocol::read_version(&client)?;
-    if version != protocol::VERSION {
-        return Err(format!("Unsupported version: {}", version).into());
-    }
-    let settings = ClientSettings::new(sampling_interval);
-    protocol::write_client_settings(&mut client, &settings)?;
-    println!("Client connected: {}", addr);
-    tx.send(Event::Connect).unwrap();
+    read_connection(
+        client,
+        &addr,
+        tx,
+        settings,
+        error_log,
+        diagnostics,
+        recording,
+        #[cfg(feature = "parquet")]
+        capture,
+    )
+}
+
+#[cfg(unix)]
+#[allow(clippy::too_many_arguments)]
+fn read_unix(
+    path: &Path,
+    tx: &EventSender,
+    settings: &ReaderSettings,
+    timeout: Duration,
+    error_log: &mut ErrorLog,
+    diagnostics: &mut Diagnostics,
+    recording: Option<&Recording>,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = UnixStream::connect(path)?;
+    client.set_read_timeout(Some(timeout))?;
+    read_connection(
+        client,
+        &path.display(),
+        tx,
+        settings,
+        error_log,
+        diagnostics,
+        recording,
+        #[cfg(feature = "parquet")]
+        capture,
+    )
+}
+
+/// Handshakes with the server and reads the packets until the connection is closed or the
+/// settings are changed
+#[allow(clippy::too_many_arguments)]
+fn read_connection<C: Read + Write>(
+    mut client: C,
+    addr: &dyn fmt::Display,
+    tx: &EventSender,
+    settings: &ReaderSettings,
+    error_log: &mut ErrorLog,
+    diagnostics: &mut Diagnostics,
+    recording: Option<&Recording>,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let version = protocol::read_version(&mut client)?;
+    diagnostics.server_version = Some(version);
+    check_version(version)?;
+    if let Some(ref token) = settings.token {
+        protocol::write_token(&mut client, token)?;
+    }
+    protocol::write_client_settings(&mut client, &settings.current())?;
+    info!(%addr, "client connected");
+    diagnostics.connected = true;
+    error_log.reset();
+    tx.send(Event::Connect)?;
+    let Some(recording) = recording else {
+        return read_packets(
+            client,
+            version,
+            tx,
+            Some(settings),
+            Pace::Live,
+            #[cfg(feature = "parquet")]
+            capture,
+        );
+    };
+    recording.start(version);
+    let result = read_packets(
+        RecordingReader {
+            stream: client,
+            recording,
+        },
+        version,
+        tx,
+        Some(settings),
+        Pace::Live,
+        #[cfg(feature = "parquet")]
+        capture,
+    );
+    recording.flush();
+    result
+}
+
+/// Reads a growing file like `tail -f`: blocks on EOF until more data is appended
+struct FollowFile<'a> {
+    path: &'a Path,
+    file: File,
+    pos: u64,
+    // the file size at open, the content before is history
+    initial_len: u64,
+    tailing: &'a AtomicBool,
+}
+
+impl FollowFile<'_> {
+    /// Checks if the file has been truncated or replaced with a new one
+    fn rotated(&self) -> io::Result<bool> {
+        let meta = match fs::metadata(self.path) {
+            Ok(meta) => meta,
+            // the new file is not created yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if meta.len() < self.pos {
+            return Ok(true);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt as _;
+            let current = self.file.metadata()?;
+            if meta.dev() != current.dev() || meta.ino() != current.ino() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Read for FollowFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.initial_len {
+            self.tailing.store(true, Ordering::Relaxed);
+        }
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            if self.rotated()? {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "the file has been rotated",
+                ));
+            }
+            thread::sleep(FOLLOW_POLL_INTERVAL);
+        }
+    }
+}
+
+fn read_file(
+    path: &Path,
+    tx: &EventSender,
+    error_log: &mut ErrorLog,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tailing = AtomicBool::new(false);
+    let f = File::open(path)?;
+    let mut file = FollowFile {
+        path,
+        initial_len: f.metadata()?.len(),
+        file: f,
+        pos: 0,
+        tailing: &tailing,
+    };
+    let version = protocol::read_version(&mut file)?;
+    check_version(version)?;
+    info!(path = %path.display(), "following the file");
+    error_log.reset();
+    tx.send(Event::Connect)?;
+    read_packets(
+        file,
+        version,
+        tx,
+        None,
+        Pace::Follow(&tailing),
+        #[cfg(feature = "parquet")]
+        capture,
+    )
+}
+
+/// Reads a named pipe, the stream is expected to be written in the wire format (the signature and
+/// the version, followed by packets). When the producer closes the pipe, it is reopened for the
+/// next one
+fn read_fifo(
+    path: &Path,
+    tx: &EventSender,
+    error_log: &mut ErrorLog,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // blocks until a producer opens the pipe for writing
+    let mut fifo = File::open(path)?;
+    let version = protocol::read_version(&mut fifo)?;
+    check_version(version)?;
+    info!(path = %path.display(), "reading the named pipe");
+    error_log.reset();
+    tx.send(Event::Connect)?;
+    match read_packets(
+        fifo,
+        version,
+        tx,
+        None,
+        Pace::Live,
+        #[cfg(feature = "parquet")]
+        capture,
+    ) {
+        Err(e)
+            if matches!(
+                e.downcast_ref::<metrics_exporter_scope::Error>(),
+                Some(metrics_exporter_scope::Error::Io(e))
+                    if e.kind() == io::ErrorKind::UnexpectedEof
+            ) =>
+        {
+            info!(path = %path.display(), "the producer has closed the pipe");
+            tx.send(Event::Disconnect).ok();
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Reads a recording file from the beginning, pacing snapshots by their timestamp deltas (if the
+/// speed is set) or loading the whole file at once
+fn read_replay(
+    path: &Path,
+    speed: Option<&AtomicF64>,
+    tx: &EventSender,
+    error_log: &mut ErrorLog,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = io::BufReader::new(File::open(path)?);
+    let version = protocol::read_version(&mut file)?;
+    check_version(version)?;
+    if speed.is_some() {
+        info!(path = %path.display(), "replaying the file");
+    } else {
+        info!(path = %path.display(), "loading the file");
+    }
+    error_log.reset();
+    tx.send(Event::Connect)?;
+    match read_packets(
+        file,
+        version,
+        tx,
+        None,
+        speed.map_or(Pace::Load, Pace::Replay),
+        #[cfg(feature = "parquet")]
+        capture,
+    ) {
+        Err(e)
+            if matches!(
+                e.downcast_ref::<metrics_exporter_scope::Error>(),
+                Some(metrics_exporter_scope::Error::Io(e))
+                    if e.kind() == io::ErrorKind::UnexpectedEof
+            ) =>
+        {
+            info!(path = %path.display(), "end of the file");
+            tx.send(Event::Disconnect).ok();
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Sleeps between replayed snapshots for their timestamp delta, divided by the replay speed
+#[derive(Default)]
+struct ReplayPacer {
+    last_ts: Option<Monotonic>,
+    pending: Duration,
+}
+
+impl ReplayPacer {
+    fn pace(&mut self, ts: Monotonic, speed: f64) {
+        if let Some(last_ts) = self.last_ts.replace(ts) {
+            let delta =
+                u64::try_from(ts.as_nanos().saturating_sub(last_ts.as_nanos())).unwrap_or(u64::MAX);
+            self.pending += Duration::from_nanos(delta).div_f64(speed.max(REPLAY_MIN_SPEED));
+        }
+        if self.pending >= REPLAY_MIN_SLEEP {
+            thread::sleep(self.pending);
+            self.pending = Duration::ZERO;
+        }
+    }
+}
+
+fn read_packets<R: Read>(
+    mut stream: R,
+    version: u16,
+    tx: &EventSender,
+    settings: Option<&ReaderSettings>,
+    pace: Pace,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // updated from info packets, older servers always send nanoseconds
+    let mut timestamp_unit = TimestampUnit::Nanos;
+    let mut pacer = ReplayPacer::default();
     loop {
-        let packet = protocol::read_packet(&mut client)?;
-        tx.send(Event::Packet(packet)).ok();
+        if settings.map_or(false, ReaderSettings::is_changed) {
+            info!("client settings changed, reconnecting");
+            return Ok(());
+        }
+        let mut packet = protocol::read_packet_versioned(&mut stream, version)?;
+        match packet {
+            Packet::Info(ref info) => timestamp_unit = info.timestamp_unit(),
+            Packet::Snapshot(ref mut snapshot) => {
+                snapshot.set_ts(timestamp_unit.decode_ts(snapshot.ts()));
+                if let Pace::Replay(speed) = pace {
+                    pacer.pace(snapshot.ts(), speed.load(Ordering::Relaxed));
+                }
+            }
+            Packet::SnapshotBatch(ref mut batch) => {
+                for snapshot in batch.snapshots_mut() {
+                    snapshot.set_ts(timestamp_unit.decode_ts(snapshot.ts()));
+                    if let Pace::Replay(speed) = pace {
+                        pacer.pace(snapshot.ts(), speed.load(Ordering::Relaxed));
+                    }
+                }
+            }
+            Packet::Event(ref mut event) => {
+                event.set_ts(timestamp_unit.decode_ts(event.ts()));
+            }
+            Packet::Ping(ref mut ping) => {
+                ping.set_ts(timestamp_unit.decode_ts(ping.ts()));
+            }
+            Packet::InfoDelta(_) => {}
+        }
+        #[cfg(feature = "parquet")]
+        if let Some(capture) = capture {
+            match packet {
+                Packet::Snapshot(ref snapshot) => capture.push(snapshot),
+                Packet::SnapshotBatch(ref batch) => {
+                    for snapshot in batch.snapshots() {
+                        capture.push(snapshot);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let loading = match pace {
+            Pace::Load => true,
+            Pace::Follow(tailing) => !tailing.load(Ordering::Relaxed),
+            Pace::Live | Pace::Replay(_) => false,
+        };
+        if loading {
+            tx.send(Event::Loaded(packet)).ok();
+        } else {
+            tx.send(Event::Packet(packet)).ok();
+        }
     }
 }
 
-pub fn reader(addr: &str, tx: EventSender, sampling_interval: Duration, timeout: Duration) {
+/// Reads the first information packet from the source
+pub fn fetch_info(
+    source: &Source,
+    settings: &ClientSettings,
+    token: Option<&str>,
+    timeout: Duration,
+) -> Result<Info, Box<dyn std::error::Error>> {
+    let (mut stream, version): (Box<dyn Read>, u16) = match source {
+        Source::Remote(addr) => {
+            let addr = addr.to_socket_addrs()?.next().ok_or("Invalid address")?;
+            let mut client = TcpStream::connect_timeout(&addr, timeout)?;
+            client.set_read_timeout(Some(timeout))?;
+            let version = protocol::read_version(&client)?;
+            check_version(version)?;
+            if let Some(token) = token {
+                protocol::write_token(&mut client, token)?;
+            }
+            protocol::write_client_settings(&mut client, settings)?;
+            (Box::new(client), version)
+        }
+        #[cfg(unix)]
+        Source::Unix(path) => {
+            let mut client = UnixStream::connect(path)?;
+            client.set_read_timeout(Some(timeout))?;
+            let version = protocol::read_version(&client)?;
+            check_version(version)?;
+            if let Some(token) = token {
+                protocol::write_token(&mut client, token)?;
+            }
+            protocol::write_client_settings(&mut client, settings)?;
+            (Box::new(client), version)
+        }
+        Source::File(path)
+        | Source::Fifo(path)
+        | Source::Replay(path, _)
+        | Source::Offline(path) => {
+            let mut file = File::open(path)?;
+            let version = protocol::read_version(&mut file)?;
+            check_version(version)?;
+            (Box::new(file), version)
+        }
+    };
     loop {
-        if let Err(e) = read_remote(addr, &tx, sampling_interval, timeout) {
-            tx.send(Event::Disconnect).ok();
-            eprintln!("Error: {:?}", e);
+        if let Packet::Info(info) = protocol::read_packet_versioned(&mut stream, version)? {
+            return Ok(info);
+        }
+    }
+}
+
+/// Protocol errors (not a scope server, unsupported version) can not be fixed by reconnecting
+fn is_fatal(error: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<metrics_exporter_scope::Error>(),
+        Some(metrics_exporter_scope::Error::Protocol(_))
+    )
+}
+
+fn reader(
+    source: &Source,
+    tx: &EventSender,
+    settings: &ReaderSettings,
+    timeout: Duration,
+    recording: Option<&Recording>,
+    #[cfg(feature = "parquet")] capture: Option<&Capture>,
+) {
+    let mut error_log = ErrorLog::default();
+    let mut backoff = Backoff::new(settings.reconnect_max_delay);
+    loop {
+        let mut diagnostics = Diagnostics::default();
+        let result = match source {
+            Source::Remote(addr) => read_remote(
+                addr,
+                tx,
+                settings,
+                timeout,
+                &mut error_log,
+                &mut diagnostics,
+                recording,
+                #[cfg(feature = "parquet")]
+                capture,
+            ),
+            #[cfg(unix)]
+            Source::Unix(path) => read_unix(
+                path,
+                tx,
+                settings,
+                timeout,
+                &mut error_log,
+                &mut diagnostics,
+                recording,
+                #[cfg(feature = "parquet")]
+                capture,
+            ),
+            Source::File(path) => read_file(
+                path,
+                tx,
+                &mut error_log,
+                #[cfg(feature = "parquet")]
+                capture,
+            ),
+            Source::Fifo(path) => read_fifo(
+                path,
+                tx,
+                &mut error_log,
+                #[cfg(feature = "parquet")]
+                capture,
+            ),
+            Source::Replay(path, speed) => read_replay(
+                path,
+                Some(speed),
+                tx,
+                &mut error_log,
+                #[cfg(feature = "parquet")]
+                capture,
+            ),
+            Source::Offline(path) => read_replay(
+                path,
+                None,
+                tx,
+                &mut error_log,
+                #[cfg(feature = "parquet")]
+                capture,
+            ),
+        };
+        // the connection is over, the capture is written, so it is complete up to the last
+        // received packet
+        #[cfg(feature = "parquet")]
+        if let Some(capture) = capture {
+            if let Err(e) = capture.flush() {
+                error!(
+                    path = %capture.path().display(),
+                    error = %e,
+                    "unable to write the capture file"
+                );
+            }
+        }
+        match result {
+            // the recording has been replayed or loaded to the end, keep the data on screen
+            Ok(()) if matches!(source, Source::Replay(..) | Source::Offline(_)) => return,
+            // the settings have been changed or the pipe has been closed, reconnect immediately
+            Ok(()) => continue,
+            Err(e) => {
+                tx.send(Event::Disconnect).ok();
+                // the server has been up, so try to reconnect quickly
+                if diagnostics.connected {
+                    backoff.reset();
+                }
+                diagnostics.error = e.to_string();
+                diagnostics.fatal = is_fatal(e.as_ref());
+                tx.send(Event::Diagnostics(diagnostics)).ok();
+                if is_fatal(e.as_ref()) {
+                    error!(error = %e, "fatal error, giving up reconnecting");
+                    return;
+                }
+                error_log.report(&e.to_string());
+            }
+        }
+        thread::sleep(backoff.next_delay());
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Runs the reader and restarts it after [`READER_RESTART_DELAY`] if it panics, so the UI keeps
+/// reconnecting instead of staying offline forever
+pub fn supervised_reader(
+    source: &Source,
+    tx: EventSender,
+    settings: Arc<ReaderSettings>,
+    timeout: Duration,
+    recording: Option<Recording>,
+    #[cfg(feature = "parquet")] capture: Option<Capture>,
+) {
+    loop {
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            reader(
+                source,
+                &tx,
+                &settings,
+                timeout,
+                recording.as_ref(),
+                #[cfg(feature = "parquet")]
+                capture.as_ref(),
+            );
+        })) {
+            Ok(()) => break,
+            Err(payload) => {
+                error!(
+                    panic = panic_message(payload.as_ref()),
+                    "reader thread panicked, restarting"
+                );
+                tx.send(Event::Disconnect).ok();
+                thread::sleep(READER_RESTART_DELAY);
+            }
         }
-        thread::sleep(Duration::from_secs(1));
     }
 }
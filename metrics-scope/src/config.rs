@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use clap::builder::{TypedValueParser, ValueParserFactory};
+use egui::Color32;
+use notify::{RecursiveMode, Watcher as _};
+use serde::Deserialize;
+
+use crate::args::{
+    Args, PlotConfig, PredefinedRate, PredefinedSaneRange, PredefinedSma, PredefinedTrigger,
+    PredefinedYRange, Rate, SaneRange, ToPlotConfigMap as _, ToRateMap as _, ToSaneRangeMap as _,
+    ToSmaMap as _, ToTriggerMap as _, TriggerConfig,
+};
+use crate::parse_color;
+
+/// Dashboard config file, the values have the same syntax as the command-line options
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+    y_range: Vec<String>,
+    sma: Vec<String>,
+    rate: Vec<String>,
+    trigger: Vec<String>,
+    sane_range: Vec<String>,
+    // metric -> color name, #RRGGBB or #RGB
+    colors: BTreeMap<String, String>,
+}
+
+/// Predefined plot and metric settings, from the command line or from the config file
+#[derive(Clone, Default)]
+pub struct DashboardConfig {
+    pub plots: BTreeMap<String, PlotConfig>,
+    pub smas: BTreeMap<String, usize>,
+    pub rates: BTreeMap<String, Rate>,
+    pub triggers: BTreeMap<String, TriggerConfig>,
+    pub sane_ranges: BTreeMap<String, SaneRange>,
+    pub colors: BTreeMap<String, Color32>,
+}
+
+impl DashboardConfig {
+    /// The predefined settings from the command-line options (colors are set in the config only)
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            plots: args.predefined_y_range.to_plot_config_map(),
+            smas: args.predefined_sma.to_sma_map(),
+            rates: args.predefined_rate.to_rate_map(),
+            triggers: args.predefined_trigger.to_trigger_map(),
+            sane_ranges: args.sane_range.to_sane_range_map(),
+            colors: BTreeMap::new(),
+        }
+    }
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let file: ConfigFile = toml::from_str(&contents).map_err(|e| e.to_string())?;
+        let mut colors = BTreeMap::new();
+        for (name, color) in file.colors {
+            let parsed =
+                parse_color(&color).ok_or_else(|| format!("colors: invalid color: {}", color))?;
+            colors.insert(name, parsed);
+        }
+        Ok(Self {
+            plots: parse_values::<PredefinedYRange>("y-range", &file.y_range)?.to_plot_config_map(),
+            smas: parse_values::<PredefinedSma>("sma", &file.sma)?.to_sma_map(),
+            rates: parse_values::<PredefinedRate>("rate", &file.rate)?.to_rate_map(),
+            triggers: parse_values::<PredefinedTrigger>("trigger", &file.trigger)?.to_trigger_map(),
+            sane_ranges: parse_values::<PredefinedSaneRange>("sane-range", &file.sane_range)?
+                .to_sane_range_map(),
+            colors,
+        })
+    }
+    /// The settings of the other config override ones of this config
+    pub fn merged(&self, other: &Self) -> Self {
+        let mut config = self.clone();
+        config.plots.extend(other.plots.clone());
+        config.smas.extend(other.smas.clone());
+        config.rates.extend(other.rates.clone());
+        config.triggers.extend(other.triggers.clone());
+        config.sane_ranges.extend(other.sane_ranges.clone());
+        config.colors.extend(other.colors.clone());
+        config
+    }
+}
+
+/// Parses config values with the command-line option parsers
+fn parse_values<T>(key: &str, values: &[String]) -> Result<Vec<T>, String>
+where
+    T: ValueParserFactory,
+    T::Parser: TypedValueParser<Value = T>,
+{
+    let cmd = clap::Command::new("config");
+    let parser = T::value_parser();
+    values
+        .iter()
+        .map(|value| {
+            parser
+                .parse_ref(&cmd, None, OsStr::new(value))
+                .map_err(|e| {
+                    format!(
+                        "{}: {}",
+                        key,
+                        e.to_string().trim().trim_start_matches("error: ")
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Watches the config file for changes
+pub struct ConfigWatcher {
+    changed: Arc<AtomicBool>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path, ctx: egui::Context) -> notify::Result<Self> {
+        let changed = Arc::new(AtomicBool::new(false));
+        let changed_c = changed.clone();
+        let file_name = path.file_name().map(ToOwned::to_owned);
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                let Ok(event) = res else {
+                    return;
+                };
+                if (event.kind.is_modify() || event.kind.is_create())
+                    && event
+                        .paths
+                        .iter()
+                        .any(|path| path.file_name() == file_name.as_deref())
+                {
+                    changed_c.store(true, Ordering::Release);
+                    ctx.request_repaint();
+                }
+            })?;
+        // editors often replace the file instead of writing it, so the directory is watched
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            changed,
+            _watcher: watcher,
+        })
+    }
+    /// Returns `true` once after the file has been changed
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::AcqRel)
+    }
+}
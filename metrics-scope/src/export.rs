@@ -0,0 +1,246 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use egui::{ColorImage, Rect};
+
+use crate::Triggered;
+
+// the logical DPI of a display at 1 pixel per point
+const BASE_DPI: f32 = 96.0;
+
+const INCHES_PER_METER: f32 = 39.370_08;
+
+pub fn export_csv<P: AsRef<Path>>(
+    path: P,
+    source: &str,
+    data: &BTreeMap<String, Vec<f64>>,
+    triggers: &[Triggered],
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_csv(&mut writer, source, data, triggers)?;
+    writer.into_inner()?.sync_all()
+}
+
+/// Writes the data buffer as CSV, prefixed with a commented metadata section. Metric columns are
+/// aligned to the newest timestamp, missing (not yet received) values are left empty
+pub fn write_csv<W: Write>(
+    mut w: W,
+    source: &str,
+    data: &BTreeMap<String, Vec<f64>>,
+    triggers: &[Triggered],
+) -> io::Result<()> {
+    writeln!(w, "# source: {}", source)?;
+    for tr in triggers {
+        writeln!(
+            w,
+            "# trigger: t={} metric={} direction={} value={}",
+            tr.at,
+            tr.by,
+            tr.below_above.as_str(),
+            tr.value
+        )?;
+    }
+    let empty = Vec::new();
+    let ts_vec = data.get("").unwrap_or(&empty);
+    let metrics: Vec<_> = data.iter().filter(|(name, _)| !name.is_empty()).collect();
+    write!(w, "t")?;
+    for (name, _) in &metrics {
+        write!(w, ",{}", csv_field(name))?;
+    }
+    writeln!(w)?;
+    for (i, ts) in ts_vec.iter().enumerate() {
+        write!(w, "{}", ts)?;
+        for (_, values) in &metrics {
+            let offset = ts_vec.len().saturating_sub(values.len());
+            match i.checked_sub(offset).and_then(|i| values.get(i)) {
+                Some(value) if !value.is_nan() => write!(w, ",{}", value)?,
+                _ => write!(w, ",")?,
+            }
+        }
+        writeln!(w)?;
+    }
+    Ok(())
+}
+
+/// Quotes a field per RFC 4180 if it contains a delimiter, a quote or a line break
+fn csv_field(s: &str) -> Cow<'_, str> {
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+pub fn export_summary<P: AsRef<Path>>(
+    path: P,
+    source: &str,
+    data: &BTreeMap<String, Vec<f64>>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_summary(&mut writer, source, data)?;
+    writer.into_inner()?.sync_all()
+}
+
+/// Per-metric statistics of the buffered values, NaNs (missing values) are skipped
+struct Summary {
+    count: usize,
+    min: f64,
+    max: f64,
+    mean: f64,
+    stddev: f64,
+    last: f64,
+}
+
+impl Summary {
+    fn calc(values: &[f64]) -> Option<Self> {
+        let mut count = 0;
+        let mut min = f64::INFINITY;
+        let mut max = f64::NEG_INFINITY;
+        let mut mean = 0.0;
+        // Welford's running sum of squared deviations
+        let mut m2 = 0.0;
+        let mut last = f64::NAN;
+        for value in values.iter().copied().filter(|v| !v.is_nan()) {
+            count += 1;
+            min = min.min(value);
+            max = max.max(value);
+            let delta = value - mean;
+            #[allow(clippy::cast_precision_loss)]
+            let n = count as f64;
+            mean += delta / n;
+            m2 += delta * (value - mean);
+            last = value;
+        }
+        if count == 0 {
+            return None;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let stddev = (m2 / count as f64).sqrt();
+        Some(Self {
+            count,
+            min,
+            max,
+            mean,
+            stddev,
+            last,
+        })
+    }
+}
+
+/// Writes per-metric statistics (population standard deviation) of the data buffer as a text
+/// table, prefixed with the source and the covered timestamp range
+pub fn write_summary<W: Write>(
+    mut w: W,
+    source: &str,
+    data: &BTreeMap<String, Vec<f64>>,
+) -> io::Result<()> {
+    writeln!(w, "# source: {}", source)?;
+    let empty = Vec::new();
+    let ts_vec = data.get("").unwrap_or(&empty);
+    if let (Some(first), Some(last)) = (ts_vec.first(), ts_vec.last()) {
+        writeln!(
+            w,
+            "# t: {:.3}..{:.3} ({:.3} s, {} snapshots)",
+            first,
+            last,
+            last - first,
+            ts_vec.len()
+        )?;
+    }
+    let metrics: Vec<_> = data
+        .iter()
+        .filter(|(name, _)| !name.is_empty())
+        .filter_map(|(name, values)| Summary::calc(values).map(|summary| (name, summary)))
+        .collect();
+    let width = metrics
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or_default()
+        .max("metric".len());
+    writeln!(
+        w,
+        "{:<width$} {:>8} {:>14} {:>14} {:>14} {:>14} {:>14}",
+        "metric",
+        "count",
+        "min",
+        "max",
+        "mean",
+        "stddev",
+        "last",
+        width = width
+    )?;
+    for (name, s) in metrics {
+        writeln!(
+            w,
+            "{:<width$} {:>8} {:>14.6} {:>14.6} {:>14.6} {:>14.6} {:>14.6}",
+            name,
+            s.count,
+            s.min,
+            s.max,
+            s.mean,
+            s.stddev,
+            s.last,
+            width = width
+        )?;
+    }
+    Ok(())
+}
+
+/// Crops a screenshot to the area (in points). Screenshots are taken in physical pixels, so the
+/// area is scaled and the image keeps the native resolution
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn crop_screenshot(image: &ColorImage, area: Rect, pixels_per_point: f32) -> ColorImage {
+    let [width, height] = image.size;
+    let to_pixels =
+        |v: f32, max: usize| ((v * pixels_per_point).round().max(0.0) as usize).min(max);
+    let (min_x, min_y) = (to_pixels(area.min.x, width), to_pixels(area.min.y, height));
+    let max_x = to_pixels(area.max.x, width).max(min_x);
+    let max_y = to_pixels(area.max.y, height).max(min_y);
+    let mut pixels = Vec::with_capacity((max_x - min_x) * (max_y - min_y));
+    for y in min_y..max_y {
+        pixels.extend_from_slice(&image.pixels[y * width + min_x..y * width + max_x]);
+    }
+    ColorImage {
+        size: [max_x - min_x, max_y - min_y],
+        pixels,
+    }
+}
+
+/// Writes a screenshot as an RGBA PNG, the physical resolution is stored as well, so viewers
+/// display the image at the screen size
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn export_png<P: AsRef<Path>>(
+    path: P,
+    image: &ColorImage,
+    pixels_per_point: f32,
+) -> io::Result<()> {
+    let [width, height] = image.size;
+    let invalid_size = |_| io::Error::new(io::ErrorKind::InvalidInput, "the image is too large");
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(
+        &mut file,
+        u32::try_from(width).map_err(invalid_size)?,
+        u32::try_from(height).map_err(invalid_size)?,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels_per_meter = (BASE_DPI * pixels_per_point * INCHES_PER_METER).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    let data: Vec<u8> = image
+        .pixels
+        .iter()
+        .flat_map(egui::Color32::to_srgba_unmultiplied)
+        .collect();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    file.into_inner()?.sync_all()
+}
@@ -1,23 +1,36 @@
 use std::cmp;
-use std::collections::{BTreeMap, BTreeSet};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use args::{
-    Args, PlotConfig, ToPlotConfigMap as _, ToSmaMap as _, ToTriggerMap as _, TriggerConfig,
+    Args, Interpolation, NameTransform, PlotConfig, PlotOrder, Rate, Retain, SaneRange,
+    TriggerCommand, TriggerConfig,
 };
 use atomic_float::AtomicF64;
-use clap::Parser;
+use clap::{Parser, ValueEnum as _};
+use client::ReaderSettings;
 use egui::{Button, Color32, RichText, Ui};
-use egui_plot::{Legend, Line, Plot, PlotPoint, PlotPoints};
-use metrics_exporter_scope::Packet;
-use once_cell::sync::Lazy;
+use egui_plot::{
+    Legend, Line, MarkerShape, Plot, PlotBounds, PlotPoint, PlotPoints, Points, Polygon, Text,
+};
+use metrics_exporter_scope::{protocol, ClientSettings, MetricInfo, MetricKind, Packet};
+use once_cell::sync::{Lazy, OnceCell};
 use rtsc::data_policy::{DataDeliveryPolicy, DeliveryPolicy};
 
 mod args;
+#[cfg(feature = "parquet")]
+mod capture;
+mod check;
 mod client;
+mod config;
+mod export;
+#[cfg(test)]
+mod tests;
 
 type EventSender = rtsc::policy_channel::Sender<Event, parking_lot::RawMutex, parking_lot::Condvar>;
 type EventReceiver =
@@ -25,24 +38,65 @@ type EventReceiver =
 
 const DATA_BUF_SIZE: usize = 100_000;
 const UI_DELAY: Duration = Duration::from_millis(50);
+// when paused or no data is received, the UI is repainted at a low rate to save CPU
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+const IDLE_REPAINT_INTERVAL: Duration = Duration::from_millis(200);
+#[cfg(feature = "parquet")]
+const CAPTURE_WRITE_INTERVAL: Duration = Duration::from_secs(60);
 
 const MAX_TIME_WINDOW: f32 = 600.0;
 
+const SINGLE_PLOT_NAME: &str = "all";
+
+// sampling intervals, which can be selected live (ms)
+const SAMPLING_INTERVAL_PRESETS: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+// replay speed limits, a multiplier of the recorded pace
+const REPLAY_SPEED_RANGE: RangeInclusive<f64> = 0.1..=100.0;
+
+// the space, left to the seek bar value label
+const SEEK_BAR_LABEL_WIDTH: f32 = 150.0;
+
+const TRIGGER_HISTORY_SIZE: usize = 1_000;
+
+// events beyond the data buffer are dropped as well
+const MAX_TIMELINE_EVENTS: usize = 10_000;
+
+const EVENT_LANE_HEIGHT: f32 = 20.0;
+
+const CUSTOM_COLORS_KEY: &str = "custom_colors";
+
+const SELECTED_PAGE_KEY: &str = "selected_page";
+
+const SELECTED_TAB_KEY: &str = "selected_tab";
+
+// the tab for plots without a group label
+const UNGROUPED_TAB: &str = "other";
+
 enum Event {
     Connect,
     Disconnect,
+    Diagnostics(client::Diagnostics),
     Packet(Packet),
+    // a packet of the recording, loaded by the offline viewer or from the existing content of a
+    // followed file, never dropped
+    Loaded(Packet),
 }
 
 impl DataDeliveryPolicy for Event {
     fn delivery_policy(&self) -> DeliveryPolicy {
         match self {
-            Event::Connect | Event::Disconnect => DeliveryPolicy::Always,
+            Event::Connect | Event::Disconnect | Event::Diagnostics(_) | Event::Loaded(_) => {
+                DeliveryPolicy::Always
+            }
             Event::Packet(_) => DeliveryPolicy::Latest,
         }
     }
 }
 
+// the UI context, the signal handler closes the window with
+static UI_CTX: OnceCell<egui::Context> = OnceCell::new();
+
 static COLORS: Lazy<BTreeMap<String, Color32>> = Lazy::new(|| {
     let mut colors = BTreeMap::new();
     colors.insert("red".to_owned(), Color32::RED);
@@ -60,7 +114,7 @@ static COLORS: Lazy<BTreeMap<String, Color32>> = Lazy::new(|| {
 fn parse_color(color: &str) -> Option<Color32> {
     if let Some(color) = COLORS.get(color) {
         Some(*color)
-    } else if let Some(c) = color.strip_prefix('#') {
+    } else if let Some(c) = color.strip_prefix('#').filter(|c| c.is_ascii()) {
         match c.len() {
             3 => {
                 let r = u8::from_str_radix(&c[0..1].repeat(2), 16).ok()?;
@@ -81,10 +135,544 @@ fn parse_color(color: &str) -> Option<Color32> {
     }
 }
 
+fn parse_custom_colors(s: &str) -> BTreeMap<String, Color32> {
+    s.lines()
+        .filter_map(|line| {
+            let (name, color) = line.rsplit_once('=')?;
+            Some((name.to_owned(), parse_color(color)?))
+        })
+        .collect()
+}
+
+fn format_custom_colors(colors: &BTreeMap<String, Color32>) -> String {
+    colors
+        .iter()
+        .map(|(name, color)| {
+            format!(
+                "{}=#{:02x}{:02x}{:02x}\n",
+                name,
+                color.r(),
+                color.g(),
+                color.b()
+            )
+        })
+        .collect()
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_possible_wrap
+)]
+fn format_value(value: f64, scale: Option<u64>) -> String {
+    let Some(scale) = scale.filter(|s| *s > 1) else {
+        return value.to_string();
+    };
+    let digits = scale.ilog10();
+    if 10u64.pow(digits) == scale && value.is_finite() && value.abs() < 9_007_199_254_740_992.0 {
+        let raw = value.round() as i64;
+        let abs = raw.unsigned_abs();
+        format!(
+            "{}{}.{:0width$}",
+            if raw < 0 { "-" } else { "" },
+            abs / scale,
+            abs % scale,
+            width = digits as usize
+        )
+    } else {
+        (value / scale as f64).to_string()
+    }
+}
+
+/// Log Y-axes are drawn by plotting log10 of the values, non-positive ones are dropped
+fn log_y(value: f64) -> f64 {
+    if value > 0.0 {
+        value.log10()
+    } else {
+        f64::NAN
+    }
+}
+
+/// The discrete derivative of the samples (per second). The first sample has no predecessor and
+/// gaps (NaN samples) break the rate as well
+fn rate_of_change(data: &[f64], interval: f64) -> Vec<f64> {
+    let mut rate = Vec::with_capacity(data.len());
+    if !data.is_empty() {
+        rate.push(f64::NAN);
+    }
+    rate.extend(data.windows(2).map(|w| (w[1] - w[0]) / interval));
+    rate
+}
+
+fn format_wall_clock(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03} UTC",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Formats a recorded timestamp as the wall clock time, if the stream epoch is known
+fn format_recorded_time(ts: f64, stream_epoch: Option<SystemTime>) -> String {
+    stream_epoch
+        .and_then(|epoch| epoch.checked_add(Duration::from_secs_f64(ts.max(0.0))))
+        .map_or_else(|| format!("t={:.3}", ts), format_wall_clock)
+}
+
+const STATE_COLORS: [Color32; 8] = [
+    Color32::from_rgb(96, 96, 96),
+    Color32::from_rgb(64, 160, 64),
+    Color32::from_rgb(220, 160, 32),
+    Color32::from_rgb(200, 64, 64),
+    Color32::from_rgb(64, 128, 220),
+    Color32::from_rgb(160, 96, 200),
+    Color32::from_rgb(32, 180, 180),
+    Color32::from_rgb(220, 96, 160),
+];
+
+const STATE_RIBBON_HEIGHT: f64 = 0.8;
+
+fn state_color(state: f64) -> Color32 {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_possible_wrap,
+        clippy::cast_sign_loss
+    )]
+    let idx = (state.round() as i64).rem_euclid(STATE_COLORS.len() as i64) as usize;
+    STATE_COLORS[idx]
+}
+
+/// Draws a discrete metric as a horizontal ribbon, colored by the state value. Several state
+/// metrics of the same plot are stacked into bands
+#[allow(clippy::float_cmp)]
+fn show_state_ribbon(
+    plot_ui: &mut egui_plot::PlotUi,
+    name: &str,
+    data: &[f64],
+    ts_vec_axis: &[f64],
+    band: f64,
+) {
+    let mut start = 0;
+    while start < data.len() {
+        let state = data[start];
+        let mut end = start + 1;
+        while end < data.len() && (data[end] == state || (data[end].is_nan() && state.is_nan())) {
+            end += 1;
+        }
+        if !state.is_nan() {
+            let (Some(x0), Some(x1)) = (
+                ts_vec_axis.get(start),
+                ts_vec_axis.get(end).or_else(|| ts_vec_axis.get(end - 1)),
+            ) else {
+                break;
+            };
+            let y1 = band + STATE_RIBBON_HEIGHT;
+            plot_ui.polygon(
+                Polygon::new(PlotPoints::new(vec![
+                    [*x0, band],
+                    [*x1, band],
+                    [*x1, y1],
+                    [*x0, y1],
+                ]))
+                .fill_color(state_color(state))
+                .stroke(egui::Stroke::NONE)
+                .name(format!("{} (state)", name)),
+            );
+        }
+        start = end;
+    }
+}
+
+/// Single-value metrics, rendered as indicators instead of plots (`render` label)
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Indicator {
+    Bar,
+    Dial,
+}
+
+impl Indicator {
+    fn from_render(render: Option<&str>) -> Option<Self> {
+        match render? {
+            "bar" => Some(Indicator::Bar),
+            "gauge" | "dial" => Some(Indicator::Dial),
+            _ => None,
+        }
+    }
+}
+
+const INDICATOR_WIDTH: f32 = 200.0;
+
+/// Draws a semicircle dial with a needle, the fraction is the needle position (0..=1)
+fn show_dial(ui: &mut Ui, fraction: f32, color: Color32) {
+    let (rect, _) = ui.allocate_exact_size(
+        egui::vec2(INDICATOR_WIDTH / 2.0, INDICATOR_WIDTH / 4.0 + 5.0),
+        egui::Sense::hover(),
+    );
+    let painter = ui.painter_at(rect);
+    let center = egui::pos2(rect.center().x, rect.bottom() - 2.0);
+    let radius = rect.width() / 2.0 - 2.0;
+    let point = |f: f32| {
+        let angle = std::f32::consts::PI * (1.0 - f);
+        center + radius * egui::vec2(angle.cos(), -angle.sin())
+    };
+    #[allow(clippy::cast_precision_loss)]
+    let arc = |to: f32| {
+        (0..=32)
+            .map(|i| point(to * i as f32 / 32.0))
+            .collect::<Vec<_>>()
+    };
+    let bg = ui.visuals().widgets.inactive.bg_fill;
+    painter.add(egui::Shape::line(arc(1.0), egui::Stroke::new(6.0, bg)));
+    painter.add(egui::Shape::line(
+        arc(fraction),
+        egui::Stroke::new(6.0, color),
+    ));
+    painter.line_segment(
+        [center, point(fraction)],
+        egui::Stroke::new(2.0, ui.visuals().text_color()),
+    );
+}
+
+// points per segment, drawn by the spline interpolation
+const SPLINE_SEGMENT_POINTS: usize = 4;
+
+fn interpolation_name(interpolation: Interpolation) -> &'static str {
+    match interpolation {
+        Interpolation::Linear => "linear",
+        Interpolation::Step => "step",
+        Interpolation::Spline => "spline",
+    }
+}
+
+fn rate_name(rate: Rate) -> &'static str {
+    match rate {
+        Rate::Off => "off",
+        Rate::Replace => "replace",
+        Rate::Overlay => "overlay",
+    }
+}
+
+/// Builds line points from the samples, NaN samples break the line
+fn interpolate(interpolation: Interpolation, data: &[f64], ts: &[f64]) -> Vec<PlotPoint> {
+    match interpolation {
+        Interpolation::Linear => data
+            .iter()
+            .zip(ts)
+            .map(|(d, ts)| PlotPoint::new(*ts, *d))
+            .collect(),
+        Interpolation::Step => {
+            let mut points = Vec::with_capacity(data.len() * 2);
+            let mut prev: Option<f64> = None;
+            for (d, ts) in data.iter().zip(ts) {
+                if let Some(prev) = prev.filter(|prev| !prev.is_nan() && !d.is_nan()) {
+                    points.push(PlotPoint::new(*ts, prev));
+                }
+                points.push(PlotPoint::new(*ts, *d));
+                prev = Some(*d);
+            }
+            points
+        }
+        Interpolation::Spline => {
+            let mut points = Vec::with_capacity(data.len() * SPLINE_SEGMENT_POINTS);
+            let mut start = 0;
+            while start < data.len() {
+                if data[start].is_nan() {
+                    points.push(PlotPoint::new(ts[start], f64::NAN));
+                    start += 1;
+                    continue;
+                }
+                let end = data[start..]
+                    .iter()
+                    .position(|d| d.is_nan())
+                    .map_or(data.len(), |pos| start + pos);
+                monotone_spline(&data[start..end], &ts[start..end], &mut points);
+                start = end;
+            }
+            points
+        }
+    }
+}
+
+/// Monotone cubic (Fritsch-Carlson) interpolation of a NaN-free run, the curve does not
+/// overshoot the samples where the data is monotone
+fn monotone_spline(data: &[f64], ts: &[f64], points: &mut Vec<PlotPoint>) {
+    let n = data.len();
+    if n < 3 {
+        points.extend(data.iter().zip(ts).map(|(d, ts)| PlotPoint::new(*ts, *d)));
+        return;
+    }
+    let secants: Vec<f64> = (0..n - 1)
+        .map(|k| (data[k + 1] - data[k]) / (ts[k + 1] - ts[k]))
+        .collect();
+    let mut tangents = Vec::with_capacity(n);
+    tangents.push(secants[0]);
+    tangents.extend(secants.windows(2).map(|w| {
+        if w[0] * w[1] <= 0.0 {
+            0.0
+        } else {
+            (w[0] + w[1]) / 2.0
+        }
+    }));
+    tangents.push(secants[n - 2]);
+    for (k, secant) in secants.iter().enumerate() {
+        if secant.abs() < f64::EPSILON {
+            tangents[k] = 0.0;
+            tangents[k + 1] = 0.0;
+            continue;
+        }
+        let a = tangents[k] / secant;
+        let b = tangents[k + 1] / secant;
+        let s = a * a + b * b;
+        if s > 9.0 {
+            let t = 3.0 / s.sqrt();
+            tangents[k] = t * a * secant;
+            tangents[k + 1] = t * b * secant;
+        }
+    }
+    for (k, h) in ts.windows(2).map(|w| w[1] - w[0]).enumerate() {
+        for i in 0..SPLINE_SEGMENT_POINTS {
+            #[allow(clippy::cast_precision_loss)]
+            let t = i as f64 / SPLINE_SEGMENT_POINTS as f64;
+            let t2 = t * t;
+            let t3 = t2 * t;
+            let y = (2.0 * t3 - 3.0 * t2 + 1.0) * data[k]
+                + (t3 - 2.0 * t2 + t) * h * tangents[k]
+                + (-2.0 * t3 + 3.0 * t2) * data[k + 1]
+                + (t3 - t2) * h * tangents[k + 1];
+            points.push(PlotPoint::new(ts[k] + t * h, y));
+        }
+    }
+    points.push(PlotPoint::new(ts[n - 1], data[n - 1]));
+}
+
+// the most recent value marker radius
+const MARKER_RADIUS: f32 = 4.0;
+
+// the default gap shading color (light red)
+const GAP_COLOR: Color32 = Color32::from_rgb(255, 120, 120);
+
+/// Shades gaps (NaN runs after the first received value) with translucent bands, which span the
+/// value range of the data, so the chart scale is not affected
+fn show_gaps(
+    plot_ui: &mut egui_plot::PlotUi,
+    name: &str,
+    data: &[f64],
+    ts_vec_axis: &[f64],
+    color: Color32,
+) {
+    let Some((min, max)) =
+        data.iter()
+            .filter(|v| !v.is_nan())
+            .fold(None, |acc: Option<(f64, f64)>, v| {
+                Some(acc.map_or((*v, *v), |(min, max)| (min.min(*v), max.max(*v))))
+            })
+    else {
+        return;
+    };
+    let (y0, y1) = if max > min {
+        (min, max)
+    } else {
+        (min - 0.5, max + 0.5)
+    };
+    let Some(mut i) = data.iter().position(|v| !v.is_nan()) else {
+        return;
+    };
+    while i < data.len() {
+        if !data[i].is_nan() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < data.len() && data[i].is_nan() {
+            i += 1;
+        }
+        // the band spans from the last value before the gap to the first one after it
+        let (Some(x0), Some(x1)) = (
+            ts_vec_axis.get(start - 1),
+            ts_vec_axis.get(i).or_else(|| ts_vec_axis.last()),
+        ) else {
+            break;
+        };
+        plot_ui.polygon(
+            Polygon::new(PlotPoints::new(vec![
+                [*x0, y0],
+                [*x1, y0],
+                [*x1, y1],
+                [*x0, y1],
+            ]))
+            .fill_color(color.gamma_multiply(0.25))
+            .stroke(egui::Stroke::NONE)
+            .name(format!("{} (gap)", name)),
+        );
+    }
+}
+
+/// A row of the rich legend: the live value and the min/max of the displayed window (raw values)
+struct LegendRow {
+    name: String,
+    color: Option<Color32>,
+    scale: Option<u64>,
+    last: Option<f64>,
+    min_max: Option<(f64, f64)>,
+    description: Option<String>,
+}
+
+impl LegendRow {
+    fn new(name: &str, color: Option<Color32>, scale: Option<u64>, data: &[f64]) -> Self {
+        let min_max =
+            data.iter()
+                .filter(|v| !v.is_nan())
+                .fold(None, |acc: Option<(f64, f64)>, v| {
+                    Some(acc.map_or((*v, *v), |(min, max)| (min.min(*v), max.max(*v))))
+                });
+        Self {
+            name: name.to_owned(),
+            color,
+            scale,
+            last: data.iter().rev().find(|v| !v.is_nan()).copied(),
+            min_max,
+            description: None,
+        }
+    }
+    fn with_description(mut self, description: Option<&String>) -> Self {
+        self.description = description.cloned();
+        self
+    }
+}
+
+/// The same automatic colors as `egui_plot` assigns, so lines match the rich legend rows
+fn auto_color(index: usize) -> Color32 {
+    let golden_ratio = (5.0_f32.sqrt() - 1.0) / 2.0;
+    #[allow(clippy::cast_precision_loss)]
+    let h = index as f32 * golden_ratio;
+    egui::ecolor::Hsva::new(h, 0.85, 0.5, 1.0).into()
+}
+
+// the number of colors, metric names are hashed into with --color-seed
+const SEEDED_PALETTE_SIZE: u64 = 32;
+
+/// A stable automatic color of a metric: 64-bit FNV-1a hash of the seed (8 bytes, little-endian)
+/// followed by the metric name, modulo [`SEEDED_PALETTE_SIZE`], is used as the [`auto_color`]
+/// index
+fn seeded_color(name: &str, seed: u64) -> Color32 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+    let hash = seed
+        .to_le_bytes()
+        .iter()
+        .chain(name.as_bytes())
+        .fold(FNV_OFFSET_BASIS, |hash, b| {
+            (hash ^ u64::from(*b)).wrapping_mul(FNV_PRIME)
+        });
+    #[allow(clippy::cast_possible_truncation)]
+    auto_color((hash % SEEDED_PALETTE_SIZE) as usize)
+}
+
+/// Draws the rich legend rows. Click mutes/unmutes a metric, right click solos it within the plot
+fn show_rich_legend(
+    ui: &mut Ui,
+    rows: &[LegendRow],
+    muted_metrics: &mut BTreeSet<String>,
+    solo_metric: &mut Option<String>,
+) {
+    for row in rows {
+        let muted = muted_metrics.contains(&row.name)
+            || solo_metric.as_ref().map_or(false, |solo| {
+                *solo != row.name && rows.iter().any(|r| r.name == *solo)
+            });
+        let mut text = format!(
+            "\u{25a0} {}  {}",
+            row.name,
+            row.last
+                .map_or_else(|| "-".to_owned(), |v| format_value(v, row.scale))
+        );
+        if let Some((min, max)) = row.min_max {
+            text.push_str(&format!(
+                "  min {}  max {}",
+                format_value(min, row.scale),
+                format_value(max, row.scale)
+            ));
+        }
+        let mut text = RichText::new(text).monospace();
+        if muted {
+            text = text.strikethrough().weak();
+        } else if let Some(color) = row.color {
+            text = text.color(color);
+        }
+        let response = ui
+            .add(egui::Label::new(text).sense(egui::Sense::click()))
+            .on_hover_text(row.description.as_ref().map_or_else(
+                || "Click to mute, right click to solo".to_owned(),
+                |description| format!("{}\n\nClick to mute, right click to solo", description),
+            ));
+        if response.clicked() && !muted_metrics.remove(&row.name) {
+            muted_metrics.insert(row.name.clone());
+        }
+        if response.secondary_clicked() {
+            if solo_metric.as_ref() == Some(&row.name) {
+                *solo_metric = None;
+            } else {
+                muted_metrics.remove(&row.name);
+                *solo_metric = Some(row.name.clone());
+            }
+        }
+    }
+}
+
+/// What to render if the number of metrics exceeds the budget (`--max-series`)
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum SeriesBudget {
+    Ask,
+    All,
+    TopVariance,
+}
+
+/// Variance of the received (non-NaN) values
+fn variance(values: &[f64]) -> f64 {
+    let (count, sum) = values
+        .iter()
+        .filter(|v| !v.is_nan())
+        .fold((0, 0.0), |(count, sum), v| (count + 1, sum + v));
+    if count == 0 {
+        return 0.0;
+    }
+    let mean = sum / f64::from(count);
+    values
+        .iter()
+        .filter(|v| !v.is_nan())
+        .map(|v| (v - mean).powi(2))
+        .sum::<f64>()
+        / f64::from(count)
+}
+
+/// Runs the trigger action in a separate thread, so the UI is never blocked by it
+fn run_trigger_command(on_trigger: &TriggerCommand, tr: &Triggered) {
+    let mut command = on_trigger.command(&[
+        ("metric", tr.by.clone()),
+        ("value", tr.value.to_string()),
+        ("direction", tr.below_above.as_str().to_owned()),
+        ("t", tr.at.to_string()),
+    ]);
+    thread::spawn(move || match command.status() {
+        Ok(status) if !status.success() => {
+            tracing::warn!(%status, "trigger command failed");
+        }
+        Ok(_) => {}
+        Err(error) => {
+            tracing::error!(%error, "unable to run trigger command");
+        }
+    });
+}
+
 fn main() {
+    tracing_subscriber::fmt::init();
     let args = Args::parse();
     let mut source = args.source.clone();
-    if !source.contains(':') {
+    if !args.follow && !args.replay && !args.offline && !source.contains(':') {
         source = format!("{}:5001", source);
     }
     let options = eframe::NativeOptions {
@@ -95,61 +683,206 @@ fn main() {
         rtsc::policy_channel::bounded::<Event, parking_lot::RawMutex, parking_lot::Condvar>(
             DATA_BUF_SIZE,
         );
-    let source_c = source.clone();
+    let replay_speed = Arc::new(AtomicF64::new(
+        args.speed
+            .clamp(*REPLAY_SPEED_RANGE.start(), *REPLAY_SPEED_RANGE.end()),
+    ));
+    let reader_source = if let Some(path) = source.strip_prefix("fifo:") {
+        client::Source::Fifo(path.into())
+    } else if args.follow {
+        client::Source::File(source.clone().into())
+    } else if args.replay {
+        client::Source::Replay(source.clone().into(), replay_speed.clone())
+    } else if args.offline {
+        client::Source::Offline(source.clone().into())
+    } else {
+        client::Source::server(&source)
+    };
+    // recorded and piped streams are read as-is
+    let is_remote = reader_source.is_remote();
     let timeout = Duration::from_secs(args.timeout);
     let sampling_interval = Duration::from_secs_f64(args.sampling_interval);
+    if args.check {
+        let settings = ClientSettings::new(sampling_interval);
+        match client::fetch_info(&reader_source, &settings, args.token.as_deref(), timeout) {
+            Ok(info) => std::process::exit(i32::from(!check::check(&args, &info))),
+            Err(e) => {
+                eprintln!("Unable to read metrics information: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    #[cfg(feature = "parquet")]
+    let capture = args.parquet.as_ref().map(|path| {
+        let capture = capture::Capture::new(path.clone());
+        let capture_c = capture.clone();
+        thread::spawn(move || loop {
+            thread::sleep(CAPTURE_WRITE_INTERVAL);
+            if let Err(e) = capture_c.flush() {
+                tracing::error!(error = %e, "unable to write the capture file");
+            }
+        });
+        capture
+    });
+    #[cfg(feature = "parquet")]
+    let capture_c = capture.clone();
+    let mut client_settings = ClientSettings::new(sampling_interval)
+        .with_timestamp_unit(args.timestamp_unit.into())
+        .with_delta(args.delta)
+        .with_batch(args.batch);
+    #[cfg(feature = "compression")]
+    {
+        client_settings = client_settings.with_compression(args.compress);
+    }
+    if let Some(limit) = args.bandwidth_limit {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let bytes_per_second = (limit * 1000.0).max(1.0) as u64;
+        client_settings = client_settings.with_bandwidth_limit(bytes_per_second);
+    }
+    for metric_interval in &args.metric_interval {
+        client_settings = client_settings.with_metric_interval(
+            metric_interval.name.as_str(),
+            metric_interval.interval,
+            metric_interval.phase,
+        );
+    }
+    if let Some(max_metrics) = args.max_metrics {
+        client_settings = client_settings.with_max_metrics(max_metrics);
+    }
+    if let Some(ref filter) = args.server_filter {
+        // the expression has been already validated by the argument parser
+        client_settings = client_settings.with_filter(filter.as_str()).unwrap();
+    }
+    if !args.metrics.is_empty() {
+        client_settings = client_settings.with_patterns(args.metrics.iter().cloned());
+    }
+    if let Some(max_interval) = args.adaptive_max_interval {
+        client_settings = client_settings.with_adaptive_sampling(
+            Duration::from_secs_f64(max_interval),
+            args.adaptive_threshold,
+        );
+    }
+    let reader_settings = Arc::new(
+        ReaderSettings::new(client_settings)
+            .with_token(args.token.clone())
+            .with_reconnect_max_delay(Duration::from_secs_f64(args.reconnect_max_delay)),
+    );
+    let reader_settings_c = reader_settings.clone();
+    let recording = args.record.clone().and_then(|path| {
+        if is_remote {
+            Some(client::Recording::new(path))
+        } else {
+            tracing::warn!("only remote streams can be recorded");
+            None
+        }
+    });
+    // on Ctrl+C or termination the writers are flushed and the UI is closed, so the process exits
+    // the same way as if the window has been closed
+    {
+        #[cfg(feature = "parquet")]
+        let capture = capture.clone();
+        let recording = recording.clone();
+        let closing = AtomicBool::new(false);
+        if let Err(e) = ctrlc::set_handler(move || {
+            #[cfg(feature = "parquet")]
+            if let Some(ref capture) = capture {
+                if let Err(e) = capture.flush() {
+                    eprintln!("Unable to write the capture file: {}", e);
+                }
+            }
+            if let Some(ref recording) = recording {
+                recording.flush();
+            }
+            match UI_CTX.get() {
+                Some(ctx) if !closing.swap(true, Ordering::AcqRel) => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                    ctx.request_repaint();
+                }
+                // the UI is not started yet or does not close
+                _ => std::process::exit(1),
+            }
+        }) {
+            tracing::warn!(error = %e, "unable to set the signal handler");
+        }
+    }
+    let recording_c = recording.clone();
     thread::spawn(move || {
-        client::reader(&source_c, tx, sampling_interval, timeout);
+        client::supervised_reader(
+            &reader_source,
+            tx,
+            reader_settings_c,
+            timeout,
+            recording_c,
+            #[cfg(feature = "parquet")]
+            capture_c,
+        );
     });
+    let reader_settings = is_remote.then_some(reader_settings);
+    let cli_config = config::DashboardConfig::from_args(&args);
+    let dashboard_config = if let Some(ref path) = args.config {
+        match config::DashboardConfig::load(path) {
+            Ok(config) => cli_config.merged(&config),
+            Err(e) => {
+                eprintln!("Unable to load the config {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        cli_config.clone()
+    };
     // make args static
     let args = Box::leak(Box::new(args));
     eframe::run_native(
         &format!("{} - metrics-scope", source),
         options,
         Box::new(|cc| {
+            #[cfg(feature = "logo")]
             egui_extras::install_image_loaders(&cc.egui_ctx);
+            UI_CTX.set(cc.egui_ctx.clone()).ok();
             if let Some(theme) = args.theme.as_ref() {
                 match theme {
                     args::Theme::Dark => cc.egui_ctx.set_visuals(egui::Visuals::dark()),
                     args::Theme::Light => cc.egui_ctx.set_visuals(egui::Visuals::light()),
                 }
             }
-            Ok(Box::new(Scope {
+            let mut scope = Scope::new(
                 rx,
-                data: <_>::default(),
-                plots: <_>::default(),
-                plot_settings: <_>::default(),
-                colors: <_>::default(),
-                paused: false,
-                need_reset: false,
-                show_legend: !args.hide_legend,
-                time_window: args.time_window,
-                chart_cols: args.chart_cols,
-                aspect: args.chart_aspect,
-                sma_selected_plot: None,
-                sma_selected_metric: None,
-                sma_selected_value: String::new(),
-                trigger_selected_plot: None,
-                trigger_selected_metric: None,
-                trigger_selected_value_below: String::new(),
-                trigger_selected_value_above: String::new(),
-                range_selected_plot: None,
-                range_selected_value_min: String::new(),
-                range_selected_value_max: String::new(),
-                triggered: None,
-                sampling_interval_ns: Duration::from_secs_f64(args.sampling_interval)
-                    .as_nanos()
-                    .try_into()
-                    .unwrap(),
-                connected: false,
-                source: args.source.clone(),
-                predefined_smas: args.predefined_sma.to_sma_map(),
-                predefined_triggers: args.predefined_trigger.to_trigger_map(),
-                predefined_plots: args.predefined_y_range.to_plot_config_map(),
-            }))
+                args,
+                replay_speed,
+                reader_settings,
+                dashboard_config,
+                cli_config,
+            );
+            if let Some(custom_colors) = cc
+                .storage
+                .and_then(|storage| storage.get_string(CUSTOM_COLORS_KEY))
+            {
+                scope.custom_colors = parse_custom_colors(&custom_colors);
+            }
+            scope.selected_page = cc
+                .storage
+                .and_then(|storage| storage.get_string(SELECTED_PAGE_KEY))
+                .filter(|page| !page.is_empty());
+            scope.selected_tab = cc
+                .storage
+                .and_then(|storage| storage.get_string(SELECTED_TAB_KEY))
+                .filter(|tab| !tab.is_empty());
+            scope.config_watcher = args.config.as_ref().and_then(|path| {
+                config::ConfigWatcher::new(path, cc.egui_ctx.clone())
+                    .map_err(|e| eprintln!("Unable to watch the config file: {}", e))
+                    .ok()
+            });
+            Ok(Box::new(scope))
         }),
     )
     .expect("Failed to run UI");
+    if let Some(recording) = recording {
+        recording.flush();
+    }
+    #[cfg(feature = "parquet")]
+    if let Some(capture) = capture {
+        capture.flush().expect("Failed to write the capture file");
+    }
 }
 
 #[allow(clippy::struct_excessive_bools)]
@@ -157,17 +890,65 @@ struct Scope {
     rx: EventReceiver,
     data: BTreeMap<String, Vec<f64>>,
     plots: BTreeMap<String, BTreeSet<Arc<Metric>>>,
+    metric_plots: BTreeMap<String, String>,
+    plot_order: PlotOrder,
+    // plot name -> the order the plot has first appeared in
+    plot_indexes: BTreeMap<String, usize>,
+    next_plot_index: usize,
     plot_settings: BTreeMap<String, PlotSettings>,
     colors: BTreeMap<String, Color32>,
+    custom_colors: BTreeMap<String, Color32>,
+    scales: BTreeMap<String, u64>,
+    // metric units, described by the server
+    units: BTreeMap<String, String>,
+    // metric descriptions, described by the server
+    descriptions: BTreeMap<String, String>,
+    state_metrics: BTreeSet<String>,
+    indicators: BTreeMap<String, Indicator>,
+    plot_pages: BTreeMap<String, String>,
+    selected_page: Option<String>,
+    plot_groups: BTreeMap<String, String>,
+    selected_tab: Option<String>,
     paused: bool,
     need_reset: bool,
     show_legend: bool,
+    rich_legend: bool,
+    show_gaps: bool,
+    show_markers: bool,
+    color_seed: Option<u64>,
+    interpolation: Interpolation,
+    gap_color: Color32,
+    search: String,
+    focus_search: bool,
+    scroll_to_top: bool,
+    muted_metrics: BTreeSet<String>,
+    solo_metric: Option<String>,
     time_window: f32,
     chart_cols: f32,
     aspect: f32,
+    single_plot: bool,
+    filter: Option<regex::Regex>,
+    max_series: usize,
+    series_budget: SeriesBudget,
+    retain: Retain,
+    retain_memory: usize,
+    retain_capped: bool,
+    // adaptive sampling, the bandwidth limit and metric intervals omit metrics from snapshots,
+    // their last values are held
+    hold_missing: bool,
+    name_transform: Option<NameTransform>,
+    on_trigger: Option<TriggerCommand>,
+    trigger_hold: Option<Duration>,
+    // set when the chart updates are paused by a trigger
+    trigger_paused_at: Option<Instant>,
+    autopause_after: Option<Duration>,
+    // set once, when the first snapshot is received
+    first_data_at: Option<Instant>,
     sma_selected_plot: Option<String>,
     sma_selected_metric: Option<Arc<Metric>>,
     sma_selected_value: String,
+    rate_selected_plot: Option<String>,
+    rate_selected_metric: Option<Arc<Metric>>,
     trigger_selected_plot: Option<String>,
     trigger_selected_metric: Option<Arc<Metric>>,
     trigger_selected_value_below: String,
@@ -175,18 +956,98 @@ struct Scope {
     range_selected_plot: Option<String>,
     range_selected_value_min: String,
     range_selected_value_max: String,
+    color_selected_metric: Option<String>,
+    view_offset: f32,
     triggered: Option<Triggered>,
+    trigger_history: Vec<Triggered>,
+    timeline_events: VecDeque<TimelineEvent>,
+    status: Option<String>,
     sampling_interval_ns: u64,
+    reader_settings: Option<Arc<ReaderSettings>>,
+    replay_speed: Option<Arc<AtomicF64>>,
+    // the offline viewer playhead, the paused flag stops the playback
+    playback: Option<Playback>,
     connected: bool,
+    // the last reader error, displayed instead of the splash after the connect timeout
+    diagnostics: Option<client::Diagnostics>,
+    started_at: Instant,
+    // the server has truncated the metrics to the limit (sent, total)
+    truncated: Option<(usize, usize)>,
+    connect_timeout: Duration,
+    last_received: Instant,
+    stream_epoch: Option<SystemTime>,
     source: String,
+    // Some(None) - print to stdout
+    summary_on_exit: Option<Option<String>>,
     predefined_smas: BTreeMap<String, usize>,
+    predefined_rates: BTreeMap<String, Rate>,
     predefined_triggers: BTreeMap<String, TriggerConfig>,
     predefined_plots: BTreeMap<String, PlotConfig>,
+    predefined_sane_ranges: BTreeMap<String, SaneRange>,
+    predefined_colors: BTreeMap<String, Color32>,
+    config_watcher: Option<config::ConfigWatcher>,
+    config_path: Option<PathBuf>,
+    // the predefined settings from the command line, the config file is merged over them
+    cli_config: config::DashboardConfig,
+    // the last received metadata (raw metric name -> info), to reapply the reloaded config
+    metric_infos: BTreeMap<String, MetricInfo>,
+    // resolved sane ranges of metrics
+    sane_ranges: BTreeMap<String, SaneRange>,
+    // counters of rejected (out of the sane range) values
+    out_of_range: BTreeMap<String, usize>,
+    // the charts area of the last frame (in points), screenshots are cropped to it
+    charts_rect: Option<egui::Rect>,
+    screenshot_requested: bool,
+}
+
+/// The offline viewer playhead, the recording is loaded at once and browsed by moving it
+struct Playback {
+    // the recorded time (in seconds) of the time window right edge, set by the first frame with
+    // data
+    position: Option<f64>,
+    speed: f64,
+    // the playhead is moved by the time elapsed since the previous frame
+    ticked_at: Instant,
+}
+
+impl Playback {
+    fn new(speed: f64) -> Self {
+        Self {
+            position: None,
+            speed,
+            ticked_at: Instant::now(),
+        }
+    }
+    /// Moves the playhead if playing and returns its position, clamped to the range. If the
+    /// playback is started at the end of the loaded recording, it is restarted from the beginning
+    fn advance(&mut self, range: &RangeInclusive<f64>, playing: bool, loaded: bool) -> f64 {
+        let elapsed = self.ticked_at.elapsed();
+        self.ticked_at = Instant::now();
+        let position = self.position.get_or_insert(*range.start());
+        if playing {
+            if loaded && *position >= *range.end() {
+                *position = *range.start();
+            } else {
+                *position += elapsed.as_secs_f64() * self.speed;
+            }
+        }
+        *position = position.clamp(*range.start(), *range.end());
+        *position
+    }
 }
 
 struct PlotSettings {
     min_y: AtomicF64,
     max_y: AtomicF64,
+    span: AtomicUsize,
+    // 0 - the default one, otherwise Interpolation index + 1
+    interpolation: AtomicU8,
+    // frozen Y bounds of the auto-scale lock (NaN - unlocked)
+    locked_min_y: AtomicF64,
+    locked_max_y: AtomicF64,
+    // the bounds are captured from the plot state when the plot is drawn next time
+    y_lock_requested: AtomicBool,
+    log_y: AtomicBool,
 }
 
 impl PlotSettings {
@@ -194,8 +1055,74 @@ impl PlotSettings {
         Self {
             min_y: AtomicF64::new(f64::NAN),
             max_y: AtomicF64::new(f64::NAN),
+            span: AtomicUsize::new(1),
+            interpolation: AtomicU8::new(0),
+            locked_min_y: AtomicF64::new(f64::NAN),
+            locked_max_y: AtomicF64::new(f64::NAN),
+            y_lock_requested: AtomicBool::new(false),
+            log_y: AtomicBool::new(false),
+        }
+    }
+    fn is_y_locked(&self) -> bool {
+        self.y_lock_requested.load(Ordering::Relaxed) || self.get_locked_y().is_some()
+    }
+    fn lock_y(&self) {
+        self.y_lock_requested.store(true, Ordering::Relaxed);
+    }
+    fn unlock_y(&self) {
+        self.y_lock_requested.store(false, Ordering::Relaxed);
+        self.set_locked_y(None);
+    }
+    fn take_y_lock_request(&self) -> bool {
+        self.y_lock_requested.swap(false, Ordering::Relaxed)
+    }
+    fn get_locked_y(&self) -> Option<(f64, f64)> {
+        let min = self.locked_min_y.load(Ordering::Relaxed);
+        let max = self.locked_max_y.load(Ordering::Relaxed);
+        if min.is_nan() || max.is_nan() {
+            None
+        } else {
+            Some((min, max))
+        }
+    }
+    fn set_locked_y(&self, value: Option<(f64, f64)>) {
+        let (min, max) = value.unwrap_or((f64::NAN, f64::NAN));
+        self.locked_min_y.store(min, Ordering::Relaxed);
+        self.locked_max_y.store(max, Ordering::Relaxed);
+    }
+    fn get_interpolation(&self) -> Option<Interpolation> {
+        match self.interpolation.load(Ordering::Relaxed) {
+            0 => None,
+            n => Interpolation::value_variants()
+                .get(usize::from(n - 1))
+                .copied(),
         }
     }
+    fn set_interpolation(&self, value: Option<Interpolation>) {
+        let n = value.map_or(0, |value| {
+            Interpolation::value_variants()
+                .iter()
+                .position(|v| *v == value)
+                .and_then(|i| u8::try_from(i + 1).ok())
+                .unwrap_or_default()
+        });
+        self.interpolation.store(n, Ordering::Relaxed);
+    }
+    fn is_log_y(&self) -> bool {
+        self.log_y.load(Ordering::Relaxed)
+    }
+    fn set_log_y(&self, value: bool) {
+        // the locked bounds are in the plot coordinates of the previous scale
+        if self.log_y.swap(value, Ordering::Relaxed) != value {
+            self.unlock_y();
+        }
+    }
+    fn get_span(&self) -> usize {
+        self.span.load(Ordering::Relaxed)
+    }
+    fn set_span(&self, value: usize) {
+        self.span.store(value, Ordering::Relaxed);
+    }
     fn get_min_y(&self) -> Option<f64> {
         let val = self.min_y.load(Ordering::Relaxed);
         if val.is_nan() {
@@ -228,25 +1155,36 @@ impl PlotSettings {
     }
 }
 
+/// A received timeline event, the timestamp is in seconds, the same as the data ones
+struct TimelineEvent {
+    t: f64,
+    name: String,
+    label: String,
+}
+
+#[derive(Clone)]
 struct Triggered {
     at: f64,
     by: String,
     below_above: TriggeredKind,
+    value: f64,
 }
 
 impl Triggered {
-    fn below(at: f64, by: impl AsRef<str>) -> Self {
+    fn below(at: f64, by: impl AsRef<str>, value: f64) -> Self {
         Self {
             at,
             by: by.as_ref().to_owned(),
             below_above: TriggeredKind::Below,
+            value,
         }
     }
-    fn above(at: f64, by: impl AsRef<str>) -> Self {
+    fn above(at: f64, by: impl AsRef<str>, value: f64) -> Self {
         Self {
             at,
             by: by.as_ref().to_owned(),
             below_above: TriggeredKind::Above,
+            value,
         }
     }
 }
@@ -257,9 +1195,20 @@ enum TriggeredKind {
     Above,
 }
 
+impl TriggeredKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            TriggeredKind::Below => "below",
+            TriggeredKind::Above => "above",
+        }
+    }
+}
+
 struct Metric {
     name: String,
     sma_window: AtomicUsize,
+    // Rate index
+    rate: AtomicU8,
     trigger_below: AtomicF64,
     trigger_above: AtomicF64,
 }
@@ -269,6 +1218,7 @@ impl Metric {
         Self {
             name: name.to_owned(),
             sma_window: AtomicUsize::new(0),
+            rate: AtomicU8::new(0),
             trigger_below: AtomicF64::new(f64::NAN),
             trigger_above: AtomicF64::new(f64::NAN),
         }
@@ -279,6 +1229,20 @@ impl Metric {
     fn set_sma(&self, value: usize) {
         self.sma_window.store(value, Ordering::Relaxed);
     }
+    fn get_rate(&self) -> Rate {
+        Rate::value_variants()
+            .get(usize::from(self.rate.load(Ordering::Relaxed)))
+            .copied()
+            .unwrap_or(Rate::Off)
+    }
+    fn set_rate(&self, value: Rate) {
+        let n = Rate::value_variants()
+            .iter()
+            .position(|v| *v == value)
+            .and_then(|i| u8::try_from(i).ok())
+            .unwrap_or_default();
+        self.rate.store(n, Ordering::Relaxed);
+    }
     fn get_trigger_below(&self) -> Option<f64> {
         let val = self.trigger_below.load(Ordering::Relaxed);
         if val.is_nan() {
@@ -332,109 +1296,666 @@ impl PartialEq for Metric {
 }
 
 impl Scope {
+    /// Creates the scope state, the UI-stored settings (custom colors, the selected page and tab)
+    /// and the config watcher are set by the caller
+    fn new(
+        rx: EventReceiver,
+        args: &Args,
+        replay_speed: Arc<AtomicF64>,
+        reader_settings: Option<Arc<ReaderSettings>>,
+        dashboard_config: config::DashboardConfig,
+        cli_config: config::DashboardConfig,
+    ) -> Self {
+        Self {
+            rx,
+            data: <_>::default(),
+            plots: <_>::default(),
+            metric_plots: <_>::default(),
+            plot_order: args.plot_order,
+            plot_indexes: <_>::default(),
+            next_plot_index: 0,
+            plot_settings: <_>::default(),
+            colors: <_>::default(),
+            custom_colors: <_>::default(),
+            scales: <_>::default(),
+            units: <_>::default(),
+            descriptions: <_>::default(),
+            state_metrics: <_>::default(),
+            indicators: <_>::default(),
+            plot_pages: <_>::default(),
+            selected_page: None,
+            plot_groups: <_>::default(),
+            selected_tab: None,
+            // the offline viewer starts stopped at the beginning of the recording
+            paused: args.offline,
+            need_reset: false,
+            show_legend: !args.hide_legend,
+            rich_legend: args.rich_legend,
+            show_gaps: args.show_gaps,
+            show_markers: args.markers,
+            color_seed: args.color_seed,
+            interpolation: args.interpolation,
+            gap_color: args
+                .gap_color
+                .as_deref()
+                .and_then(|color| {
+                    let parsed = parse_color(color);
+                    if parsed.is_none() {
+                        eprintln!("Invalid gap color: {}", color);
+                    }
+                    parsed
+                })
+                .unwrap_or(GAP_COLOR),
+            search: String::new(),
+            focus_search: false,
+            scroll_to_top: false,
+            muted_metrics: <_>::default(),
+            solo_metric: None,
+            time_window: args.time_window,
+            chart_cols: args.chart_cols,
+            aspect: args.chart_aspect,
+            single_plot: args.single_plot,
+            filter: args.filter.clone(),
+            max_series: args.max_series,
+            series_budget: SeriesBudget::Ask,
+            // the offline viewer keeps the whole recording
+            retain: if args.offline {
+                Retain::All
+            } else {
+                args.retain
+            },
+            retain_memory: args.retain_memory.saturating_mul(1024 * 1024),
+            retain_capped: false,
+            hold_missing: args.adaptive_max_interval.is_some()
+                || args.bandwidth_limit.is_some()
+                || args.delta
+                || !args.metric_interval.is_empty(),
+            name_transform: args.name_transform.clone(),
+            on_trigger: args.on_trigger.clone(),
+            trigger_hold: (args.trigger_hold > 0.0)
+                .then(|| Duration::from_secs_f64(args.trigger_hold)),
+            trigger_paused_at: None,
+            autopause_after: args.autopause_after.map(Duration::from_secs_f64),
+            first_data_at: None,
+            sma_selected_plot: None,
+            sma_selected_metric: None,
+            sma_selected_value: String::new(),
+            rate_selected_plot: None,
+            rate_selected_metric: None,
+            trigger_selected_plot: None,
+            trigger_selected_metric: None,
+            trigger_selected_value_below: String::new(),
+            trigger_selected_value_above: String::new(),
+            range_selected_plot: None,
+            range_selected_value_min: String::new(),
+            range_selected_value_max: String::new(),
+            color_selected_metric: None,
+            view_offset: 0.0,
+            triggered: None,
+            trigger_history: Vec::new(),
+            timeline_events: VecDeque::new(),
+            status: None,
+            sampling_interval_ns: Duration::from_secs_f64(args.sampling_interval)
+                .as_nanos()
+                .try_into()
+                .unwrap(),
+            reader_settings,
+            playback: args
+                .offline
+                .then(|| Playback::new(replay_speed.load(Ordering::Relaxed))),
+            replay_speed: args.replay.then_some(replay_speed),
+            connected: false,
+            diagnostics: None,
+            truncated: None,
+            started_at: Instant::now(),
+            connect_timeout: Duration::from_secs_f64(args.connect_timeout),
+            last_received: Instant::now(),
+            stream_epoch: None,
+            source: args.source.clone(),
+            summary_on_exit: args.summary_on_exit.clone(),
+            config_watcher: None,
+            config_path: args.config.clone(),
+            cli_config,
+            metric_infos: <_>::default(),
+            predefined_smas: dashboard_config.smas,
+            predefined_rates: dashboard_config.rates,
+            predefined_triggers: dashboard_config.triggers,
+            predefined_plots: dashboard_config.plots,
+            predefined_sane_ranges: dashboard_config.sane_ranges,
+            predefined_colors: dashboard_config.colors,
+            sane_ranges: <_>::default(),
+            out_of_range: <_>::default(),
+            charts_rect: None,
+            screenshot_requested: false,
+        }
+    }
+    fn transform_name(&self, name: &str) -> String {
+        self.name_transform
+            .as_ref()
+            .map_or_else(|| name.to_owned(), |t| t.apply(name))
+    }
+    fn is_filtered_out(&self, raw_name: &str, name: &str) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(false, |f| !f.is_match(raw_name) && !f.is_match(name))
+    }
+    /// Metrics with the highest variance over the last points, up to the budget
+    fn top_variance_metrics(&self, points: usize) -> BTreeSet<String> {
+        let mut variances: Vec<(f64, &String)> = self
+            .data
+            .iter()
+            .filter(|(name, _)| !name.is_empty())
+            .map(|(name, values)| {
+                (
+                    variance(&values[values.len().saturating_sub(points)..]),
+                    name,
+                )
+            })
+            .collect();
+        variances.sort_by(|a, b| b.0.total_cmp(&a.0));
+        variances
+            .into_iter()
+            .take(self.max_series)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+    fn show_budget_warning(&mut self, ui: &mut Ui, series: usize) {
+        ui.label(
+            RichText::new(format!(
+                "{} metrics exceed the budget of {} (--max-series), rendering them all may make \
+                the UI unresponsive. Use --filter REGEX to select the required metrics.",
+                series, self.max_series
+            ))
+            .color(Color32::YELLOW),
+        );
+        ui.horizontal(|ui| {
+            if ui.button("Show all").clicked() {
+                self.series_budget = SeriesBudget::All;
+            }
+            if ui
+                .button(format!("Show top {} by variance", self.max_series))
+                .clicked()
+            {
+                self.series_budget = SeriesBudget::TopVariance;
+            }
+        });
+    }
     fn handle_event(&mut self, event: Event) {
         match event {
             Event::Connect => {
                 self.data.clear();
+                self.retain_capped = false;
                 //self.plots.clear();
                 self.colors.clear();
+                self.scales.clear();
+                self.out_of_range.clear();
+                self.timeline_events.clear();
                 self.connected = true;
+                self.diagnostics = None;
             }
             Event::Disconnect => {
                 self.connected = false;
+                // the loaded recording keeps its wall clock
+                if self.playback.is_none() {
+                    self.stream_epoch = None;
+                }
+            }
+            Event::Loaded(packet) => self.handle_event(Event::Packet(packet)),
+            Event::Diagnostics(diagnostics) => {
+                self.diagnostics = Some(diagnostics);
             }
             Event::Packet(Packet::Snapshot(mut snapshot)) => {
-                let max_time_window = Duration::from_secs_f32(MAX_TIME_WINDOW);
-                let max_data_ponts = usize::try_from(
-                    u64::try_from(max_time_window.as_nanos()).unwrap() / self.sampling_interval_ns,
-                )
-                .unwrap();
+                if self.first_data_at.is_none() {
+                    self.first_data_at = Some(Instant::now());
+                }
+                let max_data_ponts = self.max_data_points();
                 let ts_vec = self.data.entry(String::new()).or_default();
                 ts_vec.push(snapshot.ts().as_secs_f64());
                 if ts_vec.len() > max_data_ponts {
                     ts_vec.drain(0..(ts_vec.len() - max_data_ponts));
+                    if self.retain == Retain::All && !self.retain_capped {
+                        tracing::warn!(
+                            budget_mib = self.retain_memory / 1024 / 1024,
+                            "data retention memory budget reached, dropping the oldest samples"
+                        );
+                        self.status = Some("Memory budget reached, dropping old data".to_owned());
+                        self.retain_capped = true;
+                    }
+                }
+                if let Some(oldest) = ts_vec.first().copied() {
+                    while self
+                        .timeline_events
+                        .front()
+                        .map_or(false, |event| event.t < oldest)
+                    {
+                        self.timeline_events.pop_front();
+                    }
                 }
-                for (n, v) in snapshot.take_data() {
+                let mut received = BTreeSet::new();
+                for (raw_n, v) in snapshot.take_data() {
+                    let n = match self.name_transform {
+                        Some(ref t) => t.apply(&raw_n),
+                        None => raw_n.clone(),
+                    };
+                    if self.is_filtered_out(&raw_n, &n) {
+                        continue;
+                    }
+                    let mut v = v;
+                    if let Some(sane_range) = self.sane_ranges.get(&n) {
+                        // the range is set in displayed units
+                        #[allow(clippy::cast_precision_loss)]
+                        let displayed = self.scales.get(&n).map_or(v, |scale| v / *scale as f64);
+                        if !sane_range.contains(displayed) {
+                            *self.out_of_range.entry(n.clone()).or_default() += 1;
+                            v = f64::NAN;
+                        }
+                    }
+                    if self.hold_missing {
+                        received.insert(n.clone());
+                    }
                     let data_vec = self.data.entry(n).or_default();
                     data_vec.push(v);
                     if data_vec.len() > max_data_ponts {
                         data_vec.drain(0..(data_vec.len() - max_data_ponts));
                     }
                 }
-            }
-            Event::Packet(Packet::Info(info)) => {
-                for (name, m) in info.metrics() {
-                    let metric = Arc::new(Metric::new(name));
-                    let (plot, tag) = if let Some(plot) = m.labels().get("plot") {
-                        if self
-                            .plots
-                            .entry(plot.to_owned())
-                            .or_default()
-                            .insert(metric.clone())
-                        {
-                            (Some(plot.to_owned()), Some(format!("{}/{}", plot, name)))
-                        } else {
-                            (None, None)
+                if self.hold_missing {
+                    for (n, data_vec) in &mut self.data {
+                        if n.is_empty() || received.contains(n) {
+                            continue;
                         }
-                    } else if self
-                        .plots
-                        .entry(name.to_owned())
-                        .or_default()
-                        .insert(metric.clone())
-                    {
-                        (Some(name.to_owned()), Some(name.to_owned()))
-                    } else {
-                        (None, None)
-                    };
-                    if let Some(plot) = plot {
-                        let plot_settings =
-                            if let Some(plot_config) = self.predefined_plots.get(&plot) {
-                                let settings = PlotSettings::new();
-                                settings.set_min_y(plot_config.min);
-                                settings.set_max_y(plot_config.max);
-                                settings
-                            } else {
-                                PlotSettings::new()
-                            };
-                        self.plot_settings.insert(plot, plot_settings);
-                    }
-                    if let Some(tag) = tag {
-                        if let Some(sma) = self.predefined_smas.get(&tag) {
-                            metric.set_sma(*sma);
-                        }
-                        if let Some(triggers) = self.predefined_triggers.get(&tag) {
-                            if let Some(below) = triggers.below {
-                                metric.set_trigger_below(Some(below));
-                            }
-                            if let Some(above) = triggers.above {
-                                metric.set_trigger_above(Some(above));
-                            }
-                        }
-                    }
-                    if let Some(color) = m.labels().get("color") {
-                        if let Some(color) = parse_color(color) {
-                            self.colors.insert(name.to_owned(), color);
-                        } else {
-                            eprintln!("Invalid color: {}", color);
+                        data_vec.push(data_vec.last().copied().unwrap_or(f64::NAN));
+                        if data_vec.len() > max_data_ponts {
+                            data_vec.drain(0..(data_vec.len() - max_data_ponts));
                         }
                     }
                 }
             }
+            Event::Packet(Packet::Info(info)) => {
+                if let Some(stream_epoch) = info.stream_epoch() {
+                    self.stream_epoch = Some(stream_epoch);
+                }
+                self.truncated = info
+                    .truncated_from()
+                    .map(|total| (info.metrics().len(), total));
+                for (raw_name, m) in info.metrics() {
+                    self.apply_metric_info(raw_name, m);
+                }
+            }
+            Event::Packet(Packet::Event(event)) => {
+                self.timeline_events.push_back(TimelineEvent {
+                    t: event.ts().as_secs_f64(),
+                    name: event.name().to_owned(),
+                    label: event.label().to_owned(),
+                });
+                if self.timeline_events.len() > MAX_TIMELINE_EVENTS {
+                    self.timeline_events.pop_front();
+                }
+            }
+            Event::Packet(Packet::SnapshotBatch(batch)) => {
+                for snapshot in batch.into_snapshots() {
+                    self.handle_event(Event::Packet(Packet::Snapshot(snapshot)));
+                }
+            }
+            // keep-alive only, the connection is alive while packets are being read
+            Event::Packet(Packet::Ping(_)) => {}
+            Event::Packet(Packet::InfoDelta(delta)) => {
+                for (raw_name, m) in delta.metrics() {
+                    self.apply_metric_info(raw_name, m);
+                }
+                for raw_name in delta.removed() {
+                    self.metric_infos.remove(raw_name);
+                    let name = self.transform_name(raw_name);
+                    self.remove_metric(&name);
+                }
+            }
+        }
+    }
+
+    fn remove_from_plot(&mut self, plot: &str, metric: &Arc<Metric>) {
+        if let Some(metrics) = self.plots.get_mut(plot) {
+            metrics.remove(metric);
+            if metrics.is_empty() {
+                self.plots.remove(plot);
+                self.plot_indexes.remove(plot);
+                self.plot_settings.remove(plot);
+                self.plot_pages.remove(plot);
+                self.plot_groups.remove(plot);
+            }
+        }
+    }
+
+    fn remove_metric(&mut self, name: &str) {
+        if let Some(plot) = self.metric_plots.remove(name) {
+            self.remove_from_plot(&plot, &Arc::new(Metric::new(name)));
+        }
+        self.data.remove(name);
+        self.state_metrics.remove(name);
+        self.indicators.remove(name);
+        self.scales.remove(name);
+        self.units.remove(name);
+        self.descriptions.remove(name);
+        self.colors.remove(name);
+        self.sane_ranges.remove(name);
+        self.out_of_range.remove(name);
+    }
+
+    /// Applies the predefined SMA and triggers to a metric
+    fn apply_predefined_metric(&self, raw_name: &str, m: &MetricInfo, metric: &Metric) {
+        // predefined settings may refer either to the raw or to the transformed name
+        let (tag, raw_tag) = if let Some(plot) = m.labels().get("plot") {
+            (
+                format!("{}/{}", plot, metric.name),
+                format!("{}/{}", plot, raw_name),
+            )
+        } else {
+            (metric.name.clone(), raw_name.to_owned())
+        };
+        if let Some(sma) = self
+            .predefined_smas
+            .get(&tag)
+            .or_else(|| self.predefined_smas.get(&raw_tag))
+        {
+            metric.set_sma(*sma);
+        }
+        if let Some(rate) = self
+            .predefined_rates
+            .get(&tag)
+            .or_else(|| self.predefined_rates.get(&raw_tag))
+        {
+            metric.set_rate(*rate);
+        }
+        if let Some(triggers) = self
+            .predefined_triggers
+            .get(&tag)
+            .or_else(|| self.predefined_triggers.get(&raw_tag))
+        {
+            if let Some(below) = triggers.below {
+                metric.set_trigger_below(Some(below));
+            }
+            if let Some(above) = triggers.above {
+                metric.set_trigger_above(Some(above));
+            }
+        }
+    }
+
+    /// A sane range may be set for a metric (raw or transformed name) or for the whole plot
+    fn predefined_sane_range(
+        &self,
+        raw_name: &str,
+        name: &str,
+        m: &MetricInfo,
+    ) -> Option<SaneRange> {
+        let sane_range_keys = if let Some(plot) = m.labels().get("plot") {
+            vec![
+                format!("{}/{}", plot, name),
+                format!("{}/{}", plot, raw_name),
+                plot.to_owned(),
+            ]
+        } else {
+            vec![name.to_owned(), raw_name.to_owned()]
+        };
+        sane_range_keys
+            .iter()
+            .find_map(|key| self.predefined_sane_ranges.get(key))
+            .copied()
+    }
+
+    /// Reloads the config file and applies it to the existing plots and metrics, on errors the
+    /// previous config is kept
+    fn reload_config(&mut self) {
+        let Some(path) = self.config_path.as_ref() else {
+            return;
+        };
+        let config = match config::DashboardConfig::load(path) {
+            Ok(config) => self.cli_config.merged(&config),
+            Err(e) => {
+                self.status = Some(format!("Config error, the previous one is kept: {}", e));
+                return;
+            }
+        };
+        self.predefined_plots = config.plots;
+        self.predefined_smas = config.smas;
+        self.predefined_rates = config.rates;
+        self.predefined_triggers = config.triggers;
+        self.predefined_sane_ranges = config.sane_ranges;
+        self.predefined_colors = config.colors;
+        for (plot, plot_settings) in &self.plot_settings {
+            if let Some(plot_config) = self.predefined_plots.get(plot) {
+                plot_settings.set_min_y(plot_config.min);
+                plot_settings.set_max_y(plot_config.max);
+                plot_settings.set_log_y(plot_config.log);
+            }
+        }
+        let mut sane_ranges = BTreeMap::new();
+        for (raw_name, m) in &self.metric_infos {
+            let name = self.transform_name(raw_name);
+            let Some(metric) = self
+                .metric_plots
+                .get(&name)
+                .and_then(|plot| self.plots.get(plot))
+                .and_then(|metrics| metrics.get(&Metric::new(&name)))
+            else {
+                continue;
+            };
+            self.apply_predefined_metric(raw_name, m, metric);
+            if let Some(sane_range) = self.predefined_sane_range(raw_name, &name, m) {
+                sane_ranges.insert(name, sane_range);
+            }
+        }
+        self.sane_ranges = sane_ranges;
+        self.status = Some("Config reloaded".to_owned());
+    }
+
+    /// The metric color, chosen by the user, set in the config or by the program
+    fn metric_color(&self, name: &str) -> Option<Color32> {
+        self.custom_colors
+            .get(name)
+            .or_else(|| self.predefined_colors.get(name))
+            .or_else(|| self.colors.get(name))
+            .copied()
+    }
+
+    /// The automatic color of a metric: by the metric name if the color seed is set, otherwise by
+    /// the index
+    fn auto_metric_color(&self, name: &str, index: usize) -> Color32 {
+        self.color_seed
+            .map_or_else(|| auto_color(index), |seed| seeded_color(name, seed))
+    }
+
+    /// Applies metadata of a new or changed metric
+    fn apply_metric_info(&mut self, raw_name: &str, m: &MetricInfo) {
+        self.metric_infos.insert(raw_name.to_owned(), m.clone());
+        let name = &self.transform_name(raw_name);
+        if self.is_filtered_out(raw_name, name) {
+            return;
+        }
+        let metric = Arc::new(Metric::new(name));
+        let plot_name = m.labels().get("plot").unwrap_or(name);
+        if let Some(old_plot) = self
+            .metric_plots
+            .insert(name.to_owned(), plot_name.to_owned())
+            .filter(|old_plot| old_plot != plot_name)
+        {
+            // the metric has been moved to another plot, the settings are reapplied
+            // below as for a new one
+            self.remove_from_plot(&old_plot, &metric);
+        }
+        let plot = if let Some(plot) = m.labels().get("plot") {
+            if self
+                .plots
+                .entry(plot.to_owned())
+                .or_default()
+                .insert(metric.clone())
+            {
+                Some(plot.to_owned())
+            } else {
+                None
+            }
+        } else if self
+            .plots
+            .entry(name.to_owned())
+            .or_default()
+            .insert(metric.clone())
+        {
+            Some(name.to_owned())
+        } else {
+            None
+        };
+        if let Some(plot) = plot {
+            if !self.plot_indexes.contains_key(&plot) {
+                self.plot_indexes.insert(plot.clone(), self.next_plot_index);
+                self.next_plot_index += 1;
+            }
+            let plot_settings = if let Some(plot_config) = self.predefined_plots.get(&plot) {
+                let settings = PlotSettings::new();
+                settings.set_min_y(plot_config.min);
+                settings.set_max_y(plot_config.max);
+                settings.set_log_y(plot_config.log);
+                settings
+            } else {
+                PlotSettings::new()
+            };
+            self.plot_settings.insert(plot, plot_settings);
+            self.apply_predefined_metric(raw_name, m, &metric);
+        }
+        if let Some(sane_range) = self.predefined_sane_range(raw_name, name, m) {
+            self.sane_ranges.insert(name.to_owned(), sane_range);
+        } else {
+            self.sane_ranges.remove(name);
+        }
+        if m.render() == Some("state") {
+            self.state_metrics.insert(name.to_owned());
+        } else {
+            self.state_metrics.remove(name);
+        }
+        if let Some(indicator) = Indicator::from_render(m.render()) {
+            self.indicators.insert(name.to_owned(), indicator);
+        } else {
+            self.indicators.remove(name);
+        }
+        if let Some(scale) = m.scale() {
+            self.scales.insert(name.to_owned(), scale);
+        } else {
+            self.scales.remove(name);
+        }
+        if let Some(unit) = m.unit() {
+            self.units.insert(name.to_owned(), unit.to_owned());
+        } else {
+            self.units.remove(name);
+        }
+        if let Some(description) = m.description() {
+            self.descriptions
+                .insert(name.to_owned(), description.to_owned());
+        } else {
+            self.descriptions.remove(name);
+        }
+        if let Some(page) = m.labels().get("page") {
+            let plot = m.labels().get("plot").unwrap_or(name);
+            self.plot_pages.insert(plot.to_owned(), page.to_owned());
+        }
+        if let Some(group) = m.labels().get("group") {
+            let plot = m.labels().get("plot").unwrap_or(name);
+            self.plot_groups.insert(plot.to_owned(), group.to_owned());
+        }
+        if let Some(span) = m.labels().get("span") {
+            let plot = m.labels().get("plot").unwrap_or(name);
+            if let Some(plot_settings) = self.plot_settings.get(plot) {
+                if let Ok(span) = span.parse() {
+                    plot_settings.set_span(span);
+                } else {
+                    eprintln!("Invalid span: {}", span);
+                }
+            }
+        }
+        if let Some(interpolation) = m.labels().get("interpolation") {
+            let plot = m.labels().get("plot").unwrap_or(name);
+            if let Some(plot_settings) = self.plot_settings.get(plot) {
+                if let Ok(interpolation) = Interpolation::from_str(interpolation, true) {
+                    plot_settings.set_interpolation(Some(interpolation));
+                } else {
+                    eprintln!("Invalid interpolation: {}", interpolation);
+                }
+            }
+        } else if m.kind() == MetricKind::Counter {
+            // counters change in steps, unless the plot is set otherwise
+            let plot = m.labels().get("plot").unwrap_or(name);
+            if let Some(plot_settings) = self.plot_settings.get(plot) {
+                if plot_settings.get_interpolation().is_none() {
+                    plot_settings.set_interpolation(Some(Interpolation::Step));
+                }
+            }
+        }
+        if let Some(color) = m.labels().get("color") {
+            if let Some(color) = parse_color(color) {
+                self.colors.insert(name.to_owned(), color);
+            } else {
+                eprintln!("Invalid color: {}", color);
+            }
         }
     }
 
     fn process_global_keys(&mut self, ui: &mut Ui) {
-        if ui.input(|i| i.key_pressed(egui::Key::L)) {
-            self.show_legend = !self.show_legend;
+        if ui.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F)) {
+            self.focus_search = true;
         }
         if ui.input(|i| i.key_pressed(egui::Key::F5)) {
             self.need_reset = true;
             self.triggered = None;
         }
+        // letter shortcuts are typed into text fields instead
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::L)) {
+            self.show_legend = !self.show_legend;
+        }
         if ui.input(|i| i.key_pressed(egui::Key::P)) {
             self.paused = !self.paused;
             self.triggered = None;
         }
+        if ui.input(|i| i.key_pressed(egui::Key::E)) {
+            self.export_csv();
+        }
+        if ui.input(|i| i.key_pressed(egui::Key::S)) {
+            self.request_screenshot(ui.ctx());
+        }
+    }
+
+    fn show_search(&mut self, ui: &mut Ui) {
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut self.search)
+                .hint_text("Search (Ctrl+F)")
+                .desired_width(150.0),
+        );
+        if self.focus_search {
+            response.request_focus();
+            self.focus_search = false;
+        }
+        if response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.search.clear();
+            self.scroll_to_top = true;
+        }
+        if response.changed() {
+            self.scroll_to_top = true;
+        }
+        if !self.search.is_empty() && ui.small_button("✖").clicked() {
+            self.search.clear();
+            self.scroll_to_top = true;
+        }
+    }
+
+    /// Checks if the plot name or any of its metric names contain the search query (case
+    /// insensitive)
+    // metrics are ordered by their names, which are immutable
+    #[allow(clippy::mutable_key_type)]
+    fn matches_search(&self, plot: &str, metrics: &BTreeSet<Arc<Metric>>) -> bool {
+        let query = self.search.trim().to_lowercase();
+        query.is_empty()
+            || plot.to_lowercase().contains(&query)
+            || metrics
+                .iter()
+                .any(|m| m.name.to_lowercase().contains(&query))
     }
 
     fn show_sma_toolbar(&mut self, ui: &mut Ui) {
@@ -484,6 +2005,57 @@ impl Scope {
         ui.end_row();
     }
 
+    fn show_rate_toolbar(&mut self, ui: &mut Ui) {
+        egui::ComboBox::from_label("Rate")
+            .selected_text(self.rate_selected_plot.as_deref().unwrap_or("-"))
+            .show_ui(ui, |ui| {
+                if self.rate_selected_plot.is_some() && ui.selectable_label(false, "-").clicked() {
+                    self.rate_selected_plot = None;
+                    self.rate_selected_metric = None;
+                }
+                for plot in self.plots.keys() {
+                    if ui.selectable_label(false, plot).clicked() {
+                        self.rate_selected_plot = Some(plot.clone());
+                        self.rate_selected_metric = None;
+                    }
+                }
+            });
+        if let Some(plot) = self.rate_selected_plot.as_ref() {
+            if let Some(metrics) = self.plots.get(plot).as_mut() {
+                egui::ComboBox::from_label("Rate for metric")
+                    .selected_text(
+                        self.rate_selected_metric
+                            .as_ref()
+                            .map(|m| m.name.clone())
+                            .unwrap_or_default(),
+                    )
+                    .show_ui(ui, |ui| {
+                        for metric in *metrics {
+                            if ui.selectable_label(false, &metric.name).clicked() {
+                                self.rate_selected_metric = Some(metric.clone());
+                            }
+                        }
+                    });
+            }
+            if let Some(metric) = self.rate_selected_metric.as_ref() {
+                let current = metric.get_rate();
+                egui::ComboBox::from_id_source("rate_mode")
+                    .selected_text(rate_name(current))
+                    .show_ui(ui, |ui| {
+                        for rate in Rate::value_variants() {
+                            if ui
+                                .selectable_label(current == *rate, rate_name(*rate))
+                                .clicked()
+                            {
+                                metric.set_rate(*rate);
+                            }
+                        }
+                    });
+            }
+        }
+        ui.end_row();
+    }
+
     fn show_trigger_toolbar(&mut self, ui: &mut Ui) {
         egui::ComboBox::from_label("Trigger")
             .selected_text(self.trigger_selected_plot.as_deref().unwrap_or("-"))
@@ -615,10 +2187,248 @@ impl Scope {
                     .unwrap()
                     .set_max_y(self.range_selected_value_max.parse().ok());
             }
+            let plot_settings = self.plot_settings.get(plot).unwrap();
+            let mut log_y = plot_settings.is_log_y();
+            if ui
+                .checkbox(&mut log_y, "Log Y")
+                .on_hover_text("Logarithmic Y-axis, non-positive values are not displayed")
+                .changed()
+            {
+                plot_settings.set_log_y(log_y);
+            }
+            let mut y_locked = plot_settings.is_y_locked();
+            if ui
+                .checkbox(&mut y_locked, "Lock Y")
+                .on_hover_text("Freeze the current Y auto-scale bounds until unlocked or reset")
+                .changed()
+            {
+                if y_locked {
+                    plot_settings.lock_y();
+                } else {
+                    plot_settings.unlock_y();
+                }
+            }
+            let current = plot_settings.get_interpolation();
+            egui::ComboBox::from_label("Interpolation")
+                .selected_text(current.map_or("default", interpolation_name))
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(current.is_none(), "default").clicked() {
+                        plot_settings.set_interpolation(None);
+                    }
+                    for interpolation in Interpolation::value_variants() {
+                        if ui
+                            .selectable_label(
+                                current == Some(*interpolation),
+                                interpolation_name(*interpolation),
+                            )
+                            .clicked()
+                        {
+                            plot_settings.set_interpolation(Some(*interpolation));
+                        }
+                    }
+                });
+        }
+        ui.end_row();
+    }
+
+    fn show_color_toolbar(&mut self, ui: &mut Ui) {
+        egui::ComboBox::from_label("Color")
+            .selected_text(self.color_selected_metric.as_deref().unwrap_or("-"))
+            .show_ui(ui, |ui| {
+                if self.color_selected_metric.is_some() && ui.selectable_label(false, "-").clicked()
+                {
+                    self.color_selected_metric = None;
+                }
+                for metric in self.plots.values().flatten() {
+                    if ui.selectable_label(false, &metric.name).clicked() {
+                        self.color_selected_metric = Some(metric.name.clone());
+                    }
+                }
+            });
+        if let Some(name) = self.color_selected_metric.as_ref() {
+            let mut color = self
+                .metric_color(name)
+                .unwrap_or_else(|| ui.visuals().text_color());
+            if egui::color_picker::color_edit_button_srgba(
+                ui,
+                &mut color,
+                egui::color_picker::Alpha::Opaque,
+            )
+            .changed()
+            {
+                self.custom_colors.insert(name.clone(), color);
+            }
+            if self.custom_colors.contains_key(name) && ui.button("Reset").clicked() {
+                self.custom_colors.remove(name);
+            }
         }
         ui.end_row();
     }
 
+    /// Max number of data points kept per metric, according to the retention mode
+    fn max_data_points(&self) -> usize {
+        match self.retain {
+            Retain::Rolling => usize::try_from(
+                u64::try_from(Duration::from_secs_f32(MAX_TIME_WINDOW).as_nanos()).unwrap()
+                    / self.sampling_interval_ns,
+            )
+            .unwrap(),
+            Retain::All => {
+                self.retain_memory / (std::mem::size_of::<f64>() * cmp::max(self.data.len(), 1))
+            }
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn show_scrub_bar(&mut self, ui: &mut Ui, buffered_points: usize, data_points: usize) {
+        let max_data_points = self.max_data_points();
+        let (rect, response) =
+            ui.allocate_exact_size(egui::vec2(ui.available_width(), 12.0), egui::Sense::drag());
+        let painter = ui.painter_at(rect);
+        let px_per_point = rect.width() / cmp::max(max_data_points, 1) as f32;
+        painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(
+                    rect.right() - buffered_points as f32 * px_per_point,
+                    rect.top(),
+                ),
+                rect.right_bottom(),
+            ),
+            2.0,
+            ui.visuals().widgets.inactive.bg_fill,
+        );
+        let window_right = rect.right() - self.view_offset * px_per_point;
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(window_right - data_points as f32 * px_per_point, rect.top()),
+                egui::pos2(window_right, rect.bottom()),
+            ),
+            2.0,
+            ui.visuals().selection.bg_fill,
+        );
+        if response.dragged() && px_per_point > 0.0 {
+            self.paused = true;
+            // the user takes over, no auto-resume
+            self.trigger_paused_at = None;
+            let max_offset = buffered_points.saturating_sub(data_points) as f32;
+            self.view_offset =
+                (self.view_offset - response.drag_delta().x / px_per_point).clamp(0.0, max_offset);
+        }
+    }
+
+    /// The offline viewer seek bar over the whole recording, the playhead is the time window right
+    /// edge
+    fn show_seek_bar(&mut self, ui: &mut Ui, range: RangeInclusive<f64>) {
+        let Some(position) = self
+            .playback
+            .as_mut()
+            .and_then(|playback| playback.position.as_mut())
+        else {
+            return;
+        };
+        let stream_epoch = self.stream_epoch;
+        ui.spacing_mut().slider_width = (ui.available_width() - SEEK_BAR_LABEL_WIDTH).max(0.0);
+        if ui
+            .add(
+                egui::Slider::new(position, range)
+                    .custom_formatter(move |value, _| format_recorded_time(value, stream_epoch)),
+            )
+            .changed()
+        {
+            // triggers are checked again at the new position
+            self.triggered = None;
+            self.trigger_paused_at = None;
+        }
+    }
+
+    /// Writes the data summary to stdout or to a file
+    fn write_summary(&self, path: Option<&str>) -> std::io::Result<()> {
+        if let Some(path) = path {
+            export::export_summary(path, &self.source, &self.data)
+        } else {
+            export::write_summary(std::io::stdout().lock(), &self.source, &self.data)
+        }
+    }
+
+    fn export_summary(&mut self) {
+        let path = format!(
+            "metrics-scope-{}-summary.txt",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        );
+        self.status = Some(match self.write_summary(Some(path.as_str())) {
+            Ok(()) => format!("Summary written to {}", path),
+            Err(e) => format!("Summary failed: {}", e),
+        });
+    }
+
+    fn export_csv(&mut self) {
+        let path = format!(
+            "metrics-scope-{}.csv",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        );
+        self.status = Some(
+            match export::export_csv(&path, &self.source, &self.data, &self.trigger_history) {
+                Ok(()) => format!("Exported to {}", path),
+                Err(e) => format!("Export failed: {}", e),
+            },
+        );
+    }
+
+    /// The screenshot is delivered with the input events of one of the next frames
+    fn request_screenshot(&mut self, ctx: &egui::Context) {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Screenshot);
+        self.screenshot_requested = true;
+    }
+
+    fn save_screenshot(&mut self, image: &egui::ColorImage, pixels_per_point: f32) {
+        if !self.screenshot_requested {
+            return;
+        }
+        self.screenshot_requested = false;
+        let image = match self.charts_rect {
+            Some(rect) => export::crop_screenshot(image, rect, pixels_per_point),
+            None => image.clone(),
+        };
+        let path = format!(
+            "metrics-scope-{}.png",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        );
+        self.status = Some(match export::export_png(&path, &image, pixels_per_point) {
+            Ok(()) => format!("Screenshot saved to {}", path),
+            Err(e) => format!("Screenshot failed: {}", e),
+        });
+    }
+
+    fn show_diagnostics(&self, ui: &mut Ui, diagnostics: &client::Diagnostics) {
+        ui.heading(format!("Unable to read data from {}", self.source));
+        ui.label(format!("Last error: {}", diagnostics.error));
+        if let Some(addr) = diagnostics.resolved_addr {
+            ui.label(format!("Resolved address: {}", addr));
+        }
+        if let Some(version) = diagnostics.server_version {
+            ui.label(format!(
+                "Server protocol version: {} (supported: {}-{})",
+                version,
+                protocol::MIN_VERSION,
+                protocol::VERSION
+            ));
+        }
+        ui.label(if diagnostics.fatal {
+            "The error is fatal, not retrying"
+        } else {
+            "Retrying..."
+        });
+    }
     fn show_common_controls(&mut self, ui: &mut Ui) {
         ui.add(
             egui::Slider::new(&mut self.time_window, 1.0..=MAX_TIME_WINDOW)
@@ -627,12 +2437,31 @@ impl Scope {
                 .integer()
                 .logarithmic(true),
         );
+        self.show_search(ui);
         ui.checkbox(&mut self.show_legend, "Legend (L)");
+        ui.checkbox(&mut self.rich_legend, "Rich legend");
+        ui.checkbox(&mut self.show_gaps, "Gaps");
+        ui.checkbox(&mut self.show_markers, "Markers");
         if ui.add(Button::new("Reset (F5)")).clicked() {
             self.need_reset = true;
             self.triggered = None;
         }
-        if self.paused {
+        if let Some(ref mut playback) = self.playback {
+            if self.paused {
+                if ui.add(Button::new("Play (P)")).clicked() {
+                    self.paused = false;
+                    self.triggered = None;
+                }
+            } else if ui.add(Button::new("Pause (P)")).clicked() {
+                self.paused = true;
+                self.triggered = None;
+            }
+            ui.add(
+                egui::Slider::new(&mut playback.speed, REPLAY_SPEED_RANGE)
+                    .text("Speed")
+                    .logarithmic(true),
+            );
+        } else if self.paused {
             if ui.add(Button::new("Resume (P)")).clicked() {
                 self.paused = false;
                 self.triggered = None;
@@ -641,6 +2470,88 @@ impl Scope {
             self.paused = true;
             self.triggered = None;
         }
+        let pages: BTreeSet<&String> = self.plot_pages.values().collect();
+        if !pages.is_empty() {
+            egui::ComboBox::from_label("Page")
+                .selected_text(self.selected_page.as_deref().unwrap_or("all"))
+                .show_ui(ui, |ui| {
+                    if ui.selectable_label(false, "all").clicked() {
+                        self.selected_page = None;
+                    }
+                    for page in pages {
+                        if ui.selectable_label(false, page).clicked() {
+                            self.selected_page = Some(page.clone());
+                        }
+                    }
+                });
+        }
+        if let Some(ref reader_settings) = self.reader_settings {
+            #[allow(clippy::cast_precision_loss)]
+            let current_ms = self.sampling_interval_ns as f64 / 1_000_000.0;
+            egui::ComboBox::from_label("Interval")
+                .selected_text(format!("{} ms", current_ms))
+                .show_ui(ui, |ui| {
+                    for ms in SAMPLING_INTERVAL_PRESETS {
+                        let interval = Duration::from_millis(ms);
+                        let interval_ns = u64::try_from(interval.as_nanos()).unwrap();
+                        if ui
+                            .selectable_label(
+                                interval_ns == self.sampling_interval_ns,
+                                format!("{} ms", ms),
+                            )
+                            .clicked()
+                            && interval_ns != self.sampling_interval_ns
+                        {
+                            // the data buffer is cleared on reconnect
+                            reader_settings.set_sampling_interval(interval);
+                            self.sampling_interval_ns = interval_ns;
+                        }
+                    }
+                });
+        }
+        if let Some(ref replay_speed) = self.replay_speed {
+            let mut speed = replay_speed.load(Ordering::Relaxed);
+            if ui
+                .add(
+                    egui::Slider::new(&mut speed, REPLAY_SPEED_RANGE)
+                        .text("Speed")
+                        .logarithmic(true),
+                )
+                .changed()
+            {
+                replay_speed.store(speed, Ordering::Relaxed);
+            }
+        }
+        if ui
+            .add(Button::new("Export"))
+            .on_hover_text("Write the buffered data to a CSV file (E)")
+            .clicked()
+        {
+            self.export_csv();
+        }
+        if ui
+            .add(Button::new("Screenshot"))
+            .on_hover_text("Save the charts as a PNG image (S)")
+            .clicked()
+        {
+            self.request_screenshot(ui.ctx());
+        }
+        if ui
+            .add(Button::new("Summary"))
+            .on_hover_text("Write per-metric statistics of the buffered data to a file")
+            .clicked()
+        {
+            self.export_summary();
+        }
+        if let Some((sent, total)) = self.truncated {
+            ui.label(
+                RichText::new(format!("truncated to {} metrics (of {})", sent, total))
+                    .color(Color32::YELLOW),
+            );
+        }
+        if let Some(ref status) = self.status {
+            ui.label(status);
+        }
         ui.end_row();
         ui.add(
             egui::Slider::new(&mut self.chart_cols, 1.0..=10.0)
@@ -656,182 +2567,848 @@ impl Scope {
     }
 
     #[allow(clippy::too_many_lines, clippy::cast_precision_loss)]
-    fn show_charts(&mut self, ui: &mut Ui, ts_vec: Vec<f64>, data_points: usize) {
-        let chart_width = ui.available_width() / self.chart_cols - 10.0;
-        let plots: Vec<_> = self.plots.iter().filter(|(_, v)| !v.is_empty()).collect();
-        let mut ts_vec_axis = vec![];
-        for i in (0..data_points).rev() {
-            ts_vec_axis.push(-(i as f64 * self.sampling_interval_ns as f64 / 1_000_000_000.0));
+    /// Tabs, built from plot groups, the ungrouped plots are put into [`UNGROUPED_TAB`]
+    fn tabs(&self) -> Vec<&str> {
+        if self.plot_groups.is_empty() {
+            return Vec::new();
+        }
+        let mut tabs: Vec<&str> = self
+            .plot_groups
+            .values()
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if self
+            .plots
+            .keys()
+            .any(|plot| !self.plot_groups.contains_key(plot))
+        {
+            tabs.push(UNGROUPED_TAB);
+        }
+        tabs
+    }
+
+    /// The selected tab or the first one if the selected tab does not exist (yet)
+    fn active_tab(&self) -> Option<String> {
+        let tabs = self.tabs();
+        self.selected_tab
+            .as_deref()
+            .filter(|tab| tabs.contains(tab))
+            .or_else(|| tabs.first().copied())
+            .map(ToOwned::to_owned)
+    }
+
+    fn show_tabs(&mut self, ui: &mut Ui) {
+        let tabs = self.tabs();
+        if tabs.is_empty() {
+            return;
+        }
+        let active_tab = self.active_tab();
+        let mut selected = None;
+        ui.horizontal(|ui| {
+            for tab in tabs {
+                if ui
+                    .selectable_label(active_tab.as_deref() == Some(tab), tab)
+                    .clicked()
+                {
+                    selected = Some(tab.to_owned());
+                }
+            }
+        });
+        if selected.is_some() {
+            self.selected_tab = selected;
+        }
+        ui.separator();
+    }
+
+    /// Draws timeline events as markers, one lane per event name, time-aligned with the charts.
+    /// Returns `true` if the lane is hovered
+    fn show_event_lane(&self, ui: &mut Ui, ts_vec: &[f64], width: f32) -> bool {
+        let (Some(first_ts), Some(last_ts)) = (ts_vec.first().copied(), ts_vec.last().copied())
+        else {
+            return false;
+        };
+        let visible: Vec<&TimelineEvent> = self
+            .timeline_events
+            .iter()
+            .filter(|event| event.t >= first_ts && event.t <= last_ts)
+            .collect();
+        let lanes: Vec<&str> = self
+            .timeline_events
+            .iter()
+            .map(|event| event.name.as_str())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if lanes.is_empty() {
+            return false;
+        }
+        // the offline viewer shows the recorded time
+        let origin = if self.playback.is_some() {
+            0.0
+        } else {
+            last_ts
+        };
+        let x_min = first_ts - origin;
+        #[allow(clippy::cast_precision_loss)]
+        let mut lane_plot = Plot::new("timeline_events")
+            .height(EVENT_LANE_HEIGHT * (lanes.len() + 1) as f32)
+            .width(width)
+            .show_axes([true, false])
+            .show_grid([true, false])
+            .include_x(x_min)
+            .include_x(last_ts - origin)
+            .include_y(0.5)
+            .include_y(-(lanes.len() as f64) + 0.5)
+            .allow_zoom(false)
+            .allow_scroll(false)
+            .link_axis("scope", true, false)
+            .link_cursor("scope", true, false)
+            .label_formatter(|name, value| {
+                if name.is_empty() {
+                    String::new()
+                } else {
+                    format!("t={}\n{}", value.x, name)
+                }
+            });
+        if self.need_reset {
+            lane_plot = lane_plot.reset();
+        }
+        lane_plot
+            .show(ui, |plot_ui| {
+                for (i, lane) in lanes.iter().enumerate() {
+                    #[allow(clippy::cast_precision_loss)]
+                    let y = -(i as f64);
+                    plot_ui.text(
+                        Text::new(PlotPoint::new(x_min, y), *lane)
+                            .anchor(egui::Align2::LEFT_BOTTOM)
+                            .color(Color32::GRAY),
+                    );
+                    let color = auto_color(i);
+                    for event in visible.iter().filter(|event| event.name == *lane) {
+                        plot_ui.points(
+                            Points::new(vec![[event.t - origin, y]])
+                                .shape(MarkerShape::Diamond)
+                                .filled(true)
+                                .radius(5.0)
+                                .color(color)
+                                .name(format!("{}: {}", event.name, event.label)),
+                        );
+                    }
+                }
+            })
+            .response
+            .hovered()
+    }
+
+    /// Draws single-value metrics as bars or dials. The range is taken from the Y-range of the
+    /// metric plot, a missing min is 0, a missing max is the max value of the displayed window
+    fn show_indicators(
+        &self,
+        ui: &mut Ui,
+        indicators: &[(String, String)],
+        data_points: usize,
+        view_offset: usize,
+    ) {
+        let default_plot_settings = PlotSettings::new();
+        ui.horizontal_wrapped(|ui| {
+            for (i, (plot, name)) in indicators.iter().enumerate() {
+                let Some(indicator) = self.indicators.get(name) else {
+                    continue;
+                };
+                let plot_settings = self
+                    .plot_settings
+                    .get(plot)
+                    .unwrap_or(&default_plot_settings);
+                let scale = self.scales.get(name).copied();
+                #[allow(clippy::cast_precision_loss)]
+                let displayed = |v: f64| scale.map_or(v, |scale| v / scale as f64);
+                let data = self.data.get(name).map_or(&[][..], |d| {
+                    let d = &d[..d.len().saturating_sub(view_offset)];
+                    &d[d.len().saturating_sub(data_points)..]
+                });
+                let last = data.iter().rev().find(|v| !v.is_nan()).copied();
+                let min = plot_settings.get_min_y().unwrap_or(0.0);
+                let max = plot_settings.get_max_y().unwrap_or_else(|| {
+                    data.iter()
+                        .filter(|v| !v.is_nan())
+                        .map(|v| displayed(*v))
+                        .fold(min, f64::max)
+                });
+                #[allow(clippy::cast_possible_truncation)]
+                let fraction = last.map_or(0.0, |v| {
+                    if max > min {
+                        ((displayed(v) - min) / (max - min)).clamp(0.0, 1.0) as f32
+                    } else {
+                        0.0
+                    }
+                });
+                let color = self
+                    .metric_color(name)
+                    .unwrap_or_else(|| self.auto_metric_color(name, i));
+                let text = format!(
+                    "{}={}",
+                    name,
+                    last.map_or_else(|| "-".to_owned(), |v| format_value(v, scale))
+                );
+                ui.vertical(|ui| {
+                    ui.set_width(INDICATOR_WIDTH);
+                    match indicator {
+                        Indicator::Bar => {
+                            ui.label(text);
+                            ui.add(
+                                egui::ProgressBar::new(fraction)
+                                    .fill(color)
+                                    .desired_width(INDICATOR_WIDTH),
+                            );
+                        }
+                        Indicator::Dial => {
+                            show_dial(ui, fraction, color);
+                            ui.label(text);
+                        }
+                    }
+                    ui.label(RichText::new(format!("{}..{}", min, max)).small());
+                });
+            }
+        });
+        ui.separator();
+    }
+
+    fn show_charts(
+        &mut self,
+        ui: &mut Ui,
+        ts_vec: Vec<f64>,
+        data_points: usize,
+        view_offset: usize,
+    ) {
+        let chart_cols = if self.single_plot {
+            1.0
+        } else {
+            self.chart_cols
+        };
+        let col_width = ui.available_width() / chart_cols;
+        let series = self.metric_plots.len();
+        let allowed = if series > self.max_series {
+            match self.series_budget {
+                SeriesBudget::Ask => {
+                    self.show_budget_warning(ui, series);
+                    return;
+                }
+                SeriesBudget::All => None,
+                SeriesBudget::TopVariance => Some(self.top_variance_metrics(data_points)),
+            }
+        } else {
+            None
+        };
+        // indicators are drawn in the dedicated panel
+        let is_allowed = |name: &String| {
+            allowed
+                .as_ref()
+                .map_or(true, |allowed| allowed.contains(name))
+                && !self.indicators.contains_key(name)
+        };
+        let was_triggered = self.triggered.is_some();
+        let active_tab = self.active_tab();
+        let merged: (String, BTreeSet<Arc<Metric>>);
+        let mut plots: Vec<_> = if self.single_plot {
+            merged = (
+                SINGLE_PLOT_NAME.to_owned(),
+                self.plots.values().flatten().cloned().collect(),
+            );
+            vec![(&merged.0, &merged.1)]
+        } else {
+            self.plots
+                .iter()
+                .filter(|(plot, v)| {
+                    !v.is_empty()
+                        && self
+                            .selected_page
+                            .as_ref()
+                            .map_or(true, |page| self.plot_pages.get(*plot) == Some(page))
+                        && active_tab.as_ref().map_or(true, |tab| {
+                            self.plot_groups
+                                .get(*plot)
+                                .map_or(UNGROUPED_TAB, String::as_str)
+                                == tab
+                        })
+                })
+                .collect()
+        };
+        if self.plot_order == PlotOrder::FirstSeen {
+            plots.sort_by_key(|(plot, _)| {
+                self.plot_indexes.get(*plot).copied().unwrap_or(usize::MAX)
+            });
         }
+        let indicators: Vec<(String, String)> = plots
+            .iter()
+            .flat_map(|(plot, metrics)| {
+                metrics
+                    .iter()
+                    .filter(|m| self.indicators.contains_key(&m.name))
+                    .map(|m| ((*plot).clone(), m.name.clone()))
+            })
+            .collect();
+        if !indicators.is_empty() {
+            self.show_indicators(ui, &indicators, data_points, view_offset);
+        }
+        plots.retain(|(plot, metrics)| {
+            metrics.iter().any(|m| is_allowed(&m.name)) && self.matches_search(plot, metrics)
+        });
+        // the first match is on top
+        if self.scroll_to_top {
+            ui.scroll_to_cursor(Some(egui::Align::TOP));
+            self.scroll_to_top = false;
+        }
+        let default_plot_settings = PlotSettings::new();
+        let mut ts_vec_axis = vec![];
+        // the offline viewer shows the recorded time, the live one - the time before the last
+        // sample
+        let axis_origin = if self.playback.is_some() {
+            ts_vec_axis.extend(&ts_vec);
+            Some(0.0)
+        } else {
+            for i in (0..data_points).rev() {
+                ts_vec_axis.push(-(i as f64 * self.sampling_interval_ns as f64 / 1_000_000_000.0));
+            }
+            ts_vec.last().copied()
+        };
         #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-        for plot_chunk in plots.chunks(self.chart_cols as usize) {
+        let max_span = cmp::max(chart_cols as usize, 1);
+        let mut rows: Vec<Vec<_>> = Vec::new();
+        let mut row_span = 0;
+        for (plot, metrics) in plots {
+            let span = self
+                .plot_settings
+                .get(plot)
+                .map_or(1, PlotSettings::get_span)
+                .clamp(1, max_span);
+            if rows.is_empty() || row_span + span > max_span {
+                rows.push(Vec::new());
+                row_span = 0;
+            }
+            // the colors are resolved in advance, as the chart closures update the scope state
+            let colors: Vec<Option<Color32>> = metrics
+                .iter()
+                .filter(|m| is_allowed(&m.name))
+                .enumerate()
+                .map(|(i, metric)| {
+                    self.metric_color(&metric.name)
+                        // markers are colored like the lines, so the color is assigned explicitly
+                        .or_else(|| {
+                            (self.rich_legend || self.show_markers || self.color_seed.is_some())
+                                .then(|| self.auto_metric_color(&metric.name, i))
+                        })
+                })
+                .collect();
+            rows.last_mut().unwrap().push((plot, metrics, span, colors));
+            row_span += span;
+        }
+        let mut chart_hovered = false;
+        for row in rows {
             ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
-                for (plot, metrics) in plot_chunk {
+                for (plot, metrics, span, colors) in &row {
+                    #[allow(clippy::cast_precision_loss)]
+                    let span = *span as f32;
                     let mut plot_name = String::new();
-                    for metric in *metrics {
+                    for metric in metrics.iter().filter(|m| is_allowed(&m.name)) {
                         if plot_name.is_empty() && metric.name != **plot {
                             plot_name.push_str(&format!("{} ", plot));
                         }
                         if let Some(data) = self.data.get(&metric.name) {
                             if let Some(last) = data.last() {
-                                plot_name.push_str(&format!("{}={} ", metric.name, last));
+                                plot_name.push_str(&format!(
+                                    "{}={}{} ",
+                                    metric.name,
+                                    format_value(*last, self.scales.get(&metric.name).copied()),
+                                    self.units
+                                        .get(&metric.name)
+                                        .map(|unit| format!(" {}", unit))
+                                        .unwrap_or_default()
+                                ));
                             }
                         }
                     }
+                    let wall_clock_base = self.stream_epoch.zip(axis_origin);
+                    let plot_units: BTreeMap<String, String> = metrics
+                        .iter()
+                        .filter_map(|m| {
+                            self.units
+                                .get(&m.name)
+                                .map(|unit| (m.name.clone(), unit.clone()))
+                        })
+                        .collect();
+                    // the Y axis is labeled if all the plot metrics share the unit
+                    let common_unit = plot_units
+                        .values()
+                        .next()
+                        .filter(|unit| {
+                            plot_units.len() == metrics.len()
+                                && plot_units.values().all(|u| u == *unit)
+                        })
+                        .cloned();
+                    let is_log_y = self
+                        .plot_settings
+                        .get(*plot)
+                        .map_or(false, PlotSettings::is_log_y);
+                    // values and Y-ranges are converted to the plot coordinates
+                    let to_plot_y = |value: f64| if is_log_y { log_y(value) } else { value };
                     let mut chart_plot = Plot::new(plot)
-                        .view_aspect(self.aspect)
+                        .view_aspect(self.aspect * span)
                         .x_axis_label(plot_name)
-                        .label_formatter(|name, value| {
-                            if name.is_empty() {
-                                format!("t={}\n{}", value.x, value.y)
+                        .label_formatter(move |name, value| {
+                            let y = if is_log_y {
+                                10f64.powf(value.y)
                             } else {
-                                format!("t={}\n{}={}", value.x, name, value.y)
+                                value.y
+                            };
+                            let mut label = if name.is_empty() {
+                                format!("t={}\n{}", value.x, y)
+                            } else if let Some(unit) = plot_units.get(name) {
+                                format!("t={}\n{}={} {}", value.x, name, y, unit)
+                            } else {
+                                format!("t={}\n{}={}", value.x, name, y)
+                            };
+                            if let Some((epoch, origin)) = wall_clock_base {
+                                if let Some(at) = epoch.checked_add(Duration::from_secs_f64(
+                                    (origin + value.x).max(0.0),
+                                )) {
+                                    label.push_str(&format!("\n@ {}", format_wall_clock(at)));
+                                }
                             }
+                            label
                         })
-                        .width(chart_width)
+                        .width(col_width * span - 10.0)
+                        // Ctrl + mouse wheel changes the time window instead
+                        .allow_zoom(false)
                         .link_axis("scope", true, false)
                         .link_cursor("scope", true, false);
                     if self.need_reset {
                         chart_plot = chart_plot.reset();
                     }
-                    if self.show_legend {
+                    if let Some(unit) = common_unit {
+                        chart_plot = chart_plot.y_axis_label(unit);
+                    }
+                    if is_log_y {
+                        chart_plot = chart_plot
+                            .y_axis_formatter(|mark, _| format!("{}", 10f64.powf(mark.value)));
+                    }
+                    if self.show_legend && !self.rich_legend {
                         let legend = Legend::default();
                         chart_plot = chart_plot.legend(legend);
                     };
-                    let plot_settings = self.plot_settings.get(*plot).unwrap();
-                    if let Some(min_y) = plot_settings.get_min_y() {
-                        chart_plot = chart_plot.include_y(min_y);
+                    let plot_settings = self
+                        .plot_settings
+                        .get(*plot)
+                        .unwrap_or(&default_plot_settings);
+                    if self.need_reset {
+                        plot_settings.unlock_y();
+                    }
+                    if let Some(min_y) = plot_settings.get_min_y().map(to_plot_y) {
+                        if !min_y.is_nan() {
+                            chart_plot = chart_plot.include_y(min_y);
+                        }
                     }
-                    if let Some(max_y) = plot_settings.get_max_y() {
-                        chart_plot = chart_plot.include_y(max_y);
+                    if let Some(max_y) = plot_settings.get_max_y().map(to_plot_y) {
+                        if !max_y.is_nan() {
+                            chart_plot = chart_plot.include_y(max_y);
+                        }
                     }
-                    chart_plot.show(ui, |plot_ui| {
-                        for metric in *metrics {
-                            let mut data = if let Some(d) = self.data.get(&metric.name) {
-                                if self.triggered.is_none() {
-                                    if let Some(last) = d.last() {
-                                        if let Some(min) = metric.get_trigger_below() {
-                                            if *last <= min {
-                                                self.triggered = Some(Triggered::below(
-                                                    *ts_vec.last().unwrap(),
-                                                    &metric.name,
-                                                ));
+                    // a solo metric hides the others of its plot only
+                    let solo = self
+                        .solo_metric
+                        .as_ref()
+                        .filter(|solo| metrics.iter().any(|m| m.name == **solo))
+                        .cloned();
+                    let response = ui.vertical(|ui| {
+                        ui.set_max_width(col_width * span - 10.0);
+                        let plot_response = chart_plot.show(ui, |plot_ui| {
+                            if plot_settings.take_y_lock_request() {
+                                let bounds = plot_ui.plot_bounds();
+                                plot_settings
+                                    .set_locked_y(Some((bounds.min()[1], bounds.max()[1])));
+                            }
+                            // the time axis keeps following the data
+                            if let Some((min_y, max_y)) = plot_settings.get_locked_y() {
+                                let bounds = plot_ui.plot_bounds();
+                                plot_ui.set_plot_bounds(PlotBounds::from_min_max(
+                                    [bounds.min()[0], min_y],
+                                    [bounds.max()[0], max_y],
+                                ));
+                                plot_ui.set_auto_bounds(egui::Vec2b::new(true, false));
+                            }
+                            // C key copies values of all the plot metrics at the cursor
+                            let copy_index = if plot_ui.response().hovered()
+                                && plot_ui.ctx().input(|i| i.key_pressed(egui::Key::C))
+                            {
+                                plot_ui.pointer_coordinate().and_then(|pointer| {
+                                    ts_vec_axis
+                                        .iter()
+                                        .enumerate()
+                                        .min_by(|(_, a), (_, b)| {
+                                            (pointer.x - **a)
+                                                .abs()
+                                                .total_cmp(&(pointer.x - **b).abs())
+                                        })
+                                        .map(|(i, _)| i)
+                                })
+                            } else {
+                                None
+                            };
+                            let mut copied_values = Vec::new();
+                            let mut legend_rows = Vec::new();
+                            let mut state_band = 0.0;
+                            for metric in metrics.iter().filter(|m| is_allowed(&m.name)) {
+                                let mut data = if let Some(d) = self.data.get(&metric.name) {
+                                    let d = &d[..d.len().saturating_sub(view_offset)];
+                                    if self.triggered.is_none() {
+                                        // thresholds are set in displayed units, the same as the
+                                        // trigger lines
+                                        #[allow(clippy::cast_precision_loss)]
+                                        if let Some(last) = d.last().map(|v| {
+                                            self.scales
+                                                .get(&metric.name)
+                                                .map_or(*v, |scale| *v / *scale as f64)
+                                        }) {
+                                            if let Some(min) = metric.get_trigger_below() {
+                                                if last <= min {
+                                                    let triggered = Triggered::below(
+                                                        *ts_vec.last().unwrap(),
+                                                        &metric.name,
+                                                        last,
+                                                    );
+                                                    self.trigger_history.push(triggered.clone());
+                                                    self.triggered = Some(triggered);
+                                                }
                                             }
-                                        }
-                                        if let Some(max) = metric.get_trigger_above() {
-                                            if *last >= max {
-                                                self.triggered = Some(Triggered::above(
-                                                    *ts_vec.last().unwrap(),
-                                                    &metric.name,
-                                                ));
+                                            if let Some(max) = metric.get_trigger_above() {
+                                                if last >= max {
+                                                    let triggered = Triggered::above(
+                                                        *ts_vec.last().unwrap(),
+                                                        &metric.name,
+                                                        last,
+                                                    );
+                                                    self.trigger_history.push(triggered.clone());
+                                                    self.triggered = Some(triggered);
+                                                }
                                             }
                                         }
                                     }
+                                    match d.len().cmp(&data_points) {
+                                        cmp::Ordering::Less => {
+                                            let to_insert = data_points - d.len();
+                                            let mut data = Vec::with_capacity(data_points);
+                                            data.resize(to_insert, f64::NAN);
+                                            data.extend(d);
+                                            data
+                                        }
+                                        cmp::Ordering::Equal => d.to_vec(),
+                                        cmp::Ordering::Greater => {
+                                            d[d.len() - data_points..].to_vec()
+                                        }
+                                    }
+                                } else {
+                                    vec![f64::NAN; data_points]
+                                };
+                                let color = colors[legend_rows.len()];
+                                legend_rows.push(
+                                    LegendRow::new(
+                                        &metric.name,
+                                        color,
+                                        self.scales.get(&metric.name).copied(),
+                                        &data,
+                                    )
+                                    .with_description(self.descriptions.get(&metric.name)),
+                                );
+                                if self.muted_metrics.contains(&metric.name)
+                                    || solo.as_ref().map_or(false, |solo| *solo != metric.name)
+                                {
+                                    continue;
                                 }
-                                match d.len().cmp(&data_points) {
-                                    cmp::Ordering::Less => {
-                                        let to_insert = data_points - d.len();
-                                        let mut data = Vec::with_capacity(data_points);
-                                        data.resize(to_insert, f64::NAN);
-                                        data.extend(d);
-                                        data
+                                if let Some(scale) = self.scales.get(&metric.name) {
+                                    #[allow(clippy::cast_precision_loss)]
+                                    let scale = *scale as f64;
+                                    for entry in &mut data {
+                                        *entry /= scale;
                                     }
-                                    cmp::Ordering::Equal => d.clone(),
-                                    cmp::Ordering::Greater => d[d.len() - data_points..].to_vec(),
                                 }
-                            } else {
-                                vec![f64::NAN; data_points]
-                            };
-                            if let Some(min_y) = plot_settings.get_min_y() {
-                                for entry in &mut data {
-                                    if *entry < min_y {
-                                        *entry = f64::NAN;
+                                #[allow(clippy::cast_precision_loss)]
+                                let interval = self.sampling_interval_ns as f64 / 1_000_000_000.0;
+                                let mut rate = None;
+                                match metric.get_rate() {
+                                    Rate::Off => {}
+                                    Rate::Replace => data = rate_of_change(&data, interval),
+                                    Rate::Overlay => rate = Some(rate_of_change(&data, interval)),
+                                }
+                                // values, clipped by the Y-range below, are not gaps
+                                if self.show_gaps {
+                                    show_gaps(
+                                        plot_ui,
+                                        &metric.name,
+                                        &data,
+                                        &ts_vec_axis,
+                                        self.gap_color,
+                                    );
+                                }
+                                if let Some(min_y) = plot_settings.get_min_y() {
+                                    for entry in &mut data {
+                                        if *entry < min_y {
+                                            *entry = f64::NAN;
+                                        }
                                     }
                                 }
-                            }
-                            if let Some(max_y) = plot_settings.get_max_y() {
-                                for entry in &mut data {
-                                    if *entry > max_y {
-                                        *entry = f64::NAN;
+                                if let Some(max_y) = plot_settings.get_max_y() {
+                                    for entry in &mut data {
+                                        if *entry > max_y {
+                                            *entry = f64::NAN;
+                                        }
+                                    }
+                                }
+                                if self.state_metrics.contains(&metric.name) {
+                                    show_state_ribbon(
+                                        plot_ui,
+                                        &metric.name,
+                                        &data,
+                                        &ts_vec_axis,
+                                        state_band,
+                                    );
+                                    state_band += 1.0;
+                                    continue;
+                                }
+                                let sma_window = metric.get_sma();
+                                if sma_window > 0 {
+                                    let sma = data
+                                        .windows(sma_window)
+                                        .map(|w| w.iter().sum::<f64>() / w.len() as f64)
+                                        .collect::<Vec<_>>();
+                                    let pp = PlotPoints::Owned(
+                                        sma.into_iter()
+                                            .zip(ts_vec_axis.clone())
+                                            .skip(sma_window - 1)
+                                            .map(|(d, ts)| PlotPoint::new(ts, to_plot_y(d)))
+                                            .collect(),
+                                    );
+                                    plot_ui.line(
+                                        Line::new(pp)
+                                            .name(format!("SMA {}", metric.name))
+                                            .style(egui_plot::LineStyle::Dotted { spacing: 5.0 }),
+                                    );
+                                }
+                                if let Some(rate) = rate {
+                                    let pp = PlotPoints::Owned(
+                                        rate.into_iter()
+                                            .zip(&ts_vec_axis)
+                                            .map(|(d, ts)| PlotPoint::new(*ts, to_plot_y(d)))
+                                            .collect(),
+                                    );
+                                    plot_ui.line(
+                                        Line::new(pp)
+                                            .name(format!("Rate {}", metric.name))
+                                            .style(egui_plot::LineStyle::Dashed { length: 5.0 }),
+                                    );
+                                }
+                                if let Some(value) = copy_index.and_then(|i| data.get(i)) {
+                                    if !value.is_nan() {
+                                        copied_values.push(format!("{}={}", metric.name, value));
+                                    }
+                                }
+                                if is_log_y {
+                                    for entry in &mut data {
+                                        *entry = log_y(*entry);
+                                    }
+                                }
+                                let marker = if self.show_markers {
+                                    data.iter()
+                                        .zip(&ts_vec_axis)
+                                        .rev()
+                                        .find(|(d, _)| !d.is_nan())
+                                        .map(|(d, ts)| [*ts, *d])
+                                } else {
+                                    None
+                                };
+                                // display only, triggers and stats use the raw samples
+                                let pp = PlotPoints::Owned(interpolate(
+                                    plot_settings
+                                        .get_interpolation()
+                                        .unwrap_or(self.interpolation),
+                                    &data,
+                                    &ts_vec_axis,
+                                ));
+                                let mut line = Line::new(pp).name(&metric.name);
+                                if let Some(color) = color {
+                                    line = line.color(color);
+                                }
+                                plot_ui.line(line);
+                                if let Some(marker) = marker {
+                                    let mut points = Points::new(vec![marker])
+                                        .name(&metric.name)
+                                        .shape(MarkerShape::Circle)
+                                        .filled(true)
+                                        .radius(MARKER_RADIUS);
+                                    if let Some(color) = color {
+                                        points = points.color(color);
                                     }
+                                    plot_ui.points(points);
+                                }
+                                if let Some(trigger_min) = metric.get_trigger_below() {
+                                    plot_ui.line(
+                                        Line::new(PlotPoints::Owned(vec![
+                                            PlotPoint::new(
+                                                ts_vec_axis.first().copied().unwrap_or_default(),
+                                                to_plot_y(trigger_min),
+                                            ),
+                                            PlotPoint::new(
+                                                ts_vec_axis.last().copied().unwrap_or_default(),
+                                                to_plot_y(trigger_min),
+                                            ),
+                                        ]))
+                                        .color(Color32::from_rgba_premultiplied(149, 80, 45, 20))
+                                        .style(egui_plot::LineStyle::Dashed { length: 10.0 })
+                                        .name(format!("TrB {}", metric.name)),
+                                    );
+                                }
+                                if let Some(trigger_max) = metric.get_trigger_above() {
+                                    plot_ui.line(
+                                        Line::new(PlotPoints::Owned(vec![
+                                            PlotPoint::new(
+                                                ts_vec_axis.first().copied().unwrap_or_default(),
+                                                to_plot_y(trigger_max),
+                                            ),
+                                            PlotPoint::new(
+                                                ts_vec_axis.last().copied().unwrap_or_default(),
+                                                to_plot_y(trigger_max),
+                                            ),
+                                        ]))
+                                        .color(Color32::from_rgba_premultiplied(149, 40, 45, 20))
+                                        .style(egui_plot::LineStyle::Dashed { length: 10.0 })
+                                        .name(format!("TrA {}", metric.name)),
+                                    );
                                 }
                             }
-                            let sma_window = metric.get_sma();
-                            if sma_window > 0 {
-                                let sma = data
-                                    .windows(sma_window)
-                                    .map(|w| w.iter().sum::<f64>() / w.len() as f64)
-                                    .collect::<Vec<_>>();
-                                let pp = PlotPoints::Owned(
-                                    sma.into_iter()
-                                        .zip(ts_vec_axis.clone())
-                                        .skip(sma_window - 1)
-                                        .map(|(d, ts)| PlotPoint::new(ts, d))
-                                        .collect(),
-                                );
-                                plot_ui.line(
-                                    Line::new(pp)
-                                        .name(format!("SMA {}", metric.name))
-                                        .style(egui_plot::LineStyle::Dotted { spacing: 5.0 }),
-                                );
+                            if let Some(ts) = copy_index.and_then(|i| ts_vec.get(i)) {
+                                let mut text = format!("t={}", ts);
+                                if let Some(at) = self.stream_epoch.and_then(|epoch| {
+                                    epoch.checked_add(Duration::from_secs_f64(ts.max(0.0)))
+                                }) {
+                                    text.push_str(&format!(" ({})", format_wall_clock(at)));
+                                }
+                                for value in &copied_values {
+                                    text.push(' ');
+                                    text.push_str(value);
+                                }
+                                self.status = Some(format!("Copied: {}", text));
+                                plot_ui.ctx().copy_text(text);
                             }
-                            let pp = PlotPoints::Owned(
-                                data.into_iter()
-                                    .zip(ts_vec_axis.clone())
-                                    .map(|(d, ts)| PlotPoint::new(ts, d))
-                                    .collect(),
+                            legend_rows
+                        });
+                        if self.show_legend && self.rich_legend {
+                            show_rich_legend(
+                                ui,
+                                &plot_response.inner,
+                                &mut self.muted_metrics,
+                                &mut self.solo_metric,
                             );
-                            let mut line = Line::new(pp).name(&metric.name);
-                            if let Some(color) = self.colors.get(&metric.name) {
-                                line = line.color(*color);
-                            }
-                            plot_ui.line(line);
-                            if let Some(trigger_min) = metric.get_trigger_below() {
-                                plot_ui.line(
-                                    Line::new(PlotPoints::Owned(vec![
-                                        PlotPoint::new(
-                                            ts_vec_axis.first().copied().unwrap_or_default(),
-                                            trigger_min,
-                                        ),
-                                        PlotPoint::new(
-                                            ts_vec_axis.last().copied().unwrap_or_default(),
-                                            trigger_min,
-                                        ),
-                                    ]))
-                                    .color(Color32::from_rgba_premultiplied(149, 80, 45, 20))
-                                    .style(egui_plot::LineStyle::Dashed { length: 10.0 })
-                                    .name(format!("TrB {}", metric.name)),
-                                );
-                            }
-                            if let Some(trigger_max) = metric.get_trigger_above() {
-                                plot_ui.line(
-                                    Line::new(PlotPoints::Owned(vec![
-                                        PlotPoint::new(
-                                            ts_vec_axis.first().copied().unwrap_or_default(),
-                                            trigger_max,
-                                        ),
-                                        PlotPoint::new(
-                                            ts_vec_axis.last().copied().unwrap_or_default(),
-                                            trigger_max,
-                                        ),
-                                    ]))
-                                    .color(Color32::from_rgba_premultiplied(149, 40, 45, 20))
-                                    .style(egui_plot::LineStyle::Dashed { length: 10.0 })
-                                    .name(format!("TrA {}", metric.name)),
-                                );
-                            }
                         }
+                        let out_of_range: usize = metrics
+                            .iter()
+                            .filter_map(|m| self.out_of_range.get(&m.name))
+                            .sum();
+                        if out_of_range > 0 {
+                            ui.label(
+                                RichText::new(format!("out of range: {}", out_of_range))
+                                    .small()
+                                    .color(Color32::YELLOW),
+                            );
+                        }
+                        plot_response.response.hovered()
                     });
+                    if response.inner {
+                        chart_hovered = true;
+                    }
                 }
             });
         }
+        let lane_width = ui.available_width() - 10.0;
+        if self.show_event_lane(ui, &ts_vec, lane_width) {
+            chart_hovered = true;
+        }
+        if chart_hovered {
+            let zoom = ui.input(|i| i.zoom_delta());
+            if (zoom - 1.0).abs() > f32::EPSILON {
+                self.time_window = (self.time_window / zoom).clamp(1.0, MAX_TIME_WINDOW);
+            }
+        }
+        if !was_triggered {
+            if let (Some(tr), Some(on_trigger)) = (&self.triggered, &self.on_trigger) {
+                run_trigger_command(on_trigger, tr);
+            }
+        }
+        if self.trigger_history.len() > TRIGGER_HISTORY_SIZE {
+            self.trigger_history
+                .drain(..self.trigger_history.len() - TRIGGER_HISTORY_SIZE);
+        }
     }
 }
 
 impl eframe::App for Scope {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(CUSTOM_COLORS_KEY, format_custom_colors(&self.custom_colors));
+        storage.set_string(
+            SELECTED_PAGE_KEY,
+            self.selected_page.clone().unwrap_or_default(),
+        );
+        storage.set_string(
+            SELECTED_TAB_KEY,
+            self.selected_tab.clone().unwrap_or_default(),
+        );
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if let Some(ref path) = self.summary_on_exit {
+            if let Err(e) = self.write_summary(path.as_deref()) {
+                eprintln!("Unable to write the summary: {}", e);
+            }
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let screenshot = ctx.input(|i| {
+            i.raw.events.iter().find_map(|event| match event {
+                egui::Event::Screenshot { image, .. } => Some(image.clone()),
+                _ => None,
+            })
+        });
+        if let Some(image) = screenshot {
+            self.save_screenshot(&image, ctx.pixels_per_point());
+        }
+        if self
+            .config_watcher
+            .as_ref()
+            .map_or(false, config::ConfigWatcher::take_changed)
+        {
+            self.reload_config();
+        }
         let time_window = Duration::from_secs_f32(self.time_window);
-        if self.paused {
+        if let (Some(after), Some(first_data_at)) = (self.autopause_after, self.first_data_at) {
+            if first_data_at.elapsed() >= after {
+                // once per session, the user may resume manually
+                self.autopause_after = None;
+                self.paused = true;
+                self.status = Some("Auto-paused".to_owned());
+            }
+        }
+        if let (Some(hold), Some(paused_at)) = (self.trigger_hold, self.trigger_paused_at) {
+            if !self.paused || self.triggered.is_none() {
+                // resumed or cleared manually
+                self.trigger_paused_at = None;
+            } else if paused_at.elapsed() >= hold {
+                self.paused = false;
+                self.triggered = None;
+                self.trigger_paused_at = None;
+            }
+        }
+        // the offline viewer keeps loading the recording while stopped and does not wait for data
+        if self.paused && self.playback.is_none() {
             thread::sleep(UI_DELAY);
         } else {
             let mut received = false;
@@ -839,22 +3416,54 @@ impl eframe::App for Scope {
                 received = true;
                 self.handle_event(event);
             }
-            if !received {
+            if received {
+                self.last_received = Instant::now();
+            } else if self.playback.is_none() {
                 thread::sleep(UI_DELAY);
             }
         }
         let Some(full_ts_vec) = self.data.get("") else {
-            egui::CentralPanel::default().show(ctx, |ui| {
-                ui.label("Connecting...");
+            egui::CentralPanel::default().show(ctx, |ui| match self.diagnostics {
+                Some(ref diagnostics) if self.started_at.elapsed() >= self.connect_timeout => {
+                    self.show_diagnostics(ui, diagnostics);
+                }
+                _ => {
+                    ui.label("Connecting...");
+                }
             });
-            thread::sleep(UI_DELAY);
-            ctx.request_repaint();
+            ctx.request_repaint_after(IDLE_REPAINT_INTERVAL);
             return;
         };
         let data_points = usize::try_from(
             u64::try_from(time_window.as_nanos()).unwrap() / self.sampling_interval_ns,
         )
         .unwrap();
+        if self.playback.is_none() && !self.paused {
+            self.view_offset = 0.0;
+        }
+        let buffered_points = full_ts_vec.len();
+        let mut seek_range = None;
+        if let Some(ref mut playback) = self.playback {
+            let first_ts = full_ts_vec.first().copied().unwrap_or_default();
+            let last_ts = full_ts_vec.last().copied().unwrap_or_default();
+            let range = (first_ts + time_window.as_secs_f64()).min(last_ts)..=last_ts;
+            let loaded = !self.connected;
+            let position = playback.advance(&range, !self.paused, loaded);
+            if loaded && position >= last_ts {
+                self.paused = true;
+            }
+            // the time window ends with the last sample at the playhead
+            let at = full_ts_vec.partition_point(|ts| *ts <= position);
+            #[allow(clippy::cast_precision_loss)]
+            {
+                self.view_offset = (buffered_points - cmp::max(at, 1)) as f32;
+            }
+            seek_range = Some(range);
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let view_offset =
+            (self.view_offset as usize).min(buffered_points.saturating_sub(data_points));
+        let full_ts_vec = &full_ts_vec[..buffered_points - view_offset];
         let mut ts_vec;
         match full_ts_vec.len().cmp(&data_points) {
             cmp::Ordering::Less => {
@@ -870,7 +3479,7 @@ impl eframe::App for Scope {
                 ts_vec.extend(full_ts_vec);
             }
             cmp::Ordering::Equal => {
-                ts_vec = full_ts_vec.clone();
+                ts_vec = full_ts_vec.to_vec();
             }
             cmp::Ordering::Greater => {
                 ts_vec = full_ts_vec[full_ts_vec.len() - data_points..].to_vec();
@@ -879,25 +3488,40 @@ impl eframe::App for Scope {
         if let Some(ref tr) = self.triggered {
             let ts_half = ts_vec.len() / 2;
             if let Some(ts) = ts_vec.get(ts_half) {
-                if tr.at <= *ts {
+                if tr.at <= *ts && !self.paused {
                     self.paused = true;
+                    self.trigger_paused_at = Some(Instant::now());
                 }
             }
         }
+        egui::TopBottomPanel::bottom("scrub_bar").show(ctx, |ui| {
+            if let Some(range) = seek_range {
+                self.show_seek_bar(ui, range);
+            } else {
+                self.show_scrub_bar(ui, buffered_points, data_points);
+            }
+        });
         egui::CentralPanel::default().show(ctx, |ui| {
             self.process_global_keys(ui);
             ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
                 ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
+                    #[cfg(feature = "logo")]
                     ui.add(
                         egui::Image::new(egui::include_image!("../assets/bma.svg"))
                             .rounding(5.0)
                             .max_width(48.0)
                             .shrink_to_fit(),
                     );
+                    #[cfg(not(feature = "logo"))]
+                    ui.label(RichText::new("BMA").heading().strong());
                     egui::Grid::new("status").show(ui, |ui| {
                         ui.label(&self.source);
                         ui.end_row();
-                        let text = if self.connected {
+                        let text = if self.playback.is_some() {
+                            RichText::new(if self.connected { "LOADING" } else { "LOADED" })
+                                .color(Color32::WHITE)
+                                .background_color(Color32::DARK_BLUE)
+                        } else if self.connected {
                             RichText::new("ONLINE")
                                 .color(Color32::WHITE)
                                 .background_color(Color32::DARK_GREEN)
@@ -911,23 +3535,35 @@ impl eframe::App for Scope {
                 });
                 egui::Grid::new("toolbar").show(ui, |ui| {
                     self.show_sma_toolbar(ui);
+                    self.show_rate_toolbar(ui);
                     self.show_trigger_toolbar(ui);
                     self.show_range_toolbar(ui);
+                    self.show_color_toolbar(ui);
                 });
             });
             egui::Grid::new("common_controls").show(ui, |ui| {
                 self.show_common_controls(ui);
             });
-            egui::ScrollArea::both().show(ui, |ui| {
-                self.show_charts(ui, ts_vec, data_points);
+            if !self.single_plot {
+                self.show_tabs(ui);
+            }
+            let charts = egui::ScrollArea::both().show(ui, |ui| {
+                self.show_charts(ui, ts_vec, data_points, view_offset);
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::RIGHT), |ui| {
                     let text = RichText::new("RoboPLC Metrics Scope © Bohemia Automation")
                         .color(Color32::DARK_GRAY);
                     ui.label(text);
                 });
             });
+            self.charts_rect = Some(charts.inner_rect);
         });
         self.need_reset = false;
-        ctx.request_repaint();
+        // the offline viewer is repainted continuously while loading or playing
+        let busy = self.playback.is_some() && (self.connected || !self.paused);
+        if !busy && (self.paused || self.last_received.elapsed() > IDLE_TIMEOUT) {
+            ctx.request_repaint_after(IDLE_REPAINT_INTERVAL);
+        } else {
+            ctx.request_repaint();
+        }
     }
 }
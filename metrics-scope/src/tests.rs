@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use atomic_float::AtomicF64;
+use clap::Parser;
+use egui::Color32;
+use metrics_exporter_scope::MetricInfo;
+
+use crate::{
+    args::{Interpolation, Rate},
+    config::DashboardConfig,
+    export::write_csv,
+    interpolate, monotone_spline, rate_of_change, Args, Event, Scope, SPLINE_SEGMENT_POINTS,
+};
+
+fn scope() -> Scope {
+    let (_tx, rx) =
+        rtsc::policy_channel::bounded::<Event, parking_lot::RawMutex, parking_lot::Condvar>(1);
+    Scope::new(
+        rx,
+        &Args::parse_from(["metrics-scope", "localhost"]),
+        Arc::new(AtomicF64::new(1.0)),
+        None,
+        DashboardConfig::default(),
+        DashboardConfig::default(),
+    )
+}
+
+fn metric_info(plot: Option<&str>) -> MetricInfo {
+    let labels = plot
+        .map(|plot| ("plot".to_owned(), plot.to_owned()))
+        .into_iter()
+        .collect();
+    MetricInfo::new(labels)
+}
+
+/// Plot name -> metric names
+fn plots(scope: &Scope) -> BTreeMap<&str, Vec<&str>> {
+    scope
+        .plots
+        .iter()
+        .map(|(plot, metrics)| {
+            (
+                plot.as_str(),
+                metrics.iter().map(|m| m.name.as_str()).collect(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn metric_moved_to_another_plot() {
+    let mut scope = scope();
+    scope.apply_metric_info("temp", &metric_info(Some("env")));
+    scope.apply_metric_info("hum", &metric_info(Some("env")));
+    scope.apply_metric_info("pressure", &metric_info(None));
+    assert_eq!(
+        plots(&scope),
+        [("env", vec!["hum", "temp"]), ("pressure", vec!["pressure"])].into()
+    );
+    // the old plot keeps the other metrics
+    scope.apply_metric_info("temp", &metric_info(Some("temp")));
+    assert_eq!(
+        plots(&scope),
+        [
+            ("env", vec!["hum"]),
+            ("pressure", vec!["pressure"]),
+            ("temp", vec!["temp"])
+        ]
+        .into()
+    );
+    assert_eq!(scope.metric_plots["temp"], "temp");
+    // the emptied plot is removed
+    scope.apply_metric_info("hum", &metric_info(Some("temp")));
+    assert_eq!(
+        plots(&scope),
+        [
+            ("pressure", vec!["pressure"]),
+            ("temp", vec!["hum", "temp"])
+        ]
+        .into()
+    );
+    assert!(!scope.plot_indexes.contains_key("env"));
+    assert!(!scope.plot_settings.contains_key("env"));
+    assert!(scope.plot_settings.contains_key("temp"));
+    // unchanged metadata keeps the plot
+    scope.apply_metric_info("hum", &metric_info(Some("temp")));
+    assert_eq!(scope.plots["temp"].len(), 2);
+}
+
+#[test]
+fn csv() {
+    let data = [
+        (String::new(), vec![1.0, 2.0, 3.0]),
+        ("temp".to_owned(), vec![20.0, f64::NAN, 22.5]),
+        // received later, aligned to the newest timestamp
+        ("a,b".to_owned(), vec![7.0]),
+        ("say \"hi\"".to_owned(), vec![1.0, 2.0]),
+    ]
+    .into();
+    let mut buf = Vec::new();
+    write_csv(&mut buf, "localhost:5001", &data, &[]).unwrap();
+    assert_eq!(
+        String::from_utf8(buf).unwrap(),
+        "# source: localhost:5001\n\
+         t,\"a,b\",\"say \"\"hi\"\"\",temp\n\
+         1,,,20\n\
+         2,,1,\n\
+         3,7,2,22.5\n"
+    );
+}
+
+/// Writes a dashboard config to a temporary file, unique per test
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "metrics-scope-test-{}-{}.toml",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn load_config(name: &str, contents: &str) -> Result<DashboardConfig, String> {
+    let path = config_file(name, contents);
+    let result = DashboardConfig::load(&path);
+    std::fs::remove_file(path).unwrap();
+    result
+}
+
+#[test]
+fn config_parsing() {
+    let config = load_config(
+        "parsing",
+        r##"
+y-range = ["plot1=0,100"]
+sma = ["plot1/temp=10"]
+rate = ["plot1/counter=overlay"]
+trigger = ["temp=,10.5"]
+sane-range = ["plot1=-100,100"]
+
+[colors]
+temp = "#ff8000"
+hum = "#0f0"
+counter = "red"
+"##,
+    )
+    .unwrap();
+    let plot = config.plots["plot1"];
+    assert_eq!((plot.min, plot.max), (Some(0.0), Some(100.0)));
+    assert_eq!(config.smas["plot1/temp"], 10);
+    assert!(config.rates["plot1/counter"] == Rate::Overlay);
+    let trigger = config.triggers["temp"];
+    assert_eq!((trigger.below, trigger.above), (None, Some(10.5)));
+    let range = config.sane_ranges["plot1"];
+    assert_eq!((range.min, range.max), (Some(-100.0), Some(100.0)));
+    assert_eq!(
+        config.colors,
+        [
+            ("counter".to_owned(), Color32::RED),
+            ("hum".to_owned(), Color32::from_rgb(0, 0xff, 0)),
+            ("temp".to_owned(), Color32::from_rgb(0xff, 0x80, 0)),
+        ]
+        .into()
+    );
+    // all keys are optional
+    let config = load_config("empty", "").unwrap();
+    assert!(config.plots.is_empty() && config.colors.is_empty());
+}
+
+#[test]
+fn config_errors() {
+    let err = load_config("unknown_key", "y-ranges = [\"plot1=0,100\"]")
+        .err()
+        .unwrap();
+    assert!(err.contains("unknown field `y-ranges`"), "{}", err);
+    for color in ["purple", "#ff80", "#gg8000", "#é1"] {
+        let err = load_config("color", &format!("[colors]\ntemp = \"{}\"", color))
+            .err()
+            .unwrap();
+        assert_eq!(err, format!("colors: invalid color: {}", color));
+    }
+    // errors of option values are prefixed with the key
+    let err = load_config("sma", "sma = [\"temp=x\"]").err().unwrap();
+    assert!(err.starts_with("sma: "), "{}", err);
+    assert!(
+        DashboardConfig::load(&std::env::temp_dir().join("metrics-scope-missing.toml")).is_err()
+    );
+}
+
+#[test]
+fn config_overrides_cli() {
+    let args = Args::parse_from([
+        "metrics-scope",
+        "localhost",
+        "--sma",
+        "temp=5",
+        "--sma",
+        "hum=3",
+        "--rate",
+        "counter",
+    ]);
+    let cli_config = DashboardConfig::from_args(&args);
+    let file_config = load_config(
+        "precedence",
+        r#"
+sma = ["temp=10"]
+rate = ["counter=overlay"]
+"#,
+    )
+    .unwrap();
+    let config = cli_config.merged(&file_config);
+    assert_eq!(config.smas["temp"], 10);
+    // the settings, missing in the config, are kept
+    assert_eq!(config.smas["hum"], 3);
+    assert!(config.rates["counter"] == Rate::Overlay);
+    // the command-line ones are intact and reapplied on config reloads
+    assert_eq!(cli_config.smas["temp"], 5);
+    assert!(cli_config.rates["counter"] == Rate::Replace);
+}
+
+/// Compares the float vectors, NaNs are equal to each other
+fn assert_floats_eq(a: &[f64], b: &[f64]) {
+    assert_eq!(a.len(), b.len(), "{:?} != {:?}", a, b);
+    for (x, y) in a.iter().zip(b) {
+        assert!(
+            x.is_nan() && y.is_nan() || (x - y).abs() < 1e-9,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+}
+
+#[test]
+fn rate() {
+    assert!(rate_of_change(&[], 0.5).is_empty());
+    // a single sample has no predecessor
+    assert_floats_eq(&rate_of_change(&[1.0], 0.5), &[f64::NAN]);
+    // non-monotonic input gives negative rates, gaps break the rate on both sides
+    assert_floats_eq(
+        &rate_of_change(&[1.0, 3.0, 2.0, f64::NAN, 5.0, 5.5], 0.5),
+        &[f64::NAN, 4.0, -2.0, f64::NAN, f64::NAN, 1.0],
+    );
+}
+
+fn spline(data: &[f64], ts: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    let mut points = Vec::new();
+    monotone_spline(data, ts, &mut points);
+    points.iter().map(|p| (p.x, p.y)).unzip()
+}
+
+#[test]
+fn spline_short_runs() {
+    // too short runs are passed as-is
+    assert_eq!(spline(&[], &[]), (vec![], vec![]));
+    assert_eq!(spline(&[5.0], &[1.0]), (vec![1.0], vec![5.0]));
+    assert_eq!(
+        spline(&[5.0, 7.0], &[1.0, 2.0]),
+        (vec![1.0, 2.0], vec![5.0, 7.0])
+    );
+}
+
+#[test]
+fn spline_shape() {
+    let data = [0.0, 1.0, 1.5, 10.0];
+    let ts = [0.0, 1.0, 2.0, 3.0];
+    let (xs, ys) = spline(&data, &ts);
+    assert_eq!(xs.len(), (data.len() - 1) * SPLINE_SEGMENT_POINTS + 1);
+    // the curve goes through the samples
+    for (k, (d, t)) in data.iter().zip(ts).enumerate() {
+        let i = k * SPLINE_SEGMENT_POINTS;
+        assert_floats_eq(&[xs[i], ys[i]], &[t, *d]);
+    }
+    // monotone data gives a monotone curve
+    assert!(xs.windows(2).all(|w| w[0] < w[1]));
+    assert!(ys.windows(2).all(|w| w[0] <= w[1]), "{:?}", ys);
+    // non-monotonic data does not overshoot the local extrema
+    let (_, ys) = spline(&[0.0, 2.0, 0.0, 2.0], &ts);
+    assert!(ys.iter().all(|y| (0.0..=2.0).contains(y)), "{:?}", ys);
+    // flat segments stay flat
+    let (_, ys) = spline(&[1.0, 1.0, 1.0, 3.0], &ts);
+    assert!(ys[..=2 * SPLINE_SEGMENT_POINTS]
+        .iter()
+        .all(|y| (y - 1.0).abs() < f64::EPSILON));
+}
+
+#[test]
+fn spline_gaps() {
+    let data = [0.0, 1.0, 2.0, f64::NAN, f64::NAN, 5.0];
+    let ts = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+    let points = interpolate(Interpolation::Spline, &data, &ts);
+    let (xs, ys): (Vec<f64>, Vec<f64>) = points.iter().map(|p| (p.x, p.y)).unzip();
+    let run = 2 * SPLINE_SEGMENT_POINTS + 1;
+    assert_eq!(xs.len(), run + 3);
+    // every NaN sample breaks the line, the runs are interpolated separately
+    assert_floats_eq(&xs[run..], &[3.0, 4.0, 5.0]);
+    assert_floats_eq(&ys[run..], &[f64::NAN, f64::NAN, 5.0]);
+    assert!(ys[..run].iter().all(|y| !y.is_nan()));
+    // a gap at the start
+    let points = interpolate(Interpolation::Spline, &[f64::NAN, 1.0], &[0.0, 1.0]);
+    assert_floats_eq(
+        &points.iter().map(|p| p.y).collect::<Vec<_>>(),
+        &[f64::NAN, 1.0],
+    );
+}
@@ -0,0 +1,78 @@
+//! C-compatible API (requires `ffi` feature)
+//!
+//! Allows non-Rust components to push gauges into the scope exporter. The library has to be built
+//! with a C-compatible crate type, e.g. `cargo rustc --release --features ffi --crate-type
+//! staticlib`.
+use std::{
+    ffi::{c_char, c_int, CStr},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use tracing::error;
+
+use crate::{Inner, ScopeBuilder};
+
+pub(crate) static SCOPE: Mutex<Option<Arc<Inner>>> = Mutex::new(None);
+
+/// Install the exporter as the global recorder, listening on the given port on all interfaces
+///
+/// Returns 0 on success, -1 on failure (e.g. the recorder is already installed).
+#[no_mangle]
+pub extern "C" fn scope_install(port: u16) -> c_int {
+    let mut scope = SCOPE.lock().unwrap_or_else(PoisonError::into_inner);
+    if scope.is_some() {
+        error!("scope exporter is already installed");
+        return -1;
+    }
+    let recorder = ScopeBuilder::new()
+        .with_addr(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))
+        .build();
+    let inner = recorder.inner.clone();
+    if let Err(error) = recorder.install() {
+        error!(%error, "unable to install scope exporter");
+        return -1;
+    }
+    scope.replace(inner);
+    0
+}
+
+/// Set a gauge value. The `~` scope prefix is added to the name automatically if missing
+///
+/// Returns 0 on success, -1 if the name is NULL or not a valid UTF-8 string.
+///
+/// # Safety
+///
+/// `name` must be NULL or a valid NUL-terminated string. The string is copied, so it may be freed
+/// by the caller right after the call.
+#[no_mangle]
+pub unsafe extern "C" fn scope_set_gauge(name: *const c_char, value: f64) -> c_int {
+    if name.is_null() {
+        return -1;
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return -1;
+    };
+    let key = if name.starts_with(crate::DEFAULT_PREFIX) {
+        name.to_owned()
+    } else {
+        format!("{}{}", crate::DEFAULT_PREFIX, name)
+    };
+    metrics::gauge!(key).set(value);
+    0
+}
+
+/// Disconnect all clients and stop the server
+///
+/// The global recorder can not be uninstalled, gauges set after the call (with
+/// `scope_set_gauge`) are ignored. The exporter can not be installed again.
+#[no_mangle]
+pub extern "C" fn scope_shutdown() {
+    if let Some(inner) = SCOPE
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+    {
+        inner.shutdown();
+    }
+}
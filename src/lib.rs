@@ -5,21 +5,30 @@ extern crate metrics_legacy as metrics;
 #[cfg(feature = "msrv")]
 extern crate metrics_util_legacy as metrics_util;
 
+#[cfg(all(unix, feature = "uds"))]
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::{
-    collections::BTreeMap,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    io::{Read, Write},
     net::{SocketAddr, TcpListener, TcpStream},
     num::TryFromIntError,
-    sync::{atomic::Ordering, Arc},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, RwLock,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use bma_ts::Monotonic;
 use metrics::{Key, Recorder};
-use metrics_util::registry::{AtomicStorage, GenerationalStorage, Registry};
-use rtsc::time::interval;
+use metrics_util::registry::{AtomicStorage, Generational, GenerationalStorage, Registry, Storage};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
 /// Crate error type
 #[derive(thiserror::Error, Debug)]
@@ -33,9 +42,12 @@ pub enum Error {
     /// Data deserialization errors
     #[error("decode error: {0}")]
     Decode(#[from] rmp_serde::decode::Error),
-    /// Recorder setup errors
+    /// Protocol errors (e.g. the peer is not a metrics-scope server)
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    /// Recorder setup errors (the recorder, which has not been set, is dropped)
     #[error("set recorder error: {0}")]
-    SetRecorder(#[from] metrics::SetRecorderError<ScopeRecorder>),
+    SetRecorder(metrics::SetRecorderError<()>),
     /// Other errors
     #[error("{0}")]
     Other(String),
@@ -47,349 +59,3295 @@ impl From<TryFromIntError> for Error {
     }
 }
 
-const CLIENT_CHAT_TIMEOUT: Duration = Duration::from_secs(60);
+impl<S: ScopeStorage> From<metrics::SetRecorderError<ScopeRecorder<S>>> for Error {
+    fn from(_error: metrics::SetRecorderError<ScopeRecorder<S>>) -> Self {
+        Self::SetRecorder(metrics::SetRecorderError(()))
+    }
+}
+
+const DEFAULT_CHAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const CLIENT_SETTINGS_TIMEOUT: Duration = Duration::from_secs(5);
 
-const SEND_INFO_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_INFO_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A ping is sent to the client if there have been no snapshots for this time
+const PING_INTERVAL: Duration = Duration::from_secs(2);
+
+// clients, which support information deltas, still get full information packets periodically
+const INFO_RESYNC_INTERVAL: Duration = Duration::from_secs(60);
 
 const SERVER_THREAD_NAME: &str = "MScopeSrv";
 
+const SAMPLER_THREAD_NAME: &str = "MScopeSmp";
+
+const SUBSCRIBER_QUEUE_SIZE: usize = 16;
+
+const EVENT_QUEUE_SIZE: usize = 1024;
+
+// the window, client bandwidth is measured for
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+// on change only, the data of every 1024th snapshot
+const MAX_BANDWIDTH_LEVEL: u32 = 11;
+
+// the event name, bandwidth limit adaptations are signaled to clients with
+const BANDWIDTH_EVENT: &str = "mscope_bandwidth";
+
+const BEHIND_SCHEDULE_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Self-metric names are prefixed with the scope prefix
+const MISSED_TICKS_METRIC: &str = "mscope_missed_ticks";
+
+const DROPPED_SNAPSHOTS_METRIC: &str = "mscope_dropped_snapshots";
+
+const DEFAULT_PREFIX: &str = "~";
+
+const DEFAULT_MAX_CLIENTS: usize = 16;
+
+/// Write timeout for connections, rejected because of the client limit
+const REJECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+const DEFAULT_HISTOGRAM_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Histogram samples are dropped with this interval while there are no clients
+const IDLE_HISTOGRAM_CLEAR_INTERVAL: Duration = Duration::from_secs(1);
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod openmetrics;
+#[cfg(feature = "prometheus-endpoint")]
+mod prometheus;
+mod status;
+#[cfg(test)]
+mod tests;
+
 /// Communication protocol
 pub mod protocol {
 
     /// Current protocol version
-    pub const VERSION: u16 = 1;
+    pub const VERSION: u16 = 8;
+
+    /// The oldest protocol version clients of this crate can communicate with
+    pub const MIN_VERSION: u16 = 1;
+
+    /// Signature, written by the server before the protocol version (since version 2)
+    pub const MAGIC: [u8; 4] = *b"MSCP";
+
+    /// The first protocol version with packets, tagged by the `type` field (older versions
+    /// distinguish packets by their fields only)
+    pub const TAGGED_PACKETS_VERSION: u16 = 3;
+
+    /// The first protocol version with information delta packets
+    pub const INFO_DELTA_VERSION: u16 = 4;
+
+    /// The first protocol version with timeline event packets
+    pub const EVENTS_VERSION: u16 = 5;
+
+    /// The first protocol version with compressed frames
+    pub const COMPRESSION_VERSION: u16 = 6;
+
+    /// The first protocol version with keep-alive ping packets
+    pub const PING_VERSION: u16 = 7;
+
+    /// The first protocol version with snapshot batch packets
+    pub const BATCH_VERSION: u16 = 8;
+
+    /// Frame length prefix bit, set for zstd-compressed frames
+    const COMPRESSED_FRAME: u32 = 1 << 31;
+
+    /// Authentication token frames are read before the client is trusted, so they are limited
+    const MAX_TOKEN_FRAME: usize = 1024;
+
+    /// Version 1 servers write the version only, with no signature before it
+    const LEGACY_HEADER: [u8; 2] = 1u16.to_le_bytes();
+
+    const SCHEMA: &str = include_str!("schema.json");
 
     use std::io::{Read, Write};
 
-    use crate::{ClientSettings, Error, Packet};
+    use crate::{
+        ClientSettings, Error, Event, Info, InfoDelta, Packet, Ping, Snapshot, SnapshotBatch,
+    };
+    use bma_ts::Monotonic;
     use serde::{Deserialize, Serialize};
 
-    /// Read protocol version from a stream
+    /// Check if a protocol version, received from a server, is supported
+    pub fn is_version_supported(version: u16) -> bool {
+        (MIN_VERSION..=VERSION).contains(&version)
+    }
+
+    /// Describe the protocol structures ([`ClientSettings`], [`Packet`] and its variants) as a JSON
+    /// Schema document, with the exact field names and types, for clients written in other
+    /// languages
+    pub fn describe_schema() -> &'static str {
+        SCHEMA
+    }
+
+    /// Read the protocol header (signature and version) from a stream
+    ///
+    /// Legacy (version 1) servers which do not send the signature are detected as well. If the
+    /// peer is not a metrics-scope server, [`Error::Protocol`] is returned.
     pub fn read_version<R>(mut stream: R) -> Result<u16, Error>
     where
         R: Read,
     {
         let buf = &mut [0u8; 2];
         stream.read_exact(buf)?;
+        if *buf == LEGACY_HEADER {
+            return Ok(1);
+        }
+        for magic_part in MAGIC.chunks(2) {
+            if buf[..] != *magic_part {
+                return Err(Error::Protocol("not a metrics-scope server".to_owned()));
+            }
+            stream.read_exact(buf)?;
+        }
         Ok(u16::from_le_bytes(*buf))
     }
-
-    /// Write protocol version to a stream
-    pub fn write_version<W>(mut stream: W) -> Result<(), Error>
-    where
-        W: Write,
-    {
-        stream.write_all(&VERSION.to_le_bytes())?;
+
+    /// Write the protocol header (signature and version) to a stream
+    pub fn write_version<W>(mut stream: W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        stream.write_all(&MAGIC)?;
+        stream.write_all(&VERSION.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Packets of protocol versions 1-2
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum UntaggedPacket {
+        Info(Info),
+        Snapshot(Snapshot),
+    }
+
+    impl From<UntaggedPacket> for Packet {
+        fn from(packet: UntaggedPacket) -> Self {
+            match packet {
+                UntaggedPacket::Info(info) => Packet::Info(info),
+                UntaggedPacket::Snapshot(snapshot) => Packet::Snapshot(snapshot),
+            }
+        }
+    }
+
+    /// Borrowed [`Packet`], encoded the same way
+    #[derive(Serialize)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    enum PacketRef<'a> {
+        Info(&'a Info),
+        Snapshot(&'a Snapshot),
+        #[serde(rename = "info_delta")]
+        InfoDelta(&'a InfoDelta),
+        Event(&'a Event),
+        Ping(&'a Ping),
+        #[serde(rename = "snapshot_batch")]
+        SnapshotBatch(&'a SnapshotBatch),
+    }
+
+    /// Read a packet of the current protocol version from a stream
+    pub fn read_packet<R>(stream: R) -> Result<Packet, Error>
+    where
+        R: Read,
+    {
+        read_packet_versioned(stream, VERSION)
+    }
+
+    /// Read a packet from a stream, encoded according to the protocol version, received from the
+    /// server
+    pub fn read_packet_versioned<R>(stream: R, version: u16) -> Result<Packet, Error>
+    where
+        R: Read,
+    {
+        if version >= TAGGED_PACKETS_VERSION {
+            read(stream)
+        } else {
+            read::<_, UntaggedPacket>(stream).map(Into::into)
+        }
+    }
+
+    /// Write a packet of the current protocol version to a stream
+    pub fn write_packet<W>(stream: W, packet: &Packet) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        write_packet_versioned(stream, packet, VERSION)
+    }
+
+    /// Write a packet to a stream, encoded according to the negotiated protocol version
+    ///
+    /// [`Packet::InfoDelta`] can not be written for versions older than [`INFO_DELTA_VERSION`],
+    /// [`Packet::Event`] - for versions older than [`EVENTS_VERSION`], [`Packet::Ping`] - for
+    /// versions older than [`PING_VERSION`], [`Packet::SnapshotBatch`] - for versions older than
+    /// [`BATCH_VERSION`].
+    pub fn write_packet_versioned<W>(stream: W, packet: &Packet, version: u16) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        write_frame(stream, &encode_packet(packet, version)?, false)
+    }
+
+    /// Write a zstd-compressed packet to a stream, encoded according to the negotiated protocol
+    /// version (clients request compression with [`ClientSettings::with_compression`])
+    ///
+    /// Compressed packets can not be written for versions older than [`COMPRESSION_VERSION`] or
+    /// if the crate is built without `compression` feature.
+    pub fn write_packet_compressed<W>(stream: W, packet: &Packet, version: u16) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        if version < COMPRESSION_VERSION {
+            return Err(Error::Protocol(format!(
+                "compression is not supported by version {}",
+                version
+            )));
+        }
+        write_frame(stream, &encode_packet(packet, version)?, true)
+    }
+
+    fn encode_packet(packet: &Packet, version: u16) -> Result<Vec<u8>, Error> {
+        let data = match (packet, version >= TAGGED_PACKETS_VERSION) {
+            (Packet::Info(info), true) => rmp_serde::to_vec_named(&PacketRef::Info(info))?,
+            (Packet::Snapshot(snapshot), true) => {
+                rmp_serde::to_vec_named(&PacketRef::Snapshot(snapshot))?
+            }
+            (Packet::InfoDelta(delta), true) if version >= INFO_DELTA_VERSION => {
+                rmp_serde::to_vec_named(&PacketRef::InfoDelta(delta))?
+            }
+            (Packet::Event(event), true) if version >= EVENTS_VERSION => {
+                rmp_serde::to_vec_named(&PacketRef::Event(event))?
+            }
+            (Packet::Ping(ping), true) if version >= PING_VERSION => {
+                rmp_serde::to_vec_named(&PacketRef::Ping(ping))?
+            }
+            (Packet::SnapshotBatch(batch), true) if version >= BATCH_VERSION => {
+                rmp_serde::to_vec_named(&PacketRef::SnapshotBatch(batch))?
+            }
+            (Packet::Info(info), false) => rmp_serde::to_vec_named(info)?,
+            (Packet::Snapshot(snapshot), false) => rmp_serde::to_vec_named(snapshot)?,
+            (Packet::InfoDelta(_), _) => {
+                return Err(Error::Protocol(format!(
+                    "information deltas are not supported by version {}",
+                    version
+                )))
+            }
+            (Packet::Event(_), _) => {
+                return Err(Error::Protocol(format!(
+                    "events are not supported by version {}",
+                    version
+                )))
+            }
+            (Packet::Ping(_), _) => {
+                return Err(Error::Protocol(format!(
+                    "pings are not supported by version {}",
+                    version
+                )))
+            }
+            (Packet::SnapshotBatch(_), _) => {
+                return Err(Error::Protocol(format!(
+                    "snapshot batches are not supported by version {}",
+                    version
+                )))
+            }
+        };
+        Ok(data)
+    }
+
+    /// Write a snapshot packet of the current protocol version to a stream (without wrapping the
+    /// snapshot into [`Packet`])
+    pub fn write_snapshot<W>(stream: W, snapshot: &Snapshot) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        write(stream, PacketRef::Snapshot(snapshot))
+    }
+
+    /// Read client settings from a stream
+    pub fn read_client_settings<R>(stream: R) -> Result<ClientSettings, Error>
+    where
+        R: Read,
+    {
+        read(stream)
+    }
+
+    /// Write client settings to a stream
+    pub fn write_client_settings<W>(stream: W, settings: &ClientSettings) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        write(stream, settings)
+    }
+
+    /// Read an authentication token from a stream (sent by clients before the settings, if the
+    /// server requires authentication)
+    pub fn read_token<R>(stream: R) -> Result<String, Error>
+    where
+        R: Read,
+    {
+        read_limited(stream, MAX_TOKEN_FRAME)
+    }
+
+    /// Write an authentication token to a stream
+    pub fn write_token<W>(stream: W, token: &str) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        write(stream, token)
+    }
+
+    /// Streaming snapshot encoder
+    ///
+    /// Encodes snapshot packets directly from (name, value) pairs into reusable buffers, so no
+    /// intermediate maps are allocated in hot loops. The output is the same as produced by
+    /// [`write_packet_versioned`].
+    pub struct SnapshotEncoder {
+        entries: Vec<u8>,
+        frame: Vec<u8>,
+        // encoded snapshots of the pending batch
+        batched: Vec<u8>,
+        batched_len: u32,
+        batch: u32,
+        version: u16,
+        compress: bool,
+    }
+
+    impl Default for SnapshotEncoder {
+        fn default() -> Self {
+            Self::versioned(VERSION)
+        }
+    }
+
+    impl SnapshotEncoder {
+        /// Create a new encoder for the current protocol version
+        pub fn new() -> Self {
+            Self::default()
+        }
+        /// Create a new encoder for the negotiated protocol version
+        pub fn versioned(version: u16) -> Self {
+            Self {
+                entries: Vec::new(),
+                frame: Vec::new(),
+                batched: Vec::new(),
+                batched_len: 0,
+                batch: 1,
+                version,
+                compress: false,
+            }
+        }
+        /// Send snapshots in batches of the given size as [`Packet::SnapshotBatch`] (the default
+        /// is 1, no batches). Snapshots are held until the batch is full, so pending ones must be
+        /// written with [`SnapshotEncoder::flush_batch`] before other packets
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the batch size is larger than 1 and the negotiated protocol
+        /// version is older than [`BATCH_VERSION`]
+        pub fn with_batch(mut self, batch: u32) -> Result<Self, Error> {
+            if batch > 1 && self.version < BATCH_VERSION {
+                return Err(Error::Protocol(format!(
+                    "snapshot batches are not supported by version {}",
+                    self.version
+                )));
+            }
+            self.batch = batch.max(1);
+            Ok(self)
+        }
+        /// Compress packets with zstd (see [`write_packet_compressed`])
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the negotiated protocol version is older than
+        /// [`COMPRESSION_VERSION`]
+        pub fn with_compression(mut self, compress: bool) -> Result<Self, Error> {
+            if compress && self.version < COMPRESSION_VERSION {
+                return Err(Error::Protocol(format!(
+                    "compression is not supported by version {}",
+                    self.version
+                )));
+            }
+            self.compress = compress;
+            Ok(self)
+        }
+        /// Encode a snapshot packet and write it to a stream
+        pub fn write<W, I, K>(&mut self, stream: W, t: Monotonic, data: I) -> Result<(), Error>
+        where
+            W: Write,
+            I: IntoIterator<Item = (K, f64)>,
+            K: AsRef<str>,
+        {
+            self.encode(t, data)?;
+            self.finish(stream)
+        }
+        /// Write the pending snapshots of the batch (if any) as a batch packet
+        pub fn flush_batch<W: Write>(&mut self, stream: W) -> Result<(), Error> {
+            if self.batched_len == 0 {
+                return Ok(());
+            }
+            self.encode_batch()?;
+            self.batched.clear();
+            self.batched_len = 0;
+            self.flush(stream)
+        }
+        fn finish<W: Write>(&mut self, stream: W) -> Result<(), Error> {
+            if self.batch == 1 {
+                self.flush(stream)
+            } else if self.batched_len >= self.batch {
+                self.flush_batch(stream)
+            } else {
+                Ok(())
+            }
+        }
+        fn encode_batch(&mut self) -> Result<(), rmp_serde::encode::Error> {
+            self.frame.clear();
+            // frame length placeholder
+            self.frame.extend_from_slice(&[0u8; 4]);
+            rmp::encode::write_map_len(&mut self.frame, 2)?;
+            rmp::encode::write_str(&mut self.frame, "type")?;
+            rmp::encode::write_str(&mut self.frame, "snapshot_batch")?;
+            rmp::encode::write_str(&mut self.frame, "s")?;
+            rmp::encode::write_array_len(&mut self.frame, self.batched_len)?;
+            self.frame.extend_from_slice(&self.batched);
+            Ok(())
+        }
+        fn flush<W: Write>(&mut self, mut stream: W) -> Result<(), Error> {
+            if self.compress {
+                return write_frame(stream, &self.frame[4..], true);
+            }
+            let frame_len = frame_len(self.frame.len() - 4)?;
+            self.frame[..4].copy_from_slice(&frame_len.to_le_bytes());
+            stream.write_all(&self.frame)?;
+            Ok(())
+        }
+        fn encode<I, K>(&mut self, t: Monotonic, data: I) -> Result<(), rmp_serde::encode::Error>
+        where
+            I: IntoIterator<Item = (K, f64)>,
+            K: AsRef<str>,
+        {
+            self.entries.clear();
+            let mut len = 0;
+            for (name, value) in data {
+                rmp::encode::write_str(&mut self.entries, name.as_ref())?;
+                rmp::encode::write_f64(&mut self.entries, value)?;
+                len += 1;
+            }
+            // snapshots of a batch are untagged, the batch packet is tagged itself
+            let out = if self.batch > 1 {
+                self.batched_len += 1;
+                rmp::encode::write_map_len(&mut self.batched, 2)?;
+                &mut self.batched
+            } else {
+                self.frame.clear();
+                // frame length placeholder
+                self.frame.extend_from_slice(&[0u8; 4]);
+                if self.version >= TAGGED_PACKETS_VERSION {
+                    rmp::encode::write_map_len(&mut self.frame, 3)?;
+                    rmp::encode::write_str(&mut self.frame, "type")?;
+                    rmp::encode::write_str(&mut self.frame, "snapshot")?;
+                } else {
+                    rmp::encode::write_map_len(&mut self.frame, 2)?;
+                }
+                &mut self.frame
+            };
+            rmp::encode::write_str(out, "t")?;
+            rmp_serde::encode::write_named(out, &t)?;
+            rmp::encode::write_str(out, "d")?;
+            rmp::encode::write_map_len(out, len)?;
+            out.extend_from_slice(&self.entries);
+            Ok(())
+        }
+    }
+
+    /// Frame length prefix, the top bit is reserved for the compressed frame flag
+    fn frame_len(len: usize) -> Result<u32, Error> {
+        let len = u32::try_from(len)?;
+        if len & COMPRESSED_FRAME != 0 {
+            return Err(Error::Protocol(format!(
+                "frame is too large: {} bytes",
+                len
+            )));
+        }
+        Ok(len)
+    }
+
+    fn write_frame<W: Write>(mut stream: W, data: &[u8], compress: bool) -> Result<(), Error> {
+        if compress {
+            let data = compress_frame(data)?;
+            stream.write_all(&(frame_len(data.len())? | COMPRESSED_FRAME).to_le_bytes())?;
+            stream.write_all(&data)?;
+        } else {
+            stream.write_all(&frame_len(data.len())?.to_le_bytes())?;
+            stream.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    fn compress_frame(data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL)?)
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn compress_frame(_data: &[u8]) -> Result<Vec<u8>, Error> {
+        Err(Error::Protocol(
+            "compression is not supported, the crate is built without compression feature"
+                .to_owned(),
+        ))
+    }
+
+    /// The decompressed frame is limited the same way as a plain one, so a small compressed
+    /// frame can not make the reader allocate a large buffer
+    #[cfg(feature = "compression")]
+    fn decompress_frame(data: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        zstd::stream::read::Decoder::new(data)?
+            .take(u64::try_from(max_len)?.saturating_add(1))
+            .read_to_end(&mut buf)?;
+        if buf.len() > max_len {
+            return Err(Error::Protocol(format!(
+                "decompressed frame is too large: over {} bytes",
+                max_len
+            )));
+        }
+        Ok(buf)
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn decompress_frame(_data: &[u8], _max_len: usize) -> Result<Vec<u8>, Error> {
+        Err(Error::Protocol(
+            "compressed frame received, the crate is built without compression feature".to_owned(),
+        ))
+    }
+
+    fn write<D, W>(stream: W, data: D) -> Result<(), Error>
+    where
+        W: Write,
+        D: Serialize,
+    {
+        write_frame(stream, &rmp_serde::to_vec_named(&data)?, false)
+    }
+
+    fn read<R, D>(stream: R) -> Result<D, Error>
+    where
+        R: Read,
+        D: for<'de> Deserialize<'de>,
+    {
+        read_limited(stream, usize::MAX)
+    }
+
+    /// Reads a frame, which is not larger than the limit, so the peer can not make the reader
+    /// allocate a large buffer
+    fn read_limited<R, D>(mut stream: R, max_len: usize) -> Result<D, Error>
+    where
+        R: Read,
+        D: for<'de> Deserialize<'de>,
+    {
+        let buf = &mut [0u8; 4];
+        stream.read_exact(buf)?;
+        let len = u32::from_le_bytes(*buf);
+        let frame_len = usize::try_from(len & !COMPRESSED_FRAME)?;
+        if frame_len > max_len {
+            return Err(Error::Protocol(format!(
+                "frame is too large: {} bytes",
+                frame_len
+            )));
+        }
+        let mut buf = vec![0u8; frame_len];
+        stream.read_exact(&mut buf)?;
+        if len & COMPRESSED_FRAME != 0 {
+            let max_len = max_len.min(usize::try_from(!COMPRESSED_FRAME)?);
+            return Ok(rmp_serde::from_slice(&decompress_frame(&buf, max_len)?)?);
+        }
+        Ok(rmp_serde::from_slice(&buf)?)
+    }
+}
+
+/// Communication packets
+///
+/// Since protocol version 3 packets are tagged with the `type` field (`info`, `snapshot`,
+/// `info_delta`, `event`, `ping` or `snapshot_batch`), packets of older versions are
+/// distinguished by their fields (see [`protocol::read_packet_versioned`]).
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Packet {
+    /// Information packet (metrics metadata)
+    Info(Info),
+    /// Snapshot packet (metrics data)
+    Snapshot(Snapshot),
+    /// Information delta packet (changes of metrics metadata since the previous information
+    /// packet, since protocol version 4)
+    #[serde(rename = "info_delta")]
+    InfoDelta(InfoDelta),
+    /// Timeline event packet (since protocol version 5)
+    Event(Event),
+    /// Keep-alive packet (since protocol version 7)
+    Ping(Ping),
+    /// Snapshot batch packet (since protocol version 8)
+    #[serde(rename = "snapshot_batch")]
+    SnapshotBatch(SnapshotBatch),
+}
+
+/// Client settings
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ClientSettings {
+    sampling_interval: u64,
+    #[serde(default, skip_serializing_if = "TimestampUnit::is_nanos")]
+    timestamp_unit: TimestampUnit,
+    // not sent by version 1-2 clients, which expect untagged packets
+    #[serde(default = "untagged_packets_version")]
+    version: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    adaptive: Option<AdaptiveSampling>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    patterns: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bandwidth_limit: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_metrics: Option<usize>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    intervals: BTreeMap<String, MetricInterval>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    delta: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    compress: bool,
+    #[serde(
+        default = "single_snapshot_batch",
+        skip_serializing_if = "is_single_snapshot"
+    )]
+    batch: u32,
+}
+
+/// Per-metric sampling interval
+///
+/// The metric is sent in the first snapshot at or after every `phase + N * interval` (nanoseconds
+/// since the sampling start), so metrics with the same interval but different phases are sent
+/// in different snapshots.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+pub struct MetricInterval {
+    interval: u64,
+    #[serde(default)]
+    phase: u64,
+}
+
+/// Adaptive sampling bounds
+///
+/// A metric is sent at the sampling interval while it changes by more than the threshold, and the
+/// interval doubles (up to the max one) while the metric stays flat. Snapshots contain changed
+/// and due metrics only.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+pub struct AdaptiveSampling {
+    max_interval: u64,
+    #[serde(default)]
+    threshold: f64,
+}
+
+fn untagged_packets_version() -> u16 {
+    protocol::TAGGED_PACKETS_VERSION - 1
+}
+
+fn single_snapshot_batch() -> u32 {
+    1
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_single_snapshot(batch: &u32) -> bool {
+    *batch <= 1
+}
+
+impl ClientSettings {
+    /// # Panics
+    ///
+    /// Panics if the duration is too large to fit into a u64 (see [`ClientSettings::try_new`]).
+    pub fn new(sampling_interval: Duration) -> Self {
+        Self::try_new(sampling_interval).unwrap()
+    }
+    /// Create new client settings
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the sampling interval in nanoseconds does not fit into a u64 (about
+    /// 584 years)
+    pub fn try_new(sampling_interval: Duration) -> Result<Self, Error> {
+        let sampling_interval = duration_ns(sampling_interval, "sampling interval")?;
+        Ok(Self {
+            sampling_interval,
+            timestamp_unit: TimestampUnit::default(),
+            version: protocol::VERSION,
+            adaptive: None,
+            filter: None,
+            patterns: Vec::new(),
+            bandwidth_limit: None,
+            max_metrics: None,
+            intervals: BTreeMap::new(),
+            delta: false,
+            compress: false,
+            batch: 1,
+        })
+    }
+    /// Sampling interval
+    pub fn sampling_interval(&self) -> Duration {
+        Duration::from_nanos(self.sampling_interval)
+    }
+    /// Change the sampling interval
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is too large to fit into a u64 (see
+    /// [`ClientSettings::try_with_sampling_interval`]).
+    pub fn with_sampling_interval(self, sampling_interval: Duration) -> Self {
+        self.try_with_sampling_interval(sampling_interval).unwrap()
+    }
+    /// Change the sampling interval
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the duration in nanoseconds does not fit into a u64
+    pub fn try_with_sampling_interval(
+        mut self,
+        sampling_interval: Duration,
+    ) -> Result<Self, Error> {
+        self.sampling_interval = duration_ns(sampling_interval, "sampling interval")?;
+        Ok(self)
+    }
+    /// Request packets of an older protocol version (the default is [`protocol::VERSION`]), e.g.
+    /// for tools which are not able to decode the current one
+    pub fn with_version(mut self, version: u16) -> Self {
+        self.version = version;
+        self
+    }
+    /// Request snapshot timestamps in the given unit (the default is nanoseconds)
+    pub fn with_timestamp_unit(mut self, timestamp_unit: TimestampUnit) -> Self {
+        self.timestamp_unit = timestamp_unit;
+        self
+    }
+    /// Request adaptive sampling: flat metrics (changed by no more than the threshold) are sent
+    /// less frequently, down to once per the max interval
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is too large to fit into a u64 (see
+    /// [`ClientSettings::try_with_adaptive_sampling`]).
+    pub fn with_adaptive_sampling(self, max_interval: Duration, threshold: f64) -> Self {
+        self.try_with_adaptive_sampling(max_interval, threshold)
+            .unwrap()
+    }
+    /// Request adaptive sampling (see [`ClientSettings::with_adaptive_sampling`])
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the duration in nanoseconds does not fit into a u64
+    pub fn try_with_adaptive_sampling(
+        mut self,
+        max_interval: Duration,
+        threshold: f64,
+    ) -> Result<Self, Error> {
+        self.adaptive = Some(AdaptiveSampling {
+            max_interval: duration_ns(max_interval, "adaptive sampling max interval")?,
+            threshold,
+        });
+        Ok(self)
+    }
+    /// Request metrics, which names (without the scope prefix) match the regular expression, only.
+    /// The filter is applied by the server, so other metrics are neither sampled for the client
+    /// nor sent
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the regular expression is invalid.
+    pub fn with_filter(mut self, filter: &str) -> Result<Self, Error> {
+        compile_filter(filter)?;
+        self.filter = Some(filter.to_owned());
+        Ok(self)
+    }
+    /// Request metrics, which names (without the scope prefix) match any of the glob patterns
+    /// (`*` matches any characters, `?` matches a single one), only. The patterns are matched by
+    /// the server per connection, so clients of the same server may watch different metric sets.
+    /// No patterns means all metrics. If a filter is set as well, metrics must match both
+    pub fn with_patterns<I, P>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Request a bandwidth budget (bytes per second). When the budget is exceeded, the server
+    /// sends changed metrics only, then the data of every 2nd, 4th etc. snapshot only (snapshots
+    /// themselves still come at the sampling interval). Adaptations are signaled with
+    /// `mscope_bandwidth` timeline events
+    pub fn with_bandwidth_limit(mut self, bytes_per_second: u64) -> Self {
+        self.bandwidth_limit = Some(bytes_per_second);
+        self
+    }
+    /// Limit the number of metrics the server sends. If there are more, the ones with the
+    /// highest `priority` label values (0 if not set) are sent and the information packets are
+    /// marked as truncated (see [`Info::truncated_from`])
+    pub fn with_max_metrics(mut self, max_metrics: usize) -> Self {
+        self.max_metrics = Some(max_metrics);
+        self
+    }
+    /// Request delta snapshots: the first snapshot contains all metrics, the following ones
+    /// contain the metrics, changed since they have been sent last time, only. Clients must hold
+    /// the last values of missing metrics
+    pub fn with_delta(mut self, delta: bool) -> Self {
+        self.delta = delta;
+        self
+    }
+    /// Request snapshots in batches of the given size (the default is 1, no batches), sent as
+    /// [`Packet::SnapshotBatch`]. Batches cut the per-packet framing and write overhead at fast
+    /// sampling rates, at the cost of latency: a snapshot is received when its batch is full
+    /// (e.g. up to 9ms later for a batch of 10 at 1ms sampling). Servers, which are older than
+    /// [`protocol::BATCH_VERSION`], send single snapshots
+    pub fn with_batch(mut self, batch: u32) -> Self {
+        self.batch = batch.max(1);
+        self
+    }
+    /// Request zstd-compressed packets. Servers, which are older than
+    /// [`protocol::COMPRESSION_VERSION`] or built without `compression` feature, send plain ones
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+    /// Request the metric (the name is without the scope prefix) at a coarser interval than the
+    /// sampling one, shifted by the phase. Snapshots still come at the sampling interval, but
+    /// contain the metric only when it is due
+    ///
+    /// # Panics
+    ///
+    /// Panics if the durations are too large to fit into a u64 (see
+    /// [`ClientSettings::try_with_metric_interval`]).
+    pub fn with_metric_interval<N: Into<String>>(
+        self,
+        name: N,
+        interval: Duration,
+        phase: Duration,
+    ) -> Self {
+        self.try_with_metric_interval(name, interval, phase)
+            .unwrap()
+    }
+    /// Request the metric at a coarser interval (see [`ClientSettings::with_metric_interval`])
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the durations in nanoseconds do not fit into a u64
+    pub fn try_with_metric_interval<N: Into<String>>(
+        mut self,
+        name: N,
+        interval: Duration,
+        phase: Duration,
+    ) -> Result<Self, Error> {
+        self.intervals.insert(
+            name.into(),
+            MetricInterval {
+                interval: duration_ns(interval, "metric interval")?,
+                phase: duration_ns(phase, "metric interval phase")?,
+            },
+        );
+        Ok(self)
+    }
+}
+
+/// Duration in nanoseconds, as sent in client settings
+fn duration_ns(duration: Duration, what: &str) -> Result<u64, Error> {
+    u64::try_from(duration.as_nanos()).map_err(|_| Error::Other(format!("{} is too large", what)))
+}
+
+fn compile_filter(filter: &str) -> Result<Regex, Error> {
+    Regex::new(filter).map_err(|e| Error::Other(format!("invalid metric filter: {}", e)))
+}
+
+/// Compiles glob patterns into a single anchored regular expression
+fn compile_patterns(patterns: &[String]) -> Result<Regex, Error> {
+    let mut expr = "^(?:".to_owned();
+    for (i, pattern) in patterns.iter().enumerate() {
+        if i > 0 {
+            expr.push('|');
+        }
+        for c in pattern.chars() {
+            match c {
+                '*' => expr.push_str(".*"),
+                '?' => expr.push('.'),
+                _ => expr.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+    }
+    expr.push_str(")$");
+    compile_filter(&expr)
+}
+
+/// Snapshot timestamp units
+///
+/// Coarser units are encoded as smaller integers, which saves bandwidth at the cost of the
+/// timestamp precision.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
+pub enum TimestampUnit {
+    /// Nanoseconds
+    #[default]
+    #[serde(rename = "ns")]
+    Nanos,
+    /// Microseconds
+    #[serde(rename = "us")]
+    Micros,
+    /// Milliseconds
+    #[serde(rename = "ms")]
+    Millis,
+}
+
+impl TimestampUnit {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_nanos(&self) -> bool {
+        *self == TimestampUnit::Nanos
+    }
+    fn nanos(self) -> u64 {
+        match self {
+            TimestampUnit::Nanos => 1,
+            TimestampUnit::Micros => 1_000,
+            TimestampUnit::Millis => 1_000_000,
+        }
+    }
+    /// Convert a timestamp to the unit (the result is kept in [`Monotonic`] as-is, so it is
+    /// valid for encoding only)
+    pub fn encode_ts(self, t: Monotonic) -> Monotonic {
+        let nanos = u64::try_from(t.as_nanos()).unwrap_or(u64::MAX);
+        Monotonic::from_nanos(nanos / self.nanos())
+    }
+    /// Convert a timestamp, received in the unit, back to nanoseconds
+    pub fn decode_ts(self, t: Monotonic) -> Monotonic {
+        let value = u64::try_from(t.as_nanos()).unwrap_or(u64::MAX);
+        Monotonic::from_nanos(value.saturating_mul(self.nanos()))
+    }
+}
+
+/// Information packet
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Info {
+    metrics: BTreeMap<String, MetricInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream_epoch: Option<u64>,
+    #[serde(default, skip_serializing_if = "TimestampUnit::is_nanos")]
+    timestamp_unit: TimestampUnit,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    truncated_from: Option<usize>,
+}
+
+impl Info {
+    /// Create an information packet (no stream epoch, nanosecond timestamps)
+    pub fn new(metrics: BTreeMap<String, MetricInfo>) -> Self {
+        Self {
+            metrics,
+            stream_epoch: None,
+            timestamp_unit: TimestampUnit::default(),
+            truncated_from: None,
+        }
+    }
+    /// Set the wall-clock time the sampling has been started at
+    pub fn with_stream_epoch(mut self, stream_epoch: SystemTime) -> Self {
+        self.stream_epoch = Some(
+            u64::try_from(
+                stream_epoch
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            )
+            .unwrap_or_default(),
+        );
+        self
+    }
+    /// Set the unit of snapshot timestamps, the snapshots must be encoded with it (see
+    /// [`TimestampUnit::encode_ts`])
+    pub fn with_timestamp_unit(mut self, timestamp_unit: TimestampUnit) -> Self {
+        self.timestamp_unit = timestamp_unit;
+        self
+    }
+    /// Get metrics metadata map
+    pub fn metrics(&self) -> &BTreeMap<String, MetricInfo> {
+        &self.metrics
+    }
+    /// Wall-clock time the server started sampling at (snapshot timestamps are relative to it)
+    pub fn stream_epoch(&self) -> Option<SystemTime> {
+        self.stream_epoch
+            .map(|nanos| UNIX_EPOCH + Duration::from_nanos(nanos))
+    }
+    /// Unit of the snapshot timestamps, sent to this client (see [`TimestampUnit::decode_ts`])
+    pub fn timestamp_unit(&self) -> TimestampUnit {
+        self.timestamp_unit
+    }
+    /// The number of metrics on the server, if the metrics have been truncated to the client's
+    /// limit (see [`ClientSettings::with_max_metrics`])
+    pub fn truncated_from(&self) -> Option<usize> {
+        self.truncated_from
+    }
+}
+
+/// Information delta packet
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct InfoDelta {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metrics: BTreeMap<String, MetricInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<String>,
+}
+
+impl InfoDelta {
+    fn between(
+        previous: &BTreeMap<String, MetricInfo>,
+        current: &BTreeMap<String, MetricInfo>,
+    ) -> Self {
+        Self {
+            metrics: current
+                .iter()
+                .filter(|(name, info)| previous.get(*name) != Some(*info))
+                .map(|(name, info)| (name.clone(), info.clone()))
+                .collect(),
+            removed: previous
+                .keys()
+                .filter(|name| !current.contains_key(*name))
+                .cloned()
+                .collect(),
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.metrics.is_empty() && self.removed.is_empty()
+    }
+    /// Added and changed metrics metadata map
+    pub fn metrics(&self) -> &BTreeMap<String, MetricInfo> {
+        &self.metrics
+    }
+    /// Names of removed metrics
+    pub fn removed(&self) -> &[String] {
+        &self.removed
+    }
+}
+
+/// Metric kind
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// Monotonic counter, the value is sent as f64
+    Counter,
+    /// Gauge (kinds, unknown to the client, are treated as gauges as well)
+    #[default]
+    #[serde(other)]
+    Gauge,
+}
+
+impl MetricKind {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_gauge(&self) -> bool {
+        *self == MetricKind::Gauge
+    }
+}
+
+/// Metrics metadata
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct MetricInfo {
+    labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "MetricKind::is_gauge")]
+    kind: MetricKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scale: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    render: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+}
+
+impl MetricInfo {
+    /// Create metric metadata from labels, the scale and the rendering hint are parsed from
+    /// `scale` and `render` ones
+    pub fn new(labels: BTreeMap<String, String>) -> Self {
+        let scale = labels
+            .get("scale")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0);
+        let render = labels.get("render").cloned();
+        Self {
+            labels,
+            kind: MetricKind::default(),
+            scale,
+            render,
+            unit: None,
+            description: None,
+        }
+    }
+    /// Set the metric kind (the default is [`MetricKind::Gauge`])
+    pub fn with_kind(mut self, kind: MetricKind) -> Self {
+        self.kind = kind;
+        self
+    }
+    /// Set the metric unit (e.g. `seconds`)
+    pub fn with_unit<U: Into<String>>(mut self, unit: U) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+    /// Set the metric description
+    pub fn with_description<D: Into<String>>(mut self, description: D) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+    /// Metric labels map
+    pub fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+    /// Metric kind
+    pub fn kind(&self) -> MetricKind {
+        self.kind
+    }
+    /// Fixed-point scale (e.g. 1000 if the metric value is in milliunits), set with `scale` label
+    pub fn scale(&self) -> Option<u64> {
+        self.scale
+    }
+    /// Rendering hint (e.g. `state` for discrete metrics), set with `render` label
+    pub fn render(&self) -> Option<&str> {
+        self.render.as_deref()
+    }
+    /// Metric unit (e.g. `seconds`), set with `describe_gauge!` or `describe_counter!`
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+    /// Metric description, set with `describe_gauge!` or `describe_counter!`
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+/// Snapshot packet
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    t: Monotonic,
+    d: BTreeMap<String, f64>,
+}
+
+impl Snapshot {
+    /// Create a snapshot packet
+    pub fn new(ts: Monotonic, data: BTreeMap<String, f64>) -> Self {
+        Self { t: ts, d: data }
+    }
+    /// Snapshot timestamp (monotonic, relative to the server sampling start)
+    pub fn ts(&self) -> Monotonic {
+        self.t
+    }
+    /// Set snapshot timestamp
+    pub fn set_ts(&mut self, t: Monotonic) {
+        self.t = t;
+    }
+    /// Snapshot data map (metric name -> value)
+    pub fn data(&self) -> &BTreeMap<String, f64> {
+        &self.d
+    }
+    /// Snapshot data map mutable (metric name -> value)
+    pub fn data_mut(&mut self) -> &mut BTreeMap<String, f64> {
+        &mut self.d
+    }
+    /// Take snapshot data map
+    pub fn take_data(&mut self) -> BTreeMap<String, f64> {
+        std::mem::take(&mut self.d)
+    }
+}
+
+/// Snapshot batch packet (since protocol version 8)
+///
+/// Several consecutive snapshots, sent in a single packet, if the client has requested batches
+/// (see [`ClientSettings::with_batch`])
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct SnapshotBatch {
+    s: Vec<Snapshot>,
+}
+
+impl SnapshotBatch {
+    /// Create a snapshot batch packet
+    pub fn new(snapshots: Vec<Snapshot>) -> Self {
+        Self { s: snapshots }
+    }
+    /// Snapshots of the batch, the oldest first
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.s
+    }
+    /// Mutable snapshots of the batch (e.g. to convert the timestamps)
+    pub fn snapshots_mut(&mut self) -> &mut [Snapshot] {
+        &mut self.s
+    }
+    /// Take the snapshots of the batch
+    pub fn into_snapshots(self) -> Vec<Snapshot> {
+        self.s
+    }
+}
+
+/// Timeline event packet
+///
+/// Discrete textual events (e.g. state machine transitions), emitted by the program with
+/// [`emit_event`].
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Event {
+    name: String,
+    label: String,
+    t: Monotonic,
+}
+
+impl Event {
+    /// Create an event packet
+    pub fn new<N: Into<String>, L: Into<String>>(ts: Monotonic, name: N, label: L) -> Self {
+        Self {
+            name: name.into(),
+            label: label.into(),
+            t: ts,
+        }
+    }
+    /// Event source name (e.g. a state machine)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Event label (e.g. the new state)
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+    /// Event timestamp (monotonic, relative to the server sampling start, the same as snapshot
+    /// ones)
+    pub fn ts(&self) -> Monotonic {
+        self.t
+    }
+    /// Set event timestamp
+    pub fn set_ts(&mut self, t: Monotonic) {
+        self.t = t;
+    }
+}
+
+/// Keep-alive packet
+///
+/// Sent by the server if there have been no snapshots for a while (e.g. the sampling interval is
+/// long), so both sides notice dead peers. Clients should ignore it.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct Ping {
+    t: Monotonic,
+}
+
+impl Ping {
+    /// Create a ping packet
+    pub fn new(ts: Monotonic) -> Self {
+        Self { t: ts }
+    }
+    /// Ping timestamp (monotonic, relative to the server sampling start, the same as snapshot
+    /// ones)
+    pub fn ts(&self) -> Monotonic {
+        self.t
+    }
+    /// Set ping timestamp
+    pub fn set_ts(&mut self, t: Monotonic) {
+        self.t = t;
+    }
+}
+
+struct EventBus {
+    start: Monotonic,
+    subscribers: Vec<mpsc::SyncSender<Arc<Event>>>,
+}
+
+// set when the exporter is installed
+static EVENT_BUS: Mutex<Option<EventBus>> = Mutex::new(None);
+
+/// Emit a timeline event to connected clients
+///
+/// The event is timestamped on the call and is delivered together with the next snapshot. Events,
+/// emitted before the exporter is installed or when no clients are connected, are dropped, as well
+/// as ones for clients which can not keep up.
+pub fn emit_event<N: Into<String>, L: Into<String>>(name: N, label: L) {
+    let mut bus = EVENT_BUS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let Some(bus) = bus.as_mut() else {
+        return;
+    };
+    if bus.subscribers.is_empty() {
+        return;
+    }
+    let event = Arc::new(Event::new(
+        Monotonic::from_nanos(u64::try_from(bus.start.elapsed().as_nanos()).unwrap_or(u64::MAX)),
+        name,
+        label,
+    ));
+    bus.subscribers.retain(|tx| {
+        !matches!(
+            tx.try_send(event.clone()),
+            Err(mpsc::TrySendError::Disconnected(_))
+        )
+    });
+}
+
+fn subscribe_events() -> mpsc::Receiver<Arc<Event>> {
+    let (tx, rx) = mpsc::sync_channel(EVENT_QUEUE_SIZE);
+    if let Some(bus) = EVENT_BUS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .as_mut()
+    {
+        bus.subscribers.push(tx);
+    }
+    rx
+}
+
+/// Scheduling of the exporter threads
+///
+/// Supported on Linux only. Failures to set the scheduling (e.g. missing privileges for real-time
+/// policies) are logged as warnings and are not fatal.
+#[derive(Clone, Copy, Debug)]
+pub enum Scheduling {
+    /// Normal scheduling (SCHED_OTHER) with the given nice value (-20..=19)
+    Nice(i32),
+    /// Real-time FIFO scheduling (SCHED_FIFO) with the given priority (1..=99)
+    Fifo(i32),
+    /// Real-time round-robin scheduling (SCHED_RR) with the given priority (1..=99)
+    RoundRobin(i32),
+}
+
+impl Scheduling {
+    #[cfg(target_os = "linux")]
+    fn apply(self) -> Result<(), std::io::Error> {
+        let (policy, priority) = match self {
+            Scheduling::Nice(nice) => {
+                // nice values are per-thread on Linux
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let tid = unsafe { libc::syscall(libc::SYS_gettid) } as libc::id_t;
+                if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                return Ok(());
+            }
+            Scheduling::Fifo(priority) => (libc::SCHED_FIFO, priority),
+            Scheduling::RoundRobin(priority) => (libc::SCHED_RR, priority),
+        };
+        let param = libc::sched_param {
+            sched_priority: priority,
+        };
+        match unsafe { libc::pthread_setschedparam(libc::pthread_self(), policy, &param) } {
+            0 => Ok(()),
+            code => Err(std::io::Error::from_raw_os_error(code)),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    fn apply(self) -> Result<(), std::io::Error> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "thread scheduling is supported on Linux only",
+        ))
+    }
+}
+
+/// Applies the scheduling to the current thread, errors are not fatal
+fn apply_scheduling(scheduling: Option<Scheduling>) {
+    if let Some(scheduling) = scheduling {
+        if let Err(error) = scheduling.apply() {
+            warn!(?scheduling, %error, "unable to set thread scheduling");
+        }
+    }
+}
+
+/// The default registry storage
+pub type DefaultStorage = GenerationalStorage<AtomicStorage>;
+
+/// Gauge storage of the exporter registry
+///
+/// Besides creating gauges, the storage must provide access to their values, as the exporter
+/// samples them on its own. Implemented for [`DefaultStorage`] and for plain [`AtomicStorage`]
+/// (without generation tracking, a bit lower overhead).
+pub trait ScopeStorage: Send + Sync + 'static {
+    /// Gauge type
+    type Gauge: metrics::GaugeFn + Clone + Send + Sync + 'static;
+    /// Create an empty gauge
+    fn gauge(&self, key: &Key) -> Self::Gauge;
+    /// Get a gauge value
+    fn gauge_value(gauge: &Self::Gauge) -> f64;
+    /// Set a gauge value
+    fn set_gauge_value(gauge: &Self::Gauge, value: f64);
+    /// Create a recorder handle for a gauge
+    fn gauge_handle(gauge: &Self::Gauge) -> metrics::Gauge;
+}
+
+impl ScopeStorage for DefaultStorage {
+    type Gauge = Generational<Arc<AtomicU64>>;
+    fn gauge(&self, key: &Key) -> Self::Gauge {
+        Storage::gauge(self, key)
+    }
+    fn gauge_value(gauge: &Self::Gauge) -> f64 {
+        f64::from_bits(gauge.get_inner().load(Ordering::Acquire))
+    }
+    fn set_gauge_value(gauge: &Self::Gauge, value: f64) {
+        gauge.get_inner().store(value.to_bits(), Ordering::Release);
+    }
+    fn gauge_handle(gauge: &Self::Gauge) -> metrics::Gauge {
+        gauge.clone().into()
+    }
+}
+
+impl ScopeStorage for AtomicStorage {
+    type Gauge = Arc<AtomicU64>;
+    fn gauge(&self, key: &Key) -> Self::Gauge {
+        Storage::gauge(self, key)
+    }
+    fn gauge_value(gauge: &Self::Gauge) -> f64 {
+        f64::from_bits(gauge.load(Ordering::Acquire))
+    }
+    fn set_gauge_value(gauge: &Self::Gauge, value: f64) {
+        gauge.store(value.to_bits(), Ordering::Release);
+    }
+    fn gauge_handle(gauge: &Self::Gauge) -> metrics::Gauge {
+        metrics::Gauge::from_arc(gauge.clone())
+    }
+}
+
+/// Registry storage, scope counters and histograms are kept in the plain atomic storage
+struct RegistryStorage<S>(S);
+
+impl<S: ScopeStorage> Storage<Key> for RegistryStorage<S> {
+    type Counter = <AtomicStorage as Storage<Key>>::Counter;
+    type Gauge = S::Gauge;
+    type Histogram = <AtomicStorage as Storage<Key>>::Histogram;
+    fn counter(&self, key: &Key) -> Self::Counter {
+        AtomicStorage.counter(key)
+    }
+    fn gauge(&self, key: &Key) -> Self::Gauge {
+        self.0.gauge(key)
+    }
+    fn histogram(&self, key: &Key) -> Self::Histogram {
+        AtomicStorage.histogram(key)
+    }
+}
+
+/// A metric handle of the coherent snapshot mode, updates are blocked while a snapshot is taken
+struct CoherentHandle<H> {
+    handle: H,
+    fence: Arc<RwLock<()>>,
+}
+
+impl<H: metrics::CounterFn> metrics::CounterFn for CoherentHandle<H> {
+    fn increment(&self, value: u64) {
+        let _fence = self
+            .fence
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.handle.increment(value);
+    }
+    fn absolute(&self, value: u64) {
+        let _fence = self
+            .fence
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.handle.absolute(value);
+    }
+}
+
+impl<H: metrics::HistogramFn> metrics::HistogramFn for CoherentHandle<H> {
+    fn record(&self, value: f64) {
+        let _fence = self
+            .fence
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.handle.record(value);
+    }
+}
+
+impl<H: metrics::GaugeFn> metrics::GaugeFn for CoherentHandle<H> {
+    fn increment(&self, value: f64) {
+        let _fence = self
+            .fence
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.handle.increment(value);
+    }
+    fn decrement(&self, value: f64) {
+        let _fence = self
+            .fence
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.handle.decrement(value);
+    }
+    fn set(&self, value: f64) {
+        let _fence = self
+            .fence
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        self.handle.set(value);
+    }
+}
+
+/// Exporter builder
+pub struct ScopeBuilder<S = DefaultStorage> {
+    addrs: Vec<SocketAddr>,
+    coherent_snapshots: bool,
+    histogram_quantiles: Vec<f64>,
+    max_clients: usize,
+    chat_timeout: Duration,
+    info_interval: Duration,
+    prefix: String,
+    openmetrics: bool,
+    fallback: Option<Box<dyn Recorder + Send + Sync>>,
+    server_thread_name: String,
+    scheduling: Option<Scheduling>,
+    status_page: Option<SocketAddr>,
+    prometheus_addr: Option<SocketAddr>,
+    metric_filter: Option<ServerMetricFilter>,
+    uds_path: Option<PathBuf>,
+    auth_token: Option<String>,
+    storage: S,
+}
+
+impl Default for ScopeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScopeBuilder {
+    /// Create a new exporter builder
+    pub fn new() -> Self {
+        Self {
+            addrs: vec![(std::net::Ipv4Addr::UNSPECIFIED, 5001).into()],
+            coherent_snapshots: false,
+            histogram_quantiles: DEFAULT_HISTOGRAM_QUANTILES.to_vec(),
+            max_clients: DEFAULT_MAX_CLIENTS,
+            chat_timeout: DEFAULT_CHAT_TIMEOUT,
+            info_interval: DEFAULT_INFO_INTERVAL,
+            prefix: DEFAULT_PREFIX.to_owned(),
+            openmetrics: false,
+            fallback: None,
+            server_thread_name: SERVER_THREAD_NAME.to_owned(),
+            scheduling: None,
+            status_page: None,
+            prometheus_addr: None,
+            metric_filter: None,
+            uds_path: None,
+            auth_token: None,
+            storage: GenerationalStorage::new(AtomicStorage),
+        }
+    }
+}
+
+impl<S: ScopeStorage> ScopeBuilder<S> {
+    /// Set the registry storage (the default is [`DefaultStorage`])
+    pub fn with_storage<T: ScopeStorage>(self, storage: T) -> ScopeBuilder<T> {
+        ScopeBuilder {
+            addrs: self.addrs,
+            coherent_snapshots: self.coherent_snapshots,
+            histogram_quantiles: self.histogram_quantiles,
+            max_clients: self.max_clients,
+            chat_timeout: self.chat_timeout,
+            info_interval: self.info_interval,
+            prefix: self.prefix,
+            openmetrics: self.openmetrics,
+            fallback: self.fallback,
+            server_thread_name: self.server_thread_name,
+            scheduling: self.scheduling,
+            status_page: self.status_page,
+            prometheus_addr: self.prometheus_addr,
+            metric_filter: self.metric_filter,
+            uds_path: self.uds_path,
+            auth_token: self.auth_token,
+            storage,
+        }
+    }
+    /// Set the server listening address and port
+    pub fn with_addr<A: Into<SocketAddr>>(mut self, addr: A) -> Self {
+        self.addrs = vec![addr.into()];
+        self
+    }
+    /// Set several server listening addresses (e.g. a management network one and localhost),
+    /// clients connected to any of them are served the same way
+    pub fn with_addrs<I, A>(mut self, addrs: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<SocketAddr>,
+    {
+        self.addrs = addrs.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Serve clients on a Unix domain socket instead of TCP, so no network port is opened (Unix
+    /// only). A stale socket file, left by a previous run, is removed on bind. TCP addresses, set
+    /// after the call, are served as well
+    #[cfg(all(unix, feature = "uds"))]
+    pub fn with_uds_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.uds_path = Some(path.into());
+        self.addrs.clear();
+        self
+    }
+    /// Set the fallback recorder
+    pub fn with_fallback(mut self, fallback: Box<dyn Recorder + Send + Sync>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+    /// Set the server thread name (the default is `MScopeSrv`)
+    pub fn with_server_thread_name<N: Into<String>>(mut self, name: N) -> Self {
+        self.server_thread_name = name.into();
+        self
+    }
+    /// Set scheduling of the server, sampler and per-client threads (Linux only)
+    pub fn with_scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = Some(scheduling);
+        self
+    }
+    /// Serve a tiny auto-refreshing HTML status page (current metric values and connected
+    /// clients) on the given address, for a quick look with a web browser
+    pub fn with_status_page<A: Into<SocketAddr>>(mut self, addr: A) -> Self {
+        self.status_page = Some(addr.into());
+        self
+    }
+    /// Serve the current scope metrics in the Prometheus text exposition format on the given
+    /// address (`GET /metrics`), so the scope metrics, which are never passed to the fallback
+    /// recorder, can be scraped as well
+    #[cfg(feature = "prometheus-endpoint")]
+    pub fn with_prometheus_addr<A: Into<SocketAddr>>(mut self, addr: A) -> Self {
+        self.prometheus_addr = Some(addr.into());
+        self
+    }
+    /// Export only the scope metrics, which names (without the scope prefix) match the include
+    /// regular expression and do not match the exclude one (the default is to export all)
+    ///
+    /// The filter is a server-wide policy: filtered metrics are still recorded by the program but
+    /// never leave the process, they are not sent to clients and subscribers and are not shown by
+    /// the status page and the HTTP endpoints. Histogram quantiles are matched by their names
+    /// (e.g. `latency.p99`). Clients can narrow the set further with
+    /// [`ClientSettings::with_filter`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a regular expression is invalid
+    pub fn with_metric_filter(
+        mut self,
+        include: Option<&str>,
+        exclude: Option<&str>,
+    ) -> Result<Self, Error> {
+        self.metric_filter = Some(ServerMetricFilter {
+            include: include.map(compile_filter).transpose()?,
+            exclude: exclude.map(compile_filter).transpose()?,
+            matches: <_>::default(),
+        });
+        Ok(self)
+    }
+    /// Take coherent snapshots: all values of a snapshot are read at the same instant, no gauge
+    /// is updated in the middle of sampling (the default is `false`)
+    ///
+    /// By default, gauges are read one by one, so a snapshot may mix values of metrics, updated
+    /// by the program right before and right after the sampling has been started. With the
+    /// option enabled, gauge updates take a shared lock and the sampler takes it exclusively while
+    /// reading the values. Updates become a bit slower and may be delayed for the time of
+    /// sampling (microseconds for hundreds of metrics), so the option is recommended only for
+    /// tightly-coupled signals, e.g. inputs and outputs of a control loop. A group of gauges,
+    /// updated by the program one by one, is still not atomic.
+    pub fn with_coherent_snapshots(mut self, coherent_snapshots: bool) -> Self {
+        self.coherent_snapshots = coherent_snapshots;
+        self
+    }
+    /// Require clients to authenticate with the shared secret token, sent right after the server
+    /// version, before any metrics information. Connections with a missing or a wrong token are
+    /// closed. The token is sent in plain text, so the connection should be protected as well
+    /// (e.g. with a VPN or an SSH tunnel) if the network is not trusted
+    ///
+    /// OpenMetrics requests on the scope port are rejected, if the token is set. The status page
+    /// and the Prometheus endpoint are not authenticated, so these should not be enabled on
+    /// untrusted networks.
+    pub fn with_auth_token<T: Into<String>>(mut self, token: T) -> Self {
+        self.auth_token = Some(token.into());
+        self
+    }
+    /// Set the maximum number of concurrent client connections (the default is 16), each one is
+    /// served by a dedicated thread
+    ///
+    /// Connections over the limit get the server version and are closed right away, so clients
+    /// may retry later.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+    /// Set the write timeout of the client handshake (the default is 60 seconds, zero disables
+    /// the timeout)
+    ///
+    /// The client token and settings are always read within 5 seconds (or within the chat
+    /// timeout, if shorter), so idle peers do not hold client slots.
+    pub fn with_chat_timeout(mut self, timeout: Duration) -> Self {
+        self.chat_timeout = timeout;
+        self
+    }
+    /// Set the interval information packets are re-sent to clients at (the default is 5 seconds),
+    /// so clients learn about new metrics and metadata changes
+    ///
+    /// Zero means the information packet is sent on connect only, connected clients are not
+    /// informed about metrics, which appear later. Useful for programs with thousands of metrics,
+    /// which are all registered at startup.
+    pub fn with_info_interval(mut self, interval: Duration) -> Self {
+        self.info_interval = interval;
+        self
+    }
+    /// Set the scope metric name prefix (the default is `~`), the prefix is stripped from metric
+    /// names, sent to clients
+    ///
+    /// Metrics without the prefix are passed to the fallback recorder. An empty prefix makes all
+    /// metrics scope ones, a metric, named exactly as the prefix, is not a scope one.
+    pub fn with_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+    /// Set quantiles, calculated for scope histograms (the default is `0.5`, `0.9` and `0.99`)
+    ///
+    /// Each quantile is sent as a separate metric, named after the histogram with a suffix (e.g.
+    /// `latency.p50`, `latency.p99.9` for `0.999`). The quantiles are calculated from the samples,
+    /// recorded during the sampling interval, if no samples have been recorded, the values are NaN.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a quantile is not in the range `0.0..=1.0`
+    pub fn with_histogram_quantiles(mut self, quantiles: &[f64]) -> Self {
+        assert!(
+            quantiles.iter().all(|q| (0.0..=1.0).contains(q)),
+            "histogram quantiles must be in the range 0.0..=1.0"
+        );
+        self.histogram_quantiles = quantiles.to_vec();
+        self
+    }
+    /// Serve HTTP GET requests on the scope port with a one-shot OpenMetrics text rendering of
+    /// the current scope metrics, so simple scrapers can read them (the default is `false`)
+    ///
+    /// Native clients wait for the server first, so a connection is treated as a native one if it
+    /// sends nothing in 100ms, which delays native connections by this time.
+    pub fn with_openmetrics(mut self, openmetrics: bool) -> Self {
+        self.openmetrics = openmetrics;
+        self
+    }
+    /// Build the exporter's recorder
+    pub fn build(self) -> ScopeRecorder<S> {
+        ScopeRecorder::build(self)
+    }
+    /// Build the exporter's recorder and install it as the global recorder
+    pub fn install(self) -> Result<(), Error> {
+        self.build().install()
+    }
+    /// Build the exporter's recorder and install it as the global recorder, the exporter failures
+    /// are not fatal (see [`ScopeRecorder::install_lenient`])
+    pub fn install_lenient(self) -> Result<(), Error> {
+        self.build().install_lenient()
+    }
+}
+
+/// Size and cost of a single snapshot, see [`ScopeRecorder::estimate_snapshot_size`]
+#[derive(Clone, Debug)]
+pub struct SnapshotEstimate {
+    size: usize,
+    build_time: Duration,
+    metrics: usize,
+}
+
+impl SnapshotEstimate {
+    /// Serialized snapshot packet size in bytes (the current protocol version)
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// Time spent to sample the gauges and serialize the snapshot
+    pub fn build_time(&self) -> Duration {
+        self.build_time
+    }
+    /// Number of metrics in the snapshot
+    pub fn metrics(&self) -> usize {
+        self.metrics
+    }
+    /// Estimated bandwidth (bytes per second) of a client with the given sampling interval and
+    /// no server-side throttling
+    pub fn bytes_per_second(&self, sampling_interval: Duration) -> f64 {
+        if sampling_interval.is_zero() {
+            return f64::INFINITY;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let size = self.size as f64;
+        size / sampling_interval.as_secs_f64()
+    }
+}
+
+/// Scope recorder
+pub struct ScopeRecorder<S: ScopeStorage = DefaultStorage> {
+    inner: Arc<Inner<S>>,
+    fallback: Arc<Option<Box<dyn Recorder + Send + Sync>>>,
+    // scope metrics are ignored, set if the exporter has failed to start in the lenient mode
+    disabled: bool,
+}
+
+impl<S: ScopeStorage> Clone for ScopeRecorder<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            fallback: self.fallback.clone(),
+            disabled: self.disabled,
+        }
+    }
+}
+
+impl<S: ScopeStorage> ScopeRecorder<S> {
+    fn build(builder: ScopeBuilder<S>) -> Self {
+        Self {
+            inner: Arc::new(Inner::new(
+                builder.addrs,
+                builder.coherent_snapshots,
+                &builder.histogram_quantiles,
+                builder.max_clients,
+                builder.chat_timeout,
+                builder.info_interval,
+                builder.prefix,
+                builder.openmetrics,
+                builder.server_thread_name,
+                builder.scheduling,
+                builder.status_page,
+                builder.prometheus_addr,
+                builder.metric_filter,
+                builder.uds_path,
+                builder.auth_token,
+                builder.storage,
+            )),
+            fallback: builder.fallback.into(),
+            disabled: false,
+        }
+    }
+    /// # Panics
+    ///
+    /// Panics if the global recorder has already been set.
+    pub fn install(self) -> Result<(), Error> {
+        let inner = self.inner.clone();
+        if let Err(error) = self.spawn_tasks() {
+            inner.shutdown();
+            return Err(error.into());
+        }
+        if let Err(error) = metrics::set_global_recorder(self) {
+            inner.shutdown();
+            return Err(error.into());
+        }
+        // the sampler is started last, so it is not left behind if the recorder is not installed
+        if let Err(error) = inner.spawn_sampler() {
+            inner.shutdown();
+            return Err(error.into());
+        }
+        Ok(())
+    }
+    /// Installs the recorder as the global one, even if the exporter can not be started (e.g. the
+    /// server port is busy). In this case a warning is logged, scope metrics are ignored and the
+    /// rest are still forwarded to the fallback recorder. Suitable for field devices, where the
+    /// scope is a best-effort diagnostic tool and must not prevent the program from starting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the global recorder has already been set
+    pub fn install_lenient(mut self) -> Result<(), Error> {
+        if let Err(error) = self.spawn_tasks() {
+            warn!(error = %error, "unable to start scope exporter, scope metrics are disabled");
+            // listeners, which have been already started, are stopped
+            self.inner.shutdown();
+            self.disabled = true;
+        }
+        let inner = self.inner.clone();
+        let disabled = self.disabled;
+        if let Err(error) = metrics::set_global_recorder(self) {
+            inner.shutdown();
+            return Err(error.into());
+        }
+        if !disabled {
+            if let Err(error) = inner.spawn_sampler() {
+                warn!(error = %error, "unable to start scope sampler, scope metrics are not sent");
+                inner.shutdown();
+            }
+        }
+        Ok(())
+    }
+    /// The address the server is actually bound to (e.g. with an OS-assigned port if the port is
+    /// set to 0), the first one if there are several, `None` if the server has not been
+    /// started or has been stopped
+    ///
+    /// The server is started when the recorder is installed, the recorder is consumed, so the
+    /// address should be read from a clone:
+    ///
+    /// ```rust,no_run
+    /// use metrics_exporter_scope::ScopeBuilder;
+    ///
+    /// let recorder = ScopeBuilder::new()
+    ///     .with_addr(([127, 0, 0, 1], 0))
+    ///     .build();
+    /// recorder.clone().install().unwrap();
+    /// let addr = recorder.local_addr().unwrap();
+    /// ```
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.inner.local_addrs.lock().unwrap().first().copied()
+    }
+    /// All addresses the server is actually bound to, in the order of [`ScopeBuilder::with_addrs`]
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.inner.local_addrs.lock().unwrap().clone()
+    }
+    /// Builds and serializes a snapshot of the current metrics, the same way as it is done for
+    /// connected clients, and returns its size and the time spent. Useful to choose sampling
+    /// intervals and bandwidth limits. Clients are not affected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the snapshot can not be serialized
+    pub fn estimate_snapshot_size(&self) -> Result<SnapshotEstimate, Error> {
+        let started = Instant::now();
+        let snapshot = self.inner.current_snapshot();
+        let mut buf = Vec::new();
+        protocol::write_snapshot(&mut buf, &snapshot)?;
+        Ok(SnapshotEstimate {
+            size: buf.len(),
+            build_time: started.elapsed(),
+            metrics: snapshot.data().len(),
+        })
+    }
+    /// Samples the scope metrics right away and returns the snapshot, without a client connection
+    /// (e.g. to log all metrics on a panic or to answer a health check). Gauges are read with
+    /// `Ordering::Acquire`, the same way as for connected clients. Histograms are not drained,
+    /// their quantiles are calculated from the samples collected since the last sampler tick.
+    pub fn current_snapshot(&self) -> Snapshot {
+        self.inner.current_snapshot()
+    }
+    /// Returns the information (kinds, labels, units and descriptions) of the current scope
+    /// metrics, the same as sent to connected clients. Timestamps are in nanoseconds.
+    pub fn current_info(&self) -> Info {
+        self.inner.info(TimestampUnit::default(), None, None)
+    }
+    /// Subscribes to snapshots of the scope metrics in-process, without a client connection.
+    /// Snapshots are taken by the same sampler as the ones sent to clients, so all subscribers
+    /// with the same interval get identical data and timestamps (since the exporter start).
+    ///
+    /// Snapshots are delivered after the recorder has been installed. A subscriber, which does
+    /// not keep up, misses snapshots (counted in the `mscope_dropped_snapshots` self-metric), the
+    /// sampler is never blocked. The subscription is cancelled when the receiver is dropped, the
+    /// receiver is closed if the exporter is disabled or stopped.
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    ///
+    /// use metrics_exporter_scope::ScopeBuilder;
+    ///
+    /// let recorder = ScopeBuilder::new().build();
+    /// let snapshots = recorder.subscribe(Duration::from_millis(100)).unwrap();
+    /// recorder.install().unwrap();
+    /// for snapshot in snapshots {
+    ///     println!("{:?}", snapshot.data());
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the interval is zero or does not fit into a u64 in nanoseconds
+    pub fn subscribe(&self, interval: Duration) -> Result<mpsc::Receiver<Arc<Snapshot>>, Error> {
+        let interval_ns = u64::try_from(interval.as_nanos())
+            .ok()
+            .filter(|interval_ns| *interval_ns > 0)
+            .ok_or_else(|| Error::Other("invalid sampling interval".to_owned()))?;
+        Ok(self.inner.subscribe(interval_ns))
+    }
+    /// Scope metrics are ignored if the exporter has failed to start or has been stopped
+    fn is_disabled(&self) -> bool {
+        self.disabled || self.inner.shutdown.load(Ordering::Acquire)
+    }
+    fn spawn_tasks(&self) -> Result<(), std::io::Error> {
+        EVENT_BUS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .replace(EventBus {
+                start: self.inner.start,
+                subscribers: Vec::new(),
+            });
+        for addr in &self.inner.addrs {
+            let local_addr = self.inner.spawn_server(*addr)?;
+            self.inner.local_addrs.lock().unwrap().push(local_addr);
+        }
+        #[cfg(all(unix, feature = "uds"))]
+        if let Some(ref path) = self.inner.uds_path {
+            self.inner.spawn_unix_server(path)?;
+        }
+        if let Some(addr) = self.inner.status_page {
+            self.inner.spawn_status_page(addr)?;
+        }
+        #[cfg(feature = "prometheus-endpoint")]
+        if let Some(addr) = self.inner.prometheus_addr {
+            self.inner.spawn_prometheus_endpoint(addr)?;
+        }
+        Ok(())
+    }
+}
+
+struct Subscriber {
+    interval_ns: u64,
+    next_due: u64,
+    // histogram samples of the sampler ticks, skipped since the last snapshot sent
+    histograms: BTreeMap<String, Vec<f64>>,
+    tx: mpsc::SyncSender<Arc<Snapshot>>,
+}
+
+/// A connected client, listed on the status page
+struct ClientStatus {
+    sampling_interval: u64,
+    connected_at: Instant,
+}
+
+/// Histogram quantile metric suffix, e.g. `.p50` for `0.5`, `.p99.9` for `0.999`
+fn quantile_suffix(q: f64) -> String {
+    format!(".p{}", (q * 100_000.0).round() / 1000.0)
+}
+
+/// Nearest-rank quantile of sorted samples, NaN if there are no samples
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// A running accept loop (the server or the status page one)
+struct Listener {
+    // the bound address, used to wake up the loop on shutdown
+    addr: ListenerAddr,
+    thread: thread::JoinHandle<()>,
+}
+
+enum ListenerAddr {
+    Tcp(SocketAddr),
+    // the socket file is removed on shutdown
+    #[cfg(all(unix, feature = "uds"))]
+    Unix(PathBuf),
+}
+
+/// A client peer, listed on the status page and logged
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+enum Peer {
+    Tcp(SocketAddr),
+    // Unix socket peers are unnamed, so the connections are numbered
+    #[cfg(all(unix, feature = "uds"))]
+    Unix(u64),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(all(unix, feature = "uds"))]
+            Peer::Unix(n) => write!(f, "unix#{}", n),
+        }
+    }
+}
+
+/// A client connection, TCP and Unix socket ones are served the same way
+trait ClientStream: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+    fn shutdown(&self) -> std::io::Result<()>;
+}
+
+impl ClientStream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+    fn shutdown(&self) -> std::io::Result<()> {
+        TcpStream::shutdown(self, std::net::Shutdown::Both)
+    }
+}
+
+#[cfg(all(unix, feature = "uds"))]
+impl ClientStream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+    fn shutdown(&self) -> std::io::Result<()> {
+        UnixStream::shutdown(self, std::net::Shutdown::Both)
+    }
+}
+
+struct Inner<S: ScopeStorage = DefaultStorage> {
+    registry: Registry<Key, RegistryStorage<S>>,
+    addrs: Vec<SocketAddr>,
+    listeners: Mutex<Vec<Listener>>,
+    // the server addresses, actually bound
+    local_addrs: Mutex<Vec<SocketAddr>>,
+    max_clients: usize,
+    // write timeout of the handshake
+    chat_timeout: Duration,
+    // zero - the info is sent on connect only
+    info_interval: Duration,
+    // client connections being handled (including version probes and OpenMetrics requests)
+    active_clients: Arc<AtomicUsize>,
+    // taken exclusively by the sampler in the coherent snapshot mode
+    fence: Option<Arc<RwLock<()>>>,
+    // scope metric name prefix
+    prefix: String,
+    // quantile -> metric name suffix (e.g. `.p99`)
+    histogram_quantiles: Vec<(f64, String)>,
+    openmetrics: bool,
+    server_thread_name: String,
+    scheduling: Option<Scheduling>,
+    status_page: Option<SocketAddr>,
+    #[cfg_attr(not(feature = "prometheus-endpoint"), allow(dead_code))]
+    prometheus_addr: Option<SocketAddr>,
+    metric_filter: Option<ServerMetricFilter>,
+    #[cfg_attr(not(all(unix, feature = "uds")), allow(dead_code))]
+    uds_path: Option<PathBuf>,
+    auth_token: Option<String>,
+    start: Monotonic,
+    stream_epoch: u64,
+    subscribers: Mutex<Vec<Subscriber>>,
+    subscribers_changed: Condvar,
+    // metric name (without the prefix) -> unit and description
+    descriptions: Mutex<BTreeMap<String, (Option<metrics::Unit>, String)>>,
+    clients: Mutex<BTreeMap<Peer, ClientStatus>>,
+    shutdown: AtomicBool,
+}
+
+impl<S: ScopeStorage> Inner<S> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        addrs: Vec<SocketAddr>,
+        coherent_snapshots: bool,
+        histogram_quantiles: &[f64],
+        max_clients: usize,
+        chat_timeout: Duration,
+        info_interval: Duration,
+        prefix: String,
+        openmetrics: bool,
+        server_thread_name: String,
+        scheduling: Option<Scheduling>,
+        status_page: Option<SocketAddr>,
+        prometheus_addr: Option<SocketAddr>,
+        metric_filter: Option<ServerMetricFilter>,
+        uds_path: Option<PathBuf>,
+        auth_token: Option<String>,
+        storage: S,
+    ) -> Self {
+        let registry = Registry::new(RegistryStorage(storage));
+        Self {
+            registry,
+            addrs,
+            listeners: <_>::default(),
+            local_addrs: <_>::default(),
+            max_clients,
+            chat_timeout,
+            info_interval,
+            active_clients: <_>::default(),
+            fence: coherent_snapshots.then(|| Arc::new(RwLock::new(()))),
+            histogram_quantiles: histogram_quantiles
+                .iter()
+                .map(|q| (*q, quantile_suffix(*q)))
+                .collect(),
+            prefix,
+            openmetrics,
+            server_thread_name,
+            scheduling,
+            status_page,
+            prometheus_addr,
+            metric_filter,
+            uds_path,
+            auth_token,
+            start: Monotonic::now(),
+            stream_epoch: u64::try_from(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            )
+            .unwrap_or_default(),
+            subscribers: <_>::default(),
+            subscribers_changed: Condvar::new(),
+            descriptions: <_>::default(),
+            clients: <_>::default(),
+            shutdown: AtomicBool::new(false),
+        }
+    }
+    /// Samples the gauges
+    ///
+    /// Histogram quantiles are calculated from the samples, recorded since the previous drain,
+    /// only the sampler drains histograms (the drained samples are collected into the given map,
+    /// sorted).
+    fn snapshot(&self, t: Monotonic, drained: Option<&mut BTreeMap<String, Vec<f64>>>) -> Snapshot {
+        let mut map = BTreeMap::new();
+        let _fence = self.fence.as_ref().map(|fence| {
+            fence
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+        });
+        self.registry.visit_gauges(|key, gauge| {
+            let name = self.scope_name(key);
+            if !self.is_exported(name) {
+                return;
+            }
+            let value = S::gauge_value(gauge);
+            map.insert(name.to_string(), value);
+        });
+        self.registry.visit_counters(|key, counter| {
+            #[allow(clippy::cast_precision_loss)]
+            let value = counter.load(Ordering::Acquire) as f64;
+            let name = self.scope_name(key);
+            if self.is_exported(name) {
+                map.insert(name.to_string(), value);
+            }
+        });
+        let mut drained = drained;
+        self.registry.visit_histograms(|key, bucket| {
+            let mut samples = Vec::new();
+            if drained.is_some() {
+                bucket.clear_with(|chunk| samples.extend_from_slice(chunk));
+            } else {
+                samples = bucket.data();
+            }
+            samples.retain(|value| !value.is_nan());
+            samples.sort_unstable_by(f64::total_cmp);
+            let name = self.scope_name(key);
+            for (q, suffix) in &self.histogram_quantiles {
+                let quantile_name = format!("{}{}", name, suffix);
+                if self.is_exported(&quantile_name) {
+                    map.insert(quantile_name, quantile(&samples, *q));
+                }
+            }
+            if let Some(ref mut drained) = drained {
+                drained.insert(name.to_owned(), samples);
+            }
+        });
+        Snapshot { t, d: map }
+    }
+    /// Checks the metric against the server-wide filter
+    fn is_exported(&self, name: &str) -> bool {
+        self.metric_filter
+            .as_ref()
+            .map_or(true, |filter| filter.is_match(name))
+    }
+    /// Samples the gauges out of the sampler schedule (e.g. for the status page)
+    fn current_snapshot(&self) -> Snapshot {
+        self.snapshot(
+            Monotonic::from_nanos(
+                u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX),
+            ),
+            None,
+        )
+    }
+    /// Recalculates histogram quantiles of a snapshot from the samples of several sampler ticks
+    /// (for subscribers with intervals, coarser than the sampler one)
+    fn with_histograms(
+        &self,
+        snapshot: &Snapshot,
+        histograms: BTreeMap<String, Vec<f64>>,
+    ) -> Snapshot {
+        let mut snapshot = snapshot.clone();
+        for (name, mut samples) in histograms {
+            samples.sort_unstable_by(f64::total_cmp);
+            for (q, suffix) in &self.histogram_quantiles {
+                if let Some(value) = snapshot.d.get_mut(&format!("{}{}", name, suffix)) {
+                    *value = quantile(&samples, *q);
+                }
+            }
+        }
+        snapshot
+    }
+    fn info(
+        &self,
+        timestamp_unit: TimestampUnit,
+        filter: Option<&MetricFilter>,
+        max_metrics: Option<usize>,
+    ) -> Info {
+        let descriptions = self.descriptions.lock().unwrap();
+        let is_allowed = |name: &str| {
+            self.is_exported(name) && filter.map_or(true, |filter| filter.is_match(name))
+        };
+        let metric_info = |key: &Key, kind: MetricKind, default_plot: Option<&str>| {
+            let mut labels: BTreeMap<String, String> = key
+                .labels()
+                .map(|label| (label.key().to_owned(), label.value().to_owned()))
+                .collect();
+            if let Some(plot) = default_plot {
+                labels
+                    .entry("plot".to_owned())
+                    .or_insert_with(|| plot.to_owned());
+            }
+            let name = self.scope_name(key);
+            let mut info = MetricInfo::new(labels).with_kind(kind);
+            if let Some((unit, description)) = descriptions.get(name) {
+                if let Some(unit) = unit {
+                    info = info.with_unit(unit.as_str());
+                }
+                if !description.is_empty() {
+                    info = info.with_description(description.as_str());
+                }
+            }
+            info
+        };
+        let mut info: BTreeMap<String, MetricInfo> = self
+            .registry
+            .get_gauge_handles()
+            .keys()
+            .filter(|key| is_allowed(self.scope_name(key)))
+            .map(|key| {
+                (
+                    self.scope_name(key).to_owned(),
+                    metric_info(key, MetricKind::Gauge, None),
+                )
+            })
+            .collect();
+        info.extend(
+            self.registry
+                .get_counter_handles()
+                .keys()
+                .filter(|key| is_allowed(self.scope_name(key)))
+                .map(|key| {
+                    (
+                        self.scope_name(key).to_owned(),
+                        metric_info(key, MetricKind::Counter, None),
+                    )
+                }),
+        );
+        // histogram quantiles are sent as gauges, plotted together by default
+        for key in self.registry.get_histogram_handles().keys() {
+            let name = self.scope_name(key);
+            for (_, suffix) in &self.histogram_quantiles {
+                let quantile_name = format!("{}{}", name, suffix);
+                if is_allowed(&quantile_name) {
+                    info.insert(
+                        quantile_name,
+                        metric_info(key, MetricKind::Gauge, Some(name)),
+                    );
+                }
+            }
+        }
+        let mut truncated_from = None;
+        if let Some(max_metrics) = max_metrics.filter(|max_metrics| info.len() > *max_metrics) {
+            truncated_from = Some(info.len());
+            let mut by_priority: Vec<(i64, String)> = info
+                .iter()
+                .map(|(name, metric)| {
+                    let priority = metric
+                        .labels()
+                        .get("priority")
+                        .and_then(|priority| priority.parse().ok())
+                        .unwrap_or_default();
+                    (priority, name.clone())
+                })
+                .collect();
+            // the highest priority first, then by name, so the selection is stable
+            by_priority.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+            for (_, name) in by_priority.into_iter().skip(max_metrics) {
+                info.remove(&name);
+            }
+        }
+        Info {
+            metrics: info,
+            stream_epoch: Some(self.stream_epoch),
+            timestamp_unit,
+            truncated_from,
+        }
+    }
+    /// Subscribes to the shared snapshots, decimated to the requested interval
+    fn subscribe(&self, interval_ns: u64) -> mpsc::Receiver<Arc<Snapshot>> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE_SIZE);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        // after the shutdown the sender is dropped, so the receiver is closed at once
+        if !self.shutdown.load(Ordering::Acquire) {
+            subscribers.push(Subscriber {
+                interval_ns,
+                next_due: 0,
+                histograms: BTreeMap::new(),
+                tx,
+            });
+            self.subscribers_changed.notify_one();
+        }
+        rx
+    }
+    /// Disconnects all clients and stops the server
+    fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::Release);
+        self.subscribers.lock().unwrap().clear();
+        // wake up the sampler, so it exits
+        self.subscribers_changed.notify_all();
+        self.local_addrs.lock().unwrap().clear();
+        // wake up the accept loops and wait until they are finished
+        let listeners = std::mem::take(&mut *self.listeners.lock().unwrap());
+        for Listener { addr, thread } in listeners {
+            let woken = match addr {
+                ListenerAddr::Tcp(mut addr) => {
+                    if addr.ip().is_unspecified() {
+                        if addr.is_ipv4() {
+                            addr.set_ip(std::net::Ipv4Addr::LOCALHOST.into());
+                        } else {
+                            addr.set_ip(std::net::Ipv6Addr::LOCALHOST.into());
+                        }
+                    }
+                    TcpStream::connect_timeout(&addr, Duration::from_secs(1)).is_ok()
+                }
+                #[cfg(all(unix, feature = "uds"))]
+                ListenerAddr::Unix(path) => {
+                    let woken = UnixStream::connect(&path).is_ok();
+                    std::fs::remove_file(&path).ok();
+                    woken
+                }
+            };
+            // a loop, which can not be woken up, is left behind
+            if woken {
+                thread.join().ok();
+            }
+        }
+    }
+    fn add_listener(&self, addr: SocketAddr, thread: thread::JoinHandle<()>) {
+        self.listeners.lock().unwrap().push(Listener {
+            addr: ListenerAddr::Tcp(addr),
+            thread,
+        });
+    }
+    /// Scope metric name without the prefix, `None` if the metric is not a scope one
+    fn strip_prefix<'a>(&self, name: &'a str) -> Option<&'a str> {
+        name.strip_prefix(self.prefix.as_str())
+            .filter(|name| !name.is_empty())
+    }
+    /// Name of a registered metric without the prefix (all metrics in the registry are scope
+    /// ones)
+    fn scope_name<'a>(&self, key: &'a Key) -> &'a str {
+        &key.name()[self.prefix.len()..]
+    }
+    /// Sets an exporter self-metric, the metric is created on the first call
+    fn set_self_metric(&self, name: &str, value: f64) {
+        self.registry.get_or_create_gauge(
+            &Key::from_name(format!("{}{}", self.prefix, name)),
+            |gauge| {
+                S::set_gauge_value(gauge, value);
+            },
+        );
+    }
+    /// Samples the registry at the finest interval requested by subscribers, so all of them get
+    /// identical data and timestamps
+    fn run_sampler(&self) {
+        let mut last_tick: Option<u64> = None;
+        let mut missed_ticks = 0u64;
+        let mut dropped_snapshots = 0u64;
+        let mut last_warned: Option<Instant> = None;
+        loop {
+            let interval_ns = {
+                let mut subscribers = self.subscribers.lock().unwrap();
+                loop {
+                    if self.shutdown.load(Ordering::Acquire) {
+                        return;
+                    }
+                    if let Some(interval_ns) = subscribers.iter().map(|s| s.interval_ns).min() {
+                        break interval_ns;
+                    }
+                    let (guard, timeout) = self
+                        .subscribers_changed
+                        .wait_timeout(subscribers, IDLE_HISTOGRAM_CLEAR_INTERVAL)
+                        .unwrap();
+                    subscribers = guard;
+                    if timeout.timed_out() {
+                        // nobody drains histograms while there are no clients
+                        self.registry.visit_histograms(|_, bucket| bucket.clear());
+                    }
+                }
+            };
+            let elapsed = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+            let tick = next_tick(elapsed, interval_ns);
+            // ticks, which are already late, are skipped rather than caught up
+            let missed = last_tick.map_or(0, |last_tick| {
+                tick.saturating_sub(last_tick).saturating_sub(1) / interval_ns
+            });
+            last_tick = Some(tick);
+            if missed > 0 {
+                missed_ticks += missed;
+                #[allow(clippy::cast_precision_loss)]
+                self.set_self_metric(MISSED_TICKS_METRIC, missed_ticks as f64);
+                if last_warned.map_or(true, |t| t.elapsed() >= BEHIND_SCHEDULE_LOG_INTERVAL) {
+                    warn!(
+                        missed_ticks,
+                        interval_ns, "sampler is behind schedule, ticks skipped"
+                    );
+                    last_warned = Some(Instant::now());
+                }
+            }
+            thread::sleep(Duration::from_nanos(tick.saturating_sub(elapsed)));
+            let mut drained = BTreeMap::new();
+            let snapshot = Arc::new(self.snapshot(Monotonic::from_nanos(tick), Some(&mut drained)));
+            let dropped = self.deliver(tick, &snapshot, &drained);
+            if dropped > 0 {
+                dropped_snapshots += dropped;
+                #[allow(clippy::cast_precision_loss)]
+                self.set_self_metric(DROPPED_SNAPSHOTS_METRIC, dropped_snapshots as f64);
+            }
+        }
+    }
+    /// Sends a snapshot, taken at the tick, to the due subscribers, returns the number of
+    /// snapshots dropped for slow subscribers
+    fn deliver(
+        &self,
+        tick: u64,
+        snapshot: &Arc<Snapshot>,
+        drained: &BTreeMap<String, Vec<f64>>,
+    ) -> u64 {
+        let mut dropped = 0;
+        self.subscribers.lock().unwrap().retain_mut(|subscriber| {
+            // histogram samples are accumulated, so subscribers with coarser intervals get
+            // quantiles of their whole intervals
+            let due = tick >= subscriber.next_due;
+            if !due || !subscriber.histograms.is_empty() {
+                for (name, samples) in drained.iter().filter(|(_, s)| !s.is_empty()) {
+                    subscriber
+                        .histograms
+                        .entry(name.clone())
+                        .or_default()
+                        .extend_from_slice(samples);
+                }
+            }
+            if !due {
+                return true;
+            }
+            subscriber.next_due = next_tick(tick, subscriber.interval_ns);
+            let snapshot = if subscriber.histograms.is_empty() {
+                snapshot.clone()
+            } else {
+                Arc::new(self.with_histograms(snapshot, std::mem::take(&mut subscriber.histograms)))
+            };
+            // a slow subscriber misses the snapshot, a gone one is removed
+            match subscriber.tx.try_send(snapshot) {
+                Ok(()) => true,
+                Err(mpsc::TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => false,
+            }
+        });
+        dropped
+    }
+    fn spawn_sampler(self: &Arc<Self>) -> Result<(), std::io::Error> {
+        let metrics_scope = self.clone();
+        thread::Builder::new()
+            .name(SAMPLER_THREAD_NAME.to_owned())
+            .spawn(move || {
+                apply_scheduling(metrics_scope.scheduling);
+                metrics_scope.run_sampler();
+            })?;
         Ok(())
     }
-
-    /// Read a packet from a stream
-    pub fn read_packet<R>(stream: R) -> Result<Packet, Error>
-    where
-        R: Read,
-    {
-        read(stream)
+    /// Returns the bound address (differs from the requested one if the port is 0)
+    fn spawn_server(self: &Arc<Self>, addr: SocketAddr) -> Result<SocketAddr, std::io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let metrics_scope = self.clone();
+        let thread = thread::Builder::new()
+            .name(self.server_thread_name.clone())
+            .spawn(move || {
+                apply_scheduling(metrics_scope.scheduling);
+                while let Ok((stream, addr)) = listener.accept() {
+                    if metrics_scope.shutdown.load(Ordering::Acquire) {
+                        break;
+                    }
+                    metrics_scope.accept_client(stream, Peer::Tcp(addr), move |stream, scope| {
+                        handle_tcp_client(stream, addr, scope)
+                    });
+                }
+            })?;
+        self.add_listener(local_addr, thread);
+        Ok(local_addr)
     }
-
-    /// Write a packet to a stream
-    pub fn write_packet<W>(stream: W, packet: &Packet) -> Result<(), Error>
-    where
-        W: Write,
-    {
-        write(stream, packet)
+    /// Binds the Unix socket, a stale socket file (no server is listening on it) is removed
+    /// first
+    #[cfg(all(unix, feature = "uds"))]
+    fn spawn_unix_server(self: &Arc<Self>, path: &std::path::Path) -> std::io::Result<()> {
+        use std::os::unix::fs::FileTypeExt as _;
+        if std::fs::symlink_metadata(path).map_or(false, |meta| meta.file_type().is_socket()) {
+            if UnixStream::connect(path).is_ok() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AddrInUse,
+                    format!("the socket {} is in use", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let metrics_scope = self.clone();
+        let thread = thread::Builder::new()
+            .name(self.server_thread_name.clone())
+            .spawn(move || {
+                apply_scheduling(metrics_scope.scheduling);
+                let mut connections = 0u64;
+                while let Ok((stream, _)) = listener.accept() {
+                    if metrics_scope.shutdown.load(Ordering::Acquire) {
+                        break;
+                    }
+                    connections += 1;
+                    let peer = Peer::Unix(connections);
+                    metrics_scope.accept_client(stream, peer, move |stream, scope| {
+                        handle_client(stream, peer, scope)
+                    });
+                }
+            })?;
+        self.listeners.lock().unwrap().push(Listener {
+            addr: ListenerAddr::Unix(path.to_owned()),
+            thread,
+        });
+        Ok(())
     }
-
-    /// Read client settings from a stream
-    pub fn read_client_settings<R>(stream: R) -> Result<ClientSettings, Error>
+    /// Serves the connection in a dedicated thread, connections over the client limit are
+    /// rejected
+    fn accept_client<T, F>(self: &Arc<Self>, stream: T, peer: Peer, handler: F)
     where
-        R: Read,
+        T: ClientStream,
+        F: FnOnce(T, Arc<Self>) -> Result<(), Error> + Send + 'static,
     {
-        read(stream)
+        let Some(slot) = ClientSlot::acquire(self) else {
+            warn!(
+                peer = %peer,
+                max_clients = self.max_clients,
+                "too many clients, connection rejected"
+            );
+            reject_client(stream);
+            return;
+        };
+        let metrics_scope = self.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            apply_scheduling(metrics_scope.scheduling);
+            if let Err(error) = handler(stream, metrics_scope) {
+                error!(peer = %peer, error = %error, "client error, disconnected");
+            }
+        });
     }
+}
 
-    /// Write client settings to a stream
-    pub fn write_client_settings<W>(stream: W, settings: &ClientSettings) -> Result<(), Error>
-    where
-        W: Write,
-    {
-        write(stream, settings)
+/// Per-client metric filter (the filter expression and the patterns, a metric must match all),
+/// the match results are cached, so the regular expressions are evaluated once per metric
+struct MetricFilter {
+    regexes: Vec<Regex>,
+    matches: RefCell<BTreeMap<String, bool>>,
+}
+
+impl MetricFilter {
+    fn new(regexes: Vec<Regex>) -> Self {
+        Self {
+            regexes,
+            matches: <_>::default(),
+        }
+    }
+    fn is_match(&self, name: &str) -> bool {
+        let mut matches = self.matches.borrow_mut();
+        if let Some(matched) = matches.get(name) {
+            return *matched;
+        }
+        let matched = self.regexes.iter().all(|regex| regex.is_match(name));
+        matches.insert(name.to_owned(), matched);
+        matched
     }
+}
 
-    fn write<D, W>(mut stream: W, data: D) -> Result<(), Error>
-    where
-        W: Write,
-        D: Serialize,
-    {
-        let data = rmp_serde::to_vec_named(&data)?;
-        stream.write_all(&u32::try_from(data.len())?.to_le_bytes())?;
-        stream.write_all(&data)?;
-        Ok(())
+/// Server-wide metric filter, shared by all threads, the match results are cached as well
+struct ServerMetricFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+    matches: Mutex<BTreeMap<String, bool>>,
+}
+
+impl ServerMetricFilter {
+    fn is_match(&self, name: &str) -> bool {
+        let mut matches = self.matches.lock().unwrap();
+        if let Some(matched) = matches.get(name) {
+            return *matched;
+        }
+        let matched = self.include.as_ref().map_or(true, |r| r.is_match(name))
+            && !self.exclude.as_ref().map_or(false, |r| r.is_match(name));
+        matches.insert(name.to_owned(), matched);
+        matched
     }
+}
 
-    fn read<R, D>(mut stream: R) -> Result<D, Error>
-    where
-        R: Read,
-        D: for<'de> Deserialize<'de>,
-    {
-        let buf = &mut [0u8; 4];
-        stream.read_exact(buf)?;
-        let len = usize::try_from(u32::from_le_bytes(*buf))?;
-        let mut buf = vec![0u8; len];
-        stream.read_exact(&mut buf)?;
-        Ok(rmp_serde::from_slice(&buf)?)
+/// Per-client schedule of metrics with own intervals
+struct IntervalSchedule {
+    intervals: BTreeMap<String, MetricInterval>,
+    // metric name -> the next due time
+    next_due: BTreeMap<String, u64>,
+}
+
+impl IntervalSchedule {
+    fn new(intervals: BTreeMap<String, MetricInterval>) -> Self {
+        Self {
+            intervals,
+            next_due: BTreeMap::new(),
+        }
+    }
+    /// Checks if the metric sample should be sent to the client
+    fn is_due(&mut self, name: &str, t: u64) -> bool {
+        let Some(metric_interval) = self.intervals.get(name) else {
+            return true;
+        };
+        let interval = metric_interval.interval.max(1);
+        let phase = metric_interval.phase % interval;
+        let next_due = self.next_due.entry(name.to_owned()).or_insert(phase);
+        if t < *next_due {
+            return false;
+        }
+        *next_due = ((t - phase) / interval)
+            .saturating_add(1)
+            .saturating_mul(interval)
+            .saturating_add(phase);
+        true
     }
 }
 
-/// Communication packets
-#[derive(Clone, Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum Packet {
-    /// Information packet (metrics metadata)
-    Info(Info),
-    /// Snapshot packet (metrics data)
-    Snapshot(Snapshot),
+struct AdaptiveMetric {
+    value: f64,
+    sent_at: u64,
+    interval: u64,
 }
 
-/// Client settings
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct ClientSettings {
-    sampling_interval: u64,
+/// Per-client adaptive sampling state
+struct AdaptiveSampler {
+    min_interval: u64,
+    max_interval: u64,
+    threshold: f64,
+    metrics: BTreeMap<String, AdaptiveMetric>,
 }
 
-impl ClientSettings {
-    /// # Panics
-    ///
-    /// Panics if the duration is too large to fit into a u64.
-    pub fn new(sampling_interval: Duration) -> Self {
+impl AdaptiveSampler {
+    fn new(settings: AdaptiveSampling, sampling_interval: u64) -> Self {
         Self {
-            sampling_interval: u64::try_from(sampling_interval.as_nanos()).unwrap(),
+            min_interval: sampling_interval,
+            max_interval: settings.max_interval.max(sampling_interval),
+            threshold: settings.threshold,
+            metrics: BTreeMap::new(),
         }
     }
+    /// Checks if the metric sample should be sent to the client
+    fn is_due(&mut self, name: &str, value: f64, t: u64) -> bool {
+        let Some(metric) = self.metrics.get_mut(name) else {
+            self.metrics.insert(
+                name.to_owned(),
+                AdaptiveMetric {
+                    value,
+                    sent_at: t,
+                    interval: self.min_interval,
+                },
+            );
+            return true;
+        };
+        let changed = if value.is_nan() || metric.value.is_nan() {
+            value.is_nan() != metric.value.is_nan()
+        } else {
+            (value - metric.value).abs() > self.threshold
+        };
+        if changed {
+            metric.interval = self.min_interval;
+        } else if t.saturating_sub(metric.sent_at) >= metric.interval {
+            metric.interval = metric.interval.saturating_mul(2).min(self.max_interval);
+        } else {
+            return false;
+        }
+        metric.value = value;
+        metric.sent_at = t;
+        true
+    }
 }
 
-/// Information packet
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Info {
-    metrics: BTreeMap<String, MetricInfo>,
+/// Checks if a metric value has been changed, NaN values are equal to each other, so gaps are
+/// sent once
+fn value_changed(last: f64, value: f64) -> bool {
+    if value.is_nan() || last.is_nan() {
+        value.is_nan() != last.is_nan()
+    } else {
+        value != last
+    }
 }
 
-impl Info {
-    /// Get metrics metadata map
-    pub fn metrics(&self) -> &BTreeMap<String, MetricInfo> {
-        &self.metrics
+/// Delta snapshots, a metric is sent if it has been changed since it has been sent last time
+#[derive(Default)]
+struct DeltaFilter {
+    sent: BTreeMap<String, f64>,
+}
+
+impl DeltaFilter {
+    fn is_due(&mut self, name: &str, value: f64) -> bool {
+        let changed = self
+            .sent
+            .get(name)
+            .map_or(true, |last| value_changed(*last, value));
+        if changed {
+            self.sent.insert(name.to_owned(), value);
+        }
+        changed
+    }
+    /// Forgets removed metrics, so they are sent again if re-created
+    fn retain(&mut self, metrics: &BTreeMap<String, MetricInfo>) {
+        self.sent.retain(|name, _| metrics.contains_key(name));
     }
 }
 
-/// Metrics metadata
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct MetricInfo {
-    labels: BTreeMap<String, String>,
+/// Per-client bandwidth limiter
+///
+/// The budget is checked once per [`BANDWIDTH_WINDOW`]. If it is exceeded, the limit level is
+/// increased: level 1 sends changed metrics only, the next levels send changed metrics in every
+/// 2nd, 4th etc. snapshot only. If the client uses less than a quarter of the budget, the level
+/// is decreased.
+struct BandwidthLimiter {
+    budget: u64,
+    window_start: Instant,
+    window_bytes: u64,
+    level: u32,
+    snapshot_no: u64,
+    // the last values sent (levels 1+)
+    sent: BTreeMap<String, f64>,
 }
 
-impl MetricInfo {
-    /// Metric labels map
-    pub fn labels(&self) -> &BTreeMap<String, String> {
-        &self.labels
+impl BandwidthLimiter {
+    fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            window_start: Instant::now(),
+            window_bytes: 0,
+            level: 0,
+            snapshot_no: 0,
+            sent: BTreeMap::new(),
+        }
+    }
+    fn decimation(&self) -> u64 {
+        if self.level < 2 {
+            1
+        } else {
+            1 << (self.level - 1)
+        }
+    }
+    fn next_snapshot(&mut self) {
+        self.snapshot_no = self.snapshot_no.wrapping_add(1);
+    }
+    /// Checks if the metric sample should be sent to the client
+    fn is_due(&mut self, name: &str, value: f64) -> bool {
+        if self.level == 0 {
+            return true;
+        }
+        if self.snapshot_no % self.decimation() != 0 {
+            return false;
+        }
+        let changed = self
+            .sent
+            .get(name)
+            .map_or(true, |last| value_changed(*last, value));
+        if changed {
+            self.sent.insert(name.to_owned(), value);
+        }
+        changed
+    }
+    /// Accounts the bytes written, returns the new limit level if it has been changed
+    fn account(&mut self, bytes: u64) -> Option<u32> {
+        self.window_bytes += bytes;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < BANDWIDTH_WINDOW {
+            return None;
+        }
+        let rate = u64::try_from(
+            u128::from(self.window_bytes) * 1_000_000_000 / elapsed.as_nanos().max(1),
+        )
+        .unwrap_or(u64::MAX);
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        let level = if rate > self.budget && self.level < MAX_BANDWIDTH_LEVEL {
+            self.level + 1
+        } else if rate.saturating_mul(4) < self.budget && self.level > 0 {
+            self.level - 1
+        } else {
+            return None;
+        };
+        if level == 0 {
+            self.sent.clear();
+        }
+        self.level = level;
+        Some(level)
+    }
+    fn describe(&self) -> String {
+        match self.level {
+            0 => "full rate".to_owned(),
+            1 => "on change".to_owned(),
+            _ => format!("on change, 1/{} of snapshots", self.decimation()),
+        }
     }
 }
 
-/// Snapshot packet
-#[derive(Clone, Serialize, Deserialize, Debug)]
-pub struct Snapshot {
-    t: Monotonic,
-    d: BTreeMap<String, f64>,
+/// Counts bytes written to the client
+struct MeteredStream<W> {
+    inner: W,
+    written: u64,
 }
 
-impl Snapshot {
-    /// Snapshot timestamp (monotonic, relative to the communication start)
-    pub fn ts(&self) -> Monotonic {
-        self.t
+impl<W> MeteredStream<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
     }
-    /// Snapshot data map (metric name -> value)
-    pub fn data(&self) -> &BTreeMap<String, f64> {
-        &self.d
+    fn take_written(&mut self) -> u64 {
+        std::mem::take(&mut self.written)
     }
-    /// Snapshot data map mutable (metric name -> value)
-    pub fn data_mut(&mut self) -> &mut BTreeMap<String, f64> {
-        &mut self.d
+}
+
+impl<W: Write> Write for MeteredStream<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
     }
-    /// Take snapshot data map
-    pub fn take_data(&mut self) -> BTreeMap<String, f64> {
-        std::mem::take(&mut self.d)
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
 
-/// Exporter builder
-pub struct ScopeBuilder {
-    addr: SocketAddr,
-    fallback: Option<Box<dyn Recorder + Send + Sync>>,
+/// The first tick (a multiple of the interval) after the elapsed time
+///
+/// Ticks are snapshot timestamps in nanoseconds since the sampling start, so all of them are
+/// spaced by the interval exactly. The counter lasts for ~584 years of uptime, past that the
+/// ticks saturate instead of overflowing.
+fn next_tick(elapsed_ns: u64, interval_ns: u64) -> u64 {
+    (elapsed_ns / interval_ns)
+        .saturating_add(1)
+        .saturating_mul(interval_ns)
 }
 
-impl Default for ScopeBuilder {
-    fn default() -> Self {
-        Self::new()
+/// Writes a packet to the client, compressed if negotiated
+fn write_client_packet<W: Write>(
+    stream: W,
+    packet: &Packet,
+    version: u16,
+    compress: bool,
+) -> Result<(), Error> {
+    if compress {
+        protocol::write_packet_compressed(stream, packet, version)
+    } else {
+        protocol::write_packet_versioned(stream, packet, version)
     }
 }
 
-impl ScopeBuilder {
-    /// Create a new exporter builder
-    pub fn new() -> Self {
-        Self {
-            addr: (std::net::Ipv4Addr::UNSPECIFIED, 5001).into(),
-            fallback: None,
+/// A connection counted against the client limit, released on drop
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl ClientSlot {
+    /// Returns `None` if the limit has been reached
+    fn acquire<S: ScopeStorage>(metrics_scope: &Inner<S>) -> Option<Self> {
+        let active_clients = metrics_scope.active_clients.clone();
+        if active_clients.fetch_add(1, Ordering::AcqRel) >= metrics_scope.max_clients {
+            active_clients.fetch_sub(1, Ordering::AcqRel);
+            return None;
         }
+        Some(Self(active_clients))
     }
-    /// Set the server listening address and port
-    pub fn with_addr<A: Into<SocketAddr>>(mut self, addr: A) -> Self {
-        self.addr = addr.into();
-        self
-    }
-    /// Set the fallback recorder
-    pub fn with_fallback(mut self, fallback: Box<dyn Recorder + Send + Sync>) -> Self {
-        self.fallback = Some(fallback);
-        self
-    }
-    /// Build the exporter's recorder
-    pub fn build(self) -> ScopeRecorder {
-        ScopeRecorder::build(self.addr, self.fallback)
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
     }
-    /// Build the exporter's recorder and install it as the global recorder
-    pub fn install(self) -> Result<(), Error> {
-        self.build().install()
+}
+
+/// Sends the version (so the client does not wait for it) and closes the connection
+fn reject_client<T: ClientStream>(mut stream: T) {
+    if stream.set_write_timeout(Some(REJECT_TIMEOUT)).is_ok() {
+        protocol::write_version(&mut stream).ok();
     }
+    stream.shutdown().ok();
 }
 
-/// Scope recorder
-#[derive(Clone)]
-pub struct ScopeRecorder {
-    inner: Arc<Inner>,
-    fallback: Arc<Option<Box<dyn Recorder + Send + Sync>>>,
+/// Keeps the client listed on the status page while connected
+struct ClientRegistration<'a, S: ScopeStorage> {
+    metrics_scope: &'a Inner<S>,
+    addr: Peer,
 }
 
-impl ScopeRecorder {
-    fn build<A: Into<SocketAddr>>(
-        addr: A,
-        fallback: Option<Box<dyn Recorder + Send + Sync>>,
-    ) -> Self {
+impl<'a, S: ScopeStorage> ClientRegistration<'a, S> {
+    fn new(metrics_scope: &'a Inner<S>, addr: Peer, sampling_interval: u64) -> Self {
+        metrics_scope.clients.lock().unwrap().insert(
+            addr,
+            ClientStatus {
+                sampling_interval,
+                connected_at: Instant::now(),
+            },
+        );
         Self {
-            inner: Arc::new(Inner::new(addr.into())),
-            fallback: fallback.into(),
+            metrics_scope,
+            addr,
         }
     }
-    /// # Panics
-    ///
-    /// Panics if the global recorder has already been set.
-    pub fn install(self) -> Result<(), Error> {
-        self.spawn_tasks()?;
-        metrics::set_global_recorder(self).map_err(Into::into)
+}
+
+impl<S: ScopeStorage> Drop for ClientRegistration<'_, S> {
+    fn drop(&mut self) {
+        self.metrics_scope
+            .clients
+            .lock()
+            .unwrap()
+            .remove(&self.addr);
     }
-    fn spawn_tasks(&self) -> Result<(), std::io::Error> {
-        self.inner.spawn_server(self.inner.addr)?;
+}
+
+/// Compares the tokens in constant time (for tokens of the same length), so the token can not be
+/// guessed byte by byte by measuring the response time
+fn tokens_match(expected: &[u8], received: &[u8]) -> bool {
+    if expected.len() != received.len() {
+        return false;
+    }
+    let diff = expected
+        .iter()
+        .zip(received)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
+
+/// Reads the client token, a missing or a wrong one is an error, which closes the connection
+fn authenticate<R: Read>(stream: R, token: &str) -> Result<(), Error> {
+    let received = protocol::read_token(stream)?;
+    if tokens_match(token.as_bytes(), received.as_bytes()) {
         Ok(())
+    } else {
+        Err(Error::Other("authentication failed".to_owned()))
     }
 }
 
-struct Inner {
-    registry: Registry<Key, GenerationalStorage<AtomicStorage>>,
+/// TCP connections may be OpenMetrics requests, which are told by the first bytes
+fn handle_tcp_client<S: ScopeStorage>(
+    stream: TcpStream,
     addr: SocketAddr,
+    metrics_scope: Arc<Inner<S>>,
+) -> Result<(), Error> {
+    if metrics_scope.openmetrics && openmetrics::is_http_request(&stream)? {
+        return metrics_scope.handle_openmetrics_request(stream, addr);
+    }
+    stream.set_nodelay(true)?;
+    handle_client(stream, Peer::Tcp(addr), metrics_scope)
 }
 
-impl Inner {
-    fn new(addr: SocketAddr) -> Self {
-        let registry = Registry::new(GenerationalStorage::new(AtomicStorage));
-        Self { registry, addr }
-    }
-    fn snapshot(&self, t: Monotonic) -> Snapshot {
-        let handles = self.registry.get_gauge_handles();
-        let mut map = BTreeMap::new();
-        for (key, gauge) in handles {
-            let name = key.name();
-            let value = f64::from_bits(gauge.get_inner().load(Ordering::Acquire));
-            map.insert(name[1..].to_string(), value);
+fn handle_client<S: ScopeStorage, T: ClientStream>(
+    mut stream: T,
+    addr: Peer,
+    metrics_scope: Arc<Inner<S>>,
+) -> Result<(), Error> {
+    let chat_timeout =
+        (!metrics_scope.chat_timeout.is_zero()).then_some(metrics_scope.chat_timeout);
+    let settings_timeout = chat_timeout.map_or(CLIENT_SETTINGS_TIMEOUT, |timeout| {
+        timeout.min(CLIENT_SETTINGS_TIMEOUT)
+    });
+    stream.set_read_timeout(Some(settings_timeout))?;
+    stream.set_write_timeout(chat_timeout)?;
+    protocol::write_version(&mut stream)?;
+    let handshake = metrics_scope
+        .auth_token
+        .as_deref()
+        .map_or(Ok(()), |token| authenticate(&mut stream, token))
+        .and_then(|()| protocol::read_client_settings(&mut stream));
+    let clients_settings = match handshake {
+        Ok(settings) => settings,
+        // health checks and discovery tools read the version only
+        Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            debug!(peer = %addr, "version probe");
+            return Ok(());
         }
-        Snapshot { t, d: map }
+        Err(e) => return Err(e),
+    };
+    let version = clients_settings.version.min(protocol::VERSION);
+    info!(
+        peer = %addr,
+        version,
+        interval_ns = clients_settings.sampling_interval,
+        "client connected"
+    );
+    if clients_settings.sampling_interval == 0 {
+        return Err(Error::Other("invalid sampling interval".to_owned()));
     }
-    fn info(&self) -> Info {
-        let info = self
-            .registry
-            .get_gauge_handles()
-            .iter()
-            .map(|(key, _)| {
-                let labels = key
-                    .labels()
-                    .map(|label| (label.key().to_owned(), label.value().to_owned()));
-                (
-                    key.name()[1..].to_string(),
-                    MetricInfo {
-                        labels: labels.collect(),
-                    },
-                )
-            })
-            .collect();
-        Info { metrics: info }
+    let mut regexes = Vec::new();
+    if let Some(ref filter) = clients_settings.filter {
+        regexes.push(compile_filter(filter)?);
     }
-    fn spawn_server(self: &Arc<Self>, addr: SocketAddr) -> Result<(), std::io::Error> {
-        let listener = TcpListener::bind(addr)?;
-        let metrics_scope = self.clone();
-        thread::Builder::new()
-            .name(SERVER_THREAD_NAME.to_owned())
-            .spawn(move || {
-                while let Ok((stream, addr)) = listener.accept() {
-                    info!(?addr, "client connected");
-                    let metrics_scope = metrics_scope.clone();
-                    thread::spawn(move || {
-                        if let Err(error) = handle_client(stream, metrics_scope) {
-                            error!(?addr, ?error, "client error, disconnected");
-                        } else {
-                            info!(?addr, "client disconnected");
-                        }
-                    });
-                }
-            })?;
-        Ok(())
+    if !clients_settings.patterns.is_empty() {
+        regexes.push(compile_patterns(&clients_settings.patterns)?);
     }
-}
-fn handle_client(mut stream: TcpStream, metrics_scope: Arc<Inner>) -> Result<(), Error> {
-    stream.set_read_timeout(Some(CLIENT_CHAT_TIMEOUT))?;
-    stream.set_write_timeout(Some(CLIENT_CHAT_TIMEOUT))?;
-    stream.set_nodelay(true)?;
-    protocol::write_version(&mut stream)?;
-    let clients_settings = protocol::read_client_settings(&mut stream)?;
+    let filter = (!regexes.is_empty()).then(|| MetricFilter::new(regexes));
     stream.set_read_timeout(None)?;
     stream.set_write_timeout(None)?;
-    protocol::write_packet(&mut stream, &Packet::Info(metrics_scope.info()))?;
+    let _registration =
+        ClientRegistration::new(&metrics_scope, addr, clients_settings.sampling_interval);
+    let mut stream = MeteredStream::new(stream);
+    let timestamp_unit = clients_settings.timestamp_unit;
+    let snapshots = metrics_scope.subscribe(clients_settings.sampling_interval);
+    let events = (version >= protocol::EVENTS_VERSION).then(subscribe_events);
+    let max_metrics = clients_settings.max_metrics;
+    let info = metrics_scope.info(timestamp_unit, filter.as_ref(), max_metrics);
+    // the metrics to send, if truncated
+    let mut allowed: Option<BTreeSet<String>> = info
+        .truncated_from
+        .map(|_| info.metrics.keys().cloned().collect());
+    let compress = cfg!(feature = "compression")
+        && clients_settings.compress
+        && version >= protocol::COMPRESSION_VERSION;
+    write_client_packet(&mut stream, &Packet::Info(info.clone()), version, compress)?;
+    // the metadata, the client is aware of (tracked for delta packets only)
+    let mut client_metrics = info.metrics;
     let mut last_info_sent = Monotonic::now();
-    let int_ns = u128::from(clients_settings.sampling_interval);
-    let start = Monotonic::now();
-    for _ in interval(Duration::from_nanos(clients_settings.sampling_interval)) {
-        let ts = Monotonic::from_nanos(
-            (start.elapsed().as_nanos() / int_ns * int_ns)
-                .try_into()
-                .unwrap(),
-        );
-        let packet = Packet::Snapshot(metrics_scope.snapshot(ts));
-        if protocol::write_packet(&mut stream, &packet).is_err() {
+    let mut last_full_info_sent = last_info_sent;
+    let batch = if version >= protocol::BATCH_VERSION {
+        clients_settings.batch
+    } else {
+        1
+    };
+    let mut encoder = protocol::SnapshotEncoder::versioned(version)
+        .with_compression(compress)?
+        .with_batch(batch)?;
+    let mut adaptive = clients_settings
+        .adaptive
+        .map(|settings| AdaptiveSampler::new(settings, clients_settings.sampling_interval));
+    let mut limiter = clients_settings.bandwidth_limit.map(BandwidthLimiter::new);
+    let mut delta = clients_settings.delta.then(DeltaFilter::default);
+    let mut schedule = (!clients_settings.intervals.is_empty())
+        .then(|| IntervalSchedule::new(clients_settings.intervals.clone()));
+    loop {
+        let snapshot = match snapshots.recv_timeout(PING_INTERVAL) {
+            Ok(snapshot) => snapshot,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                // snapshots of an incomplete batch are not held longer than the ping interval
+                if encoder.flush_batch(&mut stream).is_err() {
+                    break;
+                }
+                if version >= protocol::PING_VERSION {
+                    let t = Monotonic::from_nanos(
+                        u64::try_from(metrics_scope.start.elapsed().as_nanos()).unwrap_or(u64::MAX),
+                    );
+                    let ping = Ping::new(timestamp_unit.encode_ts(t));
+                    if write_client_packet(&mut stream, &Packet::Ping(ping), version, compress)
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let sampled_at = u64::try_from(snapshot.ts().as_nanos()).unwrap_or(u64::MAX);
+        if let Some(ref mut limiter) = limiter {
+            limiter.next_snapshot();
+        }
+        let data = snapshot
+            .data()
+            .iter()
+            .filter(|(name, value)| {
+                filter.as_ref().map_or(true, |filter| filter.is_match(name))
+                    && allowed
+                        .as_ref()
+                        .map_or(true, |allowed| allowed.contains(name.as_str()))
+                    && schedule
+                        .as_mut()
+                        .map_or(true, |schedule| schedule.is_due(name, sampled_at))
+                    && adaptive
+                        .as_mut()
+                        .map_or(true, |adaptive| adaptive.is_due(name, **value, sampled_at))
+                    && limiter
+                        .as_mut()
+                        .map_or(true, |limiter| limiter.is_due(name, **value))
+                    && delta
+                        .as_mut()
+                        .map_or(true, |delta| delta.is_due(name, **value))
+            })
+            .map(|(name, value)| (name, *value));
+        let t = timestamp_unit.encode_ts(snapshot.ts());
+        if encoder.write(&mut stream, t, data).is_err() {
             break;
         }
-        if last_info_sent.elapsed() >= SEND_INFO_INTERVAL {
-            let packet = Packet::Info(metrics_scope.info());
-            if protocol::write_packet(&mut stream, &packet).is_err() {
+        if let Some(ref events) = events {
+            let mut failed = false;
+            for event in events.try_iter() {
+                // events follow the snapshots, sampled before they have been emitted
+                if encoder.flush_batch(&mut stream).is_err() {
+                    failed = true;
+                    break;
+                }
+                let mut event = (*event).clone();
+                event.set_ts(timestamp_unit.encode_ts(event.ts()));
+                if write_client_packet(&mut stream, &Packet::Event(event), version, compress)
+                    .is_err()
+                {
+                    failed = true;
+                    break;
+                }
+            }
+            if failed {
                 break;
             }
+        }
+        if !metrics_scope.info_interval.is_zero()
+            && last_info_sent.elapsed() >= metrics_scope.info_interval
+        {
+            let info = metrics_scope.info(timestamp_unit, filter.as_ref(), max_metrics);
+            allowed = info
+                .truncated_from
+                .map(|_| info.metrics.keys().cloned().collect());
+            let packet = if version < protocol::INFO_DELTA_VERSION
+                || last_full_info_sent.elapsed() >= INFO_RESYNC_INTERVAL
+            {
+                last_full_info_sent = Monotonic::now();
+                Some(Packet::Info(info.clone()))
+            } else {
+                let delta = InfoDelta::between(&client_metrics, &info.metrics);
+                (!delta.is_empty()).then_some(Packet::InfoDelta(delta))
+            };
+            if let Some(packet) = packet {
+                if encoder.flush_batch(&mut stream).is_err()
+                    || write_client_packet(&mut stream, &packet, version, compress).is_err()
+                {
+                    break;
+                }
+            }
+            if let Some(ref mut delta) = delta {
+                delta.retain(&info.metrics);
+            }
+            client_metrics = info.metrics;
             last_info_sent = Monotonic::now();
         }
+        if let Some(ref mut limiter) = limiter {
+            if let Some(level) = limiter.account(stream.take_written()) {
+                let mode = limiter.describe();
+                debug!(peer = %addr, level, %mode, "client bandwidth limit adapted");
+                if version >= protocol::EVENTS_VERSION {
+                    let event = Event::new(t, BANDWIDTH_EVENT, mode);
+                    if encoder.flush_batch(&mut stream).is_err()
+                        || write_client_packet(
+                            &mut stream,
+                            &Packet::Event(event),
+                            version,
+                            compress,
+                        )
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
     }
+    info!(peer = %addr, "client disconnected");
     Ok(())
 }
 
-impl Recorder for ScopeRecorder {
+impl<S: ScopeStorage> Recorder for ScopeRecorder<S> {
     fn describe_counter(
         &self,
         key: metrics::KeyName,
         unit: Option<metrics::Unit>,
         description: metrics::SharedString,
     ) {
-        if let Some(fallback) = self.fallback.as_ref() {
+        if let Some(name) = self.inner.strip_prefix(key.as_str()) {
+            if !self.is_disabled() {
+                self.inner
+                    .descriptions
+                    .lock()
+                    .unwrap()
+                    .insert(name.to_owned(), (unit, (*description).to_owned()));
+            }
+        } else if let Some(fallback) = self.fallback.as_ref() {
             fallback.describe_counter(key, unit, description);
         }
     }
@@ -400,7 +3358,15 @@ impl Recorder for ScopeRecorder {
         unit: Option<metrics::Unit>,
         description: metrics::SharedString,
     ) {
-        if let Some(fallback) = self.fallback.as_ref() {
+        if let Some(name) = self.inner.strip_prefix(key.as_str()) {
+            if !self.is_disabled() {
+                self.inner
+                    .descriptions
+                    .lock()
+                    .unwrap()
+                    .insert(name.to_owned(), (unit, (*description).to_owned()));
+            }
+        } else if let Some(fallback) = self.fallback.as_ref() {
             fallback.describe_gauge(key, unit, description);
         }
     }
@@ -411,7 +3377,15 @@ impl Recorder for ScopeRecorder {
         unit: Option<metrics::Unit>,
         description: metrics::SharedString,
     ) {
-        if let Some(fallback) = self.fallback.as_ref() {
+        if let Some(name) = self.inner.strip_prefix(key.as_str()) {
+            if !self.is_disabled() {
+                self.inner
+                    .descriptions
+                    .lock()
+                    .unwrap()
+                    .insert(name.to_owned(), (unit, (*description).to_owned()));
+            }
+        } else if let Some(fallback) = self.fallback.as_ref() {
             fallback.describe_histogram(key, unit, description);
         }
     }
@@ -421,7 +3395,22 @@ impl Recorder for ScopeRecorder {
         key: &metrics::Key,
         metadata: &metrics::Metadata<'_>,
     ) -> metrics::Counter {
-        if let Some(fallback) = self.fallback.as_ref() {
+        if self.inner.strip_prefix(key.name()).is_some() {
+            if self.is_disabled() {
+                metrics::Counter::noop()
+            } else if let Some(ref fence) = self.inner.fence {
+                self.inner.registry.get_or_create_counter(key, |counter| {
+                    metrics::Counter::from_arc(Arc::new(CoherentHandle {
+                        handle: counter.clone(),
+                        fence: fence.clone(),
+                    }))
+                })
+            } else {
+                self.inner.registry.get_or_create_counter(key, |counter| {
+                    metrics::Counter::from_arc(counter.clone())
+                })
+            }
+        } else if let Some(fallback) = self.fallback.as_ref() {
             fallback.register_counter(key, metadata)
         } else {
             metrics::Counter::noop()
@@ -433,10 +3422,21 @@ impl Recorder for ScopeRecorder {
         key: &metrics::Key,
         metadata: &metrics::Metadata<'_>,
     ) -> metrics::Gauge {
-        if key.name().starts_with('~') {
-            self.inner
-                .registry
-                .get_or_create_gauge(key, |c| c.clone().into())
+        if self.inner.strip_prefix(key.name()).is_some() {
+            if self.is_disabled() {
+                metrics::Gauge::noop()
+            } else if let Some(ref fence) = self.inner.fence {
+                self.inner.registry.get_or_create_gauge(key, |gauge| {
+                    metrics::Gauge::from_arc(Arc::new(CoherentHandle {
+                        handle: gauge.clone(),
+                        fence: fence.clone(),
+                    }))
+                })
+            } else {
+                self.inner
+                    .registry
+                    .get_or_create_gauge(key, S::gauge_handle)
+            }
         } else if let Some(fallback) = self.fallback.as_ref() {
             fallback.register_gauge(key, metadata)
         } else {
@@ -449,7 +3449,22 @@ impl Recorder for ScopeRecorder {
         key: &metrics::Key,
         metadata: &metrics::Metadata<'_>,
     ) -> metrics::Histogram {
-        if let Some(fallback) = self.fallback.as_ref() {
+        if self.inner.strip_prefix(key.name()).is_some() {
+            if self.is_disabled() {
+                metrics::Histogram::noop()
+            } else if let Some(ref fence) = self.inner.fence {
+                self.inner.registry.get_or_create_histogram(key, |bucket| {
+                    metrics::Histogram::from_arc(Arc::new(CoherentHandle {
+                        handle: bucket.clone(),
+                        fence: fence.clone(),
+                    }))
+                })
+            } else {
+                self.inner.registry.get_or_create_histogram(key, |bucket| {
+                    metrics::Histogram::from_arc(bucket.clone())
+                })
+            }
+        } else if let Some(fallback) = self.fallback.as_ref() {
             fallback.register_histogram(key, metadata)
         } else {
             metrics::Histogram::noop()
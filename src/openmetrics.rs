@@ -0,0 +1,144 @@
+//! One-shot OpenMetrics text exposition of the current scope metrics, served on the scope port
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    io::ErrorKind,
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use tracing::debug;
+
+use crate::{
+    status::{read_http_request, write_http_response},
+    Error, Inner, ScopeStorage,
+};
+
+/// Native clients wait for the server version, so a connection, which sends nothing during the
+/// timeout, is a native one
+const HTTP_PEEK_TIMEOUT: Duration = Duration::from_millis(100);
+
+const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Checks if the peer has started with an HTTP GET request instead of waiting for the version
+pub(crate) fn is_http_request(stream: &TcpStream) -> Result<bool, Error> {
+    stream.set_read_timeout(Some(HTTP_PEEK_TIMEOUT))?;
+    let mut buf = [0u8; 4];
+    let result = match stream.peek(&mut buf) {
+        Ok(n) => Ok(buf[..n] == *b"GET "),
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+        Err(e) => Err(e.into()),
+    };
+    stream.set_read_timeout(None)?;
+    result
+}
+
+/// Metric names may contain characters, which are not allowed by OpenMetrics, these are replaced
+/// with underscores
+pub(crate) fn metric_name(name: &str) -> String {
+    name.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit()) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn escape_help(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+pub(crate) fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+impl<S: ScopeStorage> Inner<S> {
+    /// Renders the current scope metrics, counters are exposed with the `_total` sample suffix
+    ///
+    /// Metrics, which names are the same after the conversion (e.g. `a.b` and `a-b`), would
+    /// produce duplicate families, so only the first one is rendered.
+    fn openmetrics(&self) -> String {
+        let snapshot = self.current_snapshot();
+        let counters: BTreeSet<String> = self
+            .registry
+            .get_counter_handles()
+            .keys()
+            .map(|key| self.scope_name(key).to_owned())
+            .collect();
+        let descriptions = self.descriptions.lock().unwrap();
+        let mut text = String::new();
+        // family and sample names, rendered so far
+        let mut rendered = BTreeSet::new();
+        for (name, value) in snapshot.data() {
+            let is_counter = counters.contains(name);
+            let name_om = metric_name(name);
+            let (family, sample) = if is_counter {
+                let family = name_om
+                    .strip_suffix("_total")
+                    .unwrap_or(&name_om)
+                    .to_owned();
+                let sample = format!("{}_total", family);
+                (family, sample)
+            } else {
+                (name_om.clone(), name_om)
+            };
+            if rendered.contains(&family) || rendered.contains(&sample) {
+                debug!(metric = %name, family = %family, "duplicate OpenMetrics family, skipped");
+                continue;
+            }
+            let _ = writeln!(
+                text,
+                "# TYPE {} {}",
+                family,
+                if is_counter { "counter" } else { "gauge" }
+            );
+            if let Some((_, description)) = descriptions.get(name) {
+                if !description.is_empty() {
+                    let _ = writeln!(text, "# HELP {} {}", family, escape_help(description));
+                }
+            }
+            let _ = writeln!(text, "{} {}", sample, format_value(*value));
+            rendered.insert(family);
+            rendered.insert(sample);
+        }
+        text.push_str("# EOF\n");
+        text
+    }
+    pub(crate) fn handle_openmetrics_request(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+    ) -> Result<(), Error> {
+        stream.set_read_timeout(Some(HTTP_REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(HTTP_REQUEST_TIMEOUT))?;
+        let (_, path) = read_http_request(&stream)?;
+        debug!(peer = %addr, path = %path, "OpenMetrics request");
+        let (status, content_type, body) = match path.as_str() {
+            // HTTP requests can not send the token, so authenticated servers do not serve them
+            _ if self.auth_token.is_some() => (
+                "403 Forbidden",
+                "text/plain; charset=utf-8",
+                "Authentication required".to_owned(),
+            ),
+            "/" | "/metrics" => ("200 OK", OPENMETRICS_CONTENT_TYPE, self.openmetrics()),
+            _ => (
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                "Not found".to_owned(),
+            ),
+        };
+        write_http_response(&mut stream, status, content_type, &body)
+    }
+}
@@ -0,0 +1,121 @@
+//! Prometheus text exposition of the current scope metrics, served on a dedicated address
+use std::{
+    fmt::Write as _,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::Duration,
+};
+
+use tracing::debug;
+
+use crate::{
+    apply_scheduling,
+    openmetrics::{escape_help, format_value, metric_name},
+    status::{read_http_request, write_http_response},
+    Error, Inner, MetricInfo, MetricKind, ScopeStorage, TimestampUnit,
+};
+
+const PROMETHEUS_THREAD_NAME: &str = "MScopeProm";
+
+const PROMETHEUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Label names are not allowed to contain colons, unlike metric names
+fn label_name(name: &str) -> String {
+    metric_name(name).replace(':', "_")
+}
+
+fn escape_label_value(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl<S: ScopeStorage> Inner<S> {
+    /// Renders the current scope metrics with the labels, the metrics have been registered with
+    fn prometheus(&self) -> String {
+        let info = self.info(TimestampUnit::default(), None, None);
+        let snapshot = self.current_snapshot();
+        let mut text = String::new();
+        for (name, value) in snapshot.data() {
+            let name_prom = metric_name(name);
+            let metric_info = info.metrics().get(name);
+            if let Some(description) = metric_info.and_then(MetricInfo::description) {
+                let _ = writeln!(text, "# HELP {} {}", name_prom, escape_help(description));
+            }
+            let is_counter = metric_info.map_or(false, |i| i.kind() == MetricKind::Counter);
+            let _ = writeln!(
+                text,
+                "# TYPE {} {}",
+                name_prom,
+                if is_counter { "counter" } else { "gauge" }
+            );
+            text.push_str(&name_prom);
+            if let Some(labels) = metric_info
+                .map(MetricInfo::labels)
+                .filter(|l| !l.is_empty())
+            {
+                text.push('{');
+                for (i, (label, label_value)) in labels.iter().enumerate() {
+                    if i > 0 {
+                        text.push(',');
+                    }
+                    let _ = write!(
+                        text,
+                        "{}=\"{}\"",
+                        label_name(label),
+                        escape_label_value(label_value)
+                    );
+                }
+                text.push('}');
+            }
+            let _ = writeln!(text, " {}", format_value(*value));
+        }
+        text
+    }
+    fn handle_prometheus_request(&self, mut stream: TcpStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(PROMETHEUS_TIMEOUT))?;
+        stream.set_write_timeout(Some(PROMETHEUS_TIMEOUT))?;
+        let (method, path) = read_http_request(&stream)?;
+        let (status, content_type, body) = match (method.as_str(), path.as_str()) {
+            ("GET", "/metrics") => ("200 OK", PROMETHEUS_CONTENT_TYPE, self.prometheus()),
+            ("GET", _) => (
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                "Not found".to_owned(),
+            ),
+            _ => (
+                "405 Method Not Allowed",
+                "text/plain; charset=utf-8",
+                "Method not allowed".to_owned(),
+            ),
+        };
+        write_http_response(&mut stream, status, content_type, &body)
+    }
+    /// Scrapes are served one by one in the endpoint thread, the same way as the status page
+    pub(crate) fn spawn_prometheus_endpoint(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+    ) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let metrics_scope = self.clone();
+        let thread = thread::Builder::new()
+            .name(PROMETHEUS_THREAD_NAME.to_owned())
+            .spawn(move || {
+                apply_scheduling(metrics_scope.scheduling);
+                while let Ok((stream, addr)) = listener.accept() {
+                    if metrics_scope.shutdown.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if let Err(error) = metrics_scope.handle_prometheus_request(stream) {
+                        debug!(peer = %addr, error = %error, "Prometheus scrape error");
+                    }
+                }
+            })?;
+        self.add_listener(local_addr, thread);
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! A minimal embedded HTTP status page: current metric values and connected clients
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::Ordering, Arc},
+    thread,
+    time::Duration,
+};
+
+use tracing::debug;
+
+use crate::{apply_scheduling, Error, Inner, ScopeStorage};
+
+const STATUS_PAGE_THREAD_NAME: &str = "MScopeWeb";
+
+const STATUS_PAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The max size of the request line and the headers
+const MAX_HTTP_REQUEST: u64 = 8192;
+
+// the page auto-refresh interval (seconds)
+const STATUS_PAGE_REFRESH: u64 = 1;
+
+const STATUS_PAGE_STYLE: &str = "body{font-family:sans-serif}\
+    table{border-collapse:collapse;margin-bottom:1em}\
+    th,td{padding:2px 12px;text-align:left;border-bottom:1px solid #ddd}\
+    td.v{text-align:right;font-family:monospace}";
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Reads an HTTP request, returns the method and the path (empty if missing)
+///
+/// The request is limited to [`MAX_HTTP_REQUEST`] bytes, the read timeout applies to a single
+/// read only, so a peer could keep the reader busy with an endless line otherwise.
+pub(crate) fn read_http_request<R: Read>(stream: R) -> Result<(String, String), Error> {
+    let too_large = || Error::Protocol("HTTP request is too large or incomplete".to_owned());
+    let mut reader = BufReader::new(stream.take(MAX_HTTP_REQUEST));
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    if !request_line.ends_with('\n') {
+        return Err(too_large());
+    }
+    // the headers are not used but read out, so the connection is closed cleanly
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 {
+            if reader.get_ref().limit() == 0 {
+                return Err(too_large());
+            }
+            break;
+        }
+        if header.trim_end().is_empty() {
+            break;
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    Ok((
+        parts.next().unwrap_or_default().to_owned(),
+        parts.next().unwrap_or_default().to_owned(),
+    ))
+}
+
+/// Writes an HTTP/1.0 response, the connection is closed after
+pub(crate) fn write_http_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<(), Error> {
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\n\
+        Content-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+impl<S: ScopeStorage> Inner<S> {
+    /// Renders the status page with the current metric values and connected clients
+    fn status_page(&self) -> String {
+        let snapshot = self.current_snapshot();
+        let units: BTreeMap<String, &'static str> = self
+            .descriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(name, (unit, _))| unit.as_ref().map(|unit| (name.clone(), unit.as_str())))
+            .collect();
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+            <meta http-equiv=\"refresh\" content=\"{}\">\
+            <title>metrics-scope</title><style>{}</style></head><body>\
+            <h3>Metrics</h3><table><tr><th>Metric</th><th>Value</th><th>Unit</th></tr>",
+            STATUS_PAGE_REFRESH, STATUS_PAGE_STYLE
+        );
+        for (name, value) in snapshot.data() {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td class=\"v\">{}</td><td>{}</td></tr>",
+                escape_html(name),
+                value,
+                units.get(name).copied().unwrap_or_default()
+            );
+        }
+        html.push_str(
+            "</table><h3>Clients</h3><table>\
+            <tr><th>Address</th><th>Interval, ms</th><th>Connected, s</th></tr>",
+        );
+        for (addr, client) in self.clients.lock().unwrap().iter() {
+            #[allow(clippy::cast_precision_loss)]
+            let interval_ms = client.sampling_interval as f64 / 1_000_000.0;
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td class=\"v\">{}</td><td class=\"v\">{}</td></tr>",
+                addr,
+                interval_ms,
+                client.connected_at.elapsed().as_secs()
+            );
+        }
+        html.push_str("</table></body></html>");
+        html
+    }
+    fn handle_status_request(&self, mut stream: TcpStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(STATUS_PAGE_TIMEOUT))?;
+        stream.set_write_timeout(Some(STATUS_PAGE_TIMEOUT))?;
+        let (method, path) = read_http_request(&stream)?;
+        let (status, body) = match (method.as_str(), path.as_str()) {
+            ("GET", "/") => ("200 OK", self.status_page()),
+            ("GET", _) => ("404 Not Found", "Not found".to_owned()),
+            _ => ("405 Method Not Allowed", "Method not allowed".to_owned()),
+        };
+        write_http_response(&mut stream, status, "text/html; charset=utf-8", &body)
+    }
+    /// Requests are served one by one in the status page thread, the page is tiny and is meant
+    /// for a quick look only
+    pub(crate) fn spawn_status_page(self: &Arc<Self>, addr: SocketAddr) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let metrics_scope = self.clone();
+        let thread = thread::Builder::new()
+            .name(STATUS_PAGE_THREAD_NAME.to_owned())
+            .spawn(move || {
+                apply_scheduling(metrics_scope.scheduling);
+                while let Ok((stream, addr)) = listener.accept() {
+                    if metrics_scope.shutdown.load(Ordering::Acquire) {
+                        break;
+                    }
+                    if let Err(error) = metrics_scope.handle_status_request(stream) {
+                        debug!(peer = %addr, error = %error, "status page request error");
+                    }
+                }
+            })?;
+        self.add_listener(local_addr, thread);
+        Ok(())
+    }
+}
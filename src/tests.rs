@@ -0,0 +1,859 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bma_ts::Monotonic;
+use metrics::{Key, Level, Metadata, Recorder};
+
+use serde::Serialize;
+
+use crate::{
+    compile_filter, compile_patterns, next_tick, protocol, quantile_suffix,
+    status::read_http_request, BandwidthLimiter, ClientSettings, DeltaFilter, Event, Info,
+    InfoDelta, MetricFilter, MetricInfo, MetricKind, Packet, Ping, ScopeBuilder, ScopeRecorder,
+    Snapshot, SnapshotBatch, TimestampUnit, BANDWIDTH_WINDOW,
+};
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const METADATA: Metadata<'static> = Metadata::new(module_path!(), Level::INFO, None);
+
+fn record_histogram(recorder: &ScopeRecorder, name: &'static str, value: f64) {
+    recorder
+        .register_histogram(&Key::from_static_name(name), &METADATA)
+        .record(value);
+}
+
+/// Starts the server and the sampler on a random local port, without installing the recorder as
+/// the global one
+fn start(builder: ScopeBuilder) -> ScopeRecorder {
+    let recorder = builder.with_addr(([127, 0, 0, 1], 0)).build();
+    recorder.spawn_tasks().unwrap();
+    recorder.inner.spawn_sampler().unwrap();
+    recorder
+}
+
+/// Connects to the server and completes the handshake
+fn connect(recorder: &ScopeRecorder, token: Option<&str>, settings: &ClientSettings) -> TcpStream {
+    try_connect(recorder, token, settings).unwrap()
+}
+
+/// Connects to the server, the handshake fails if the server closes the connection
+fn try_connect(
+    recorder: &ScopeRecorder,
+    token: Option<&str>,
+    settings: &ClientSettings,
+) -> Result<TcpStream, crate::Error> {
+    let mut stream = TcpStream::connect(recorder.local_addr().unwrap())?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    protocol::read_version(&mut stream)?;
+    if let Some(token) = token {
+        protocol::write_token(&mut stream, token)?;
+    }
+    protocol::write_client_settings(&mut stream, settings)?;
+    Ok(stream)
+}
+
+/// Takes a snapshot at the tick and sends it to the due subscribers, the same way as the sampler
+fn sampler_tick(recorder: &ScopeRecorder, tick: u64) {
+    let mut drained = BTreeMap::new();
+    let snapshot = recorder
+        .inner
+        .snapshot(Monotonic::from_nanos(tick), Some(&mut drained));
+    recorder.inner.deliver(tick, &Arc::new(snapshot), &drained);
+}
+
+fn set_gauge(recorder: &ScopeRecorder, name: &'static str, value: f64) {
+    recorder
+        .register_gauge(&Key::from_static_name(name), &METADATA)
+        .set(value);
+}
+
+/// Reads packets until a snapshot with the metric is received
+fn read_metric(stream: &mut TcpStream, name: &str) -> Result<f64, crate::Error> {
+    let started = Instant::now();
+    while started.elapsed() < CLIENT_TIMEOUT {
+        if let Packet::Snapshot(snapshot) = protocol::read_packet(&mut *stream)? {
+            if let Some(value) = snapshot.data().get(name) {
+                return Ok(*value);
+            }
+        }
+    }
+    Err(crate::Error::Other(format!("no {} metric received", name)))
+}
+
+/// Sends an HTTP GET request, returns the whole response
+fn http_get(addr: SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT)).unwrap();
+    write!(stream, "GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn histogram_quantiles_cover_subscriber_intervals() {
+    let recorder = ScopeBuilder::new().with_histogram_quantiles(&[1.0]).build();
+    let max_name = format!("latency{}", quantile_suffix(1.0));
+    let fine = recorder.inner.subscribe(10);
+    let coarse = recorder.inner.subscribe(30);
+    for (tick, value) in [(0, 1.0), (10, 5.0), (20, 3.0), (30, 2.0)] {
+        record_histogram(&recorder, "~latency", value);
+        sampler_tick(&recorder, tick);
+    }
+    let fine_max: Vec<f64> = fine.try_iter().map(|s| s.data()[&max_name]).collect();
+    assert_eq!(fine_max, [1.0, 5.0, 3.0, 2.0]);
+    let coarse: Vec<(u128, f64)> = coarse
+        .try_iter()
+        .map(|s| (s.ts().as_nanos(), s.data()[&max_name]))
+        .collect();
+    // the samples of the ticks, skipped for the coarse subscriber, are not lost
+    assert_eq!(coarse, [(0, 1.0), (30, 5.0)]);
+}
+
+/// Counts information packets, received by a client in 500ms
+fn count_info_packets(info_interval: Duration) -> usize {
+    let recorder = start(ScopeBuilder::new().with_info_interval(info_interval));
+    // clients, which do not support information deltas, get full information packets
+    let version = protocol::TAGGED_PACKETS_VERSION;
+    let settings = ClientSettings::new(Duration::from_millis(10)).with_version(version);
+    let mut stream = connect(&recorder, None, &settings);
+    let started = Instant::now();
+    let mut info_packets = 0;
+    while started.elapsed() < Duration::from_millis(500) {
+        if let Packet::Info(_) = protocol::read_packet_versioned(&mut stream, version).unwrap() {
+            info_packets += 1;
+        }
+    }
+    recorder.inner.shutdown();
+    info_packets
+}
+
+#[test]
+fn info_interval() {
+    assert_eq!(count_info_packets(Duration::ZERO), 1);
+    assert!(count_info_packets(Duration::from_millis(100)) >= 3);
+}
+
+#[test]
+fn client_settings_overflow() {
+    assert!(ClientSettings::try_new(Duration::MAX).is_err());
+    let settings = ClientSettings::try_new(Duration::from_millis(100)).unwrap();
+    assert_eq!(settings.sampling_interval(), Duration::from_millis(100));
+    assert!(settings
+        .clone()
+        .try_with_sampling_interval(Duration::MAX)
+        .is_err());
+    assert!(settings
+        .clone()
+        .try_with_adaptive_sampling(Duration::MAX, 0.1)
+        .is_err());
+    assert!(settings
+        .clone()
+        .try_with_metric_interval("temp", Duration::from_secs(1), Duration::MAX)
+        .is_err());
+    assert!(settings
+        .try_with_metric_interval("temp", Duration::from_secs(1), Duration::ZERO)
+        .is_ok());
+}
+
+fn sample_snapshot(t: u64, value: f64) -> Snapshot {
+    Snapshot::new(
+        Monotonic::from_nanos(t),
+        [("temp".to_owned(), value)].into_iter().collect(),
+    )
+}
+
+fn sample_metrics(unit: &str) -> BTreeMap<String, MetricInfo> {
+    let labels = [("plot".to_owned(), "env".to_owned())]
+        .into_iter()
+        .collect();
+    [("temp".to_owned(), MetricInfo::new(labels).with_unit(unit))]
+        .into_iter()
+        .collect()
+}
+
+/// A packet, the minimal protocol version and the variant checker
+type SamplePacket = (Packet, u16, fn(&Packet) -> bool);
+
+/// A packet of every type
+fn sample_packets() -> Vec<SamplePacket> {
+    vec![
+        (
+            Packet::Snapshot(sample_snapshot(1_000, 21.5)),
+            protocol::MIN_VERSION,
+            |p| matches!(p, Packet::Snapshot(_)),
+        ),
+        (
+            Packet::SnapshotBatch(SnapshotBatch::new(vec![
+                sample_snapshot(1_000, 21.5),
+                sample_snapshot(2_000, f64::NAN),
+            ])),
+            protocol::BATCH_VERSION,
+            |p| matches!(p, Packet::SnapshotBatch(_)),
+        ),
+        (
+            Packet::Info(Info::new(sample_metrics("C"))),
+            protocol::MIN_VERSION,
+            |p| matches!(p, Packet::Info(_)),
+        ),
+        (
+            Packet::InfoDelta(InfoDelta::between(
+                &sample_metrics("C"),
+                &sample_metrics("F"),
+            )),
+            protocol::INFO_DELTA_VERSION,
+            |p| matches!(p, Packet::InfoDelta(_)),
+        ),
+        (
+            Packet::Event(Event::new(Monotonic::from_nanos(1_000), "mode", "auto")),
+            protocol::EVENTS_VERSION,
+            |p| matches!(p, Packet::Event(_)),
+        ),
+        (
+            Packet::Ping(Ping::new(Monotonic::from_nanos(1_000))),
+            protocol::PING_VERSION,
+            |p| matches!(p, Packet::Ping(_)),
+        ),
+    ]
+}
+
+fn encode(packet: &Packet, version: u16) -> Vec<u8> {
+    let mut buf = Vec::new();
+    protocol::write_packet_versioned(&mut buf, packet, version).unwrap();
+    buf
+}
+
+#[test]
+fn packet_round_trip() {
+    for (packet, _, is_variant) in sample_packets() {
+        let buf = encode(&packet, protocol::VERSION);
+        let decoded = protocol::read_packet(&buf[..]).unwrap();
+        assert!(
+            is_variant(&decoded),
+            "{:?} decoded as {:?}",
+            packet,
+            decoded
+        );
+        assert_eq!(encode(&decoded, protocol::VERSION), buf);
+    }
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_packet_round_trip() {
+    for (packet, _, is_variant) in sample_packets() {
+        let mut buf = Vec::new();
+        protocol::write_packet_compressed(&mut buf, &packet, protocol::VERSION).unwrap();
+        let decoded = protocol::read_packet(&buf[..]).unwrap();
+        assert!(
+            is_variant(&decoded),
+            "{:?} decoded as {:?}",
+            packet,
+            decoded
+        );
+        assert_eq!(
+            encode(&decoded, protocol::VERSION),
+            encode(&packet, protocol::VERSION)
+        );
+    }
+}
+
+#[test]
+fn packet_older_versions() {
+    for (packet, min_version, is_variant) in sample_packets() {
+        for version in protocol::MIN_VERSION..=protocol::VERSION {
+            let mut buf = Vec::new();
+            let result = protocol::write_packet_versioned(&mut buf, &packet, version);
+            if version < min_version {
+                assert!(
+                    result.is_err(),
+                    "{:?} written for version {}",
+                    packet,
+                    version
+                );
+                continue;
+            }
+            result.unwrap();
+            // untagged packets of versions 1-2 are told by their fields
+            let decoded = protocol::read_packet_versioned(&buf[..], version).unwrap();
+            assert!(
+                is_variant(&decoded),
+                "{:?} decoded as {:?}",
+                packet,
+                decoded
+            );
+            assert_eq!(encode(&decoded, version), buf);
+        }
+    }
+}
+
+/// A snapshot packet of a future protocol version with an unknown optional field
+#[derive(Serialize)]
+struct FutureSnapshot {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    t: u64,
+    d: BTreeMap<String, f64>,
+    metrics: Vec<String>,
+}
+
+#[test]
+fn packet_unknown_fields() {
+    let data = rmp_serde::to_vec_named(&FutureSnapshot {
+        kind: "snapshot",
+        t: 1_000,
+        d: [("temp".to_owned(), 21.5)].into_iter().collect(),
+        // a field, named as the information packet one, must not change the packet type
+        metrics: vec!["temp".to_owned()],
+    })
+    .unwrap();
+    let mut buf = u32::try_from(data.len()).unwrap().to_le_bytes().to_vec();
+    buf.extend(data);
+    let Packet::Snapshot(snapshot) = protocol::read_packet(&buf[..]).unwrap() else {
+        panic!("not a snapshot");
+    };
+    assert_eq!(snapshot.data()["temp"], 21.5);
+}
+
+/// Compile the protocol schema, validating against the given definition
+fn schema_of(definition: &str) -> jsonschema::JSONSchema {
+    let mut schema: serde_json::Value = serde_json::from_str(protocol::describe_schema()).unwrap();
+    schema["$ref"] = format!("#/$defs/{}", definition).into();
+    jsonschema::JSONSchema::compile(&schema).unwrap()
+}
+
+fn assert_valid<T: Serialize + std::fmt::Debug>(schema: &jsonschema::JSONSchema, value: &T) {
+    let instance = serde_json::to_value(value).unwrap();
+    if let Err(errors) = schema.validate(&instance) {
+        let errors: Vec<String> = errors.map(|e| e.to_string()).collect();
+        panic!("{:?} does not match the schema: {:?}", value, errors);
+    };
+}
+
+#[test]
+fn schema_version() {
+    let schema: serde_json::Value = serde_json::from_str(protocol::describe_schema()).unwrap();
+    assert_eq!(schema["protocolVersion"], protocol::VERSION);
+}
+
+#[test]
+fn schema_packets() {
+    let schema = schema_of("Packet");
+    for (packet, _, _) in sample_packets() {
+        // JSON has no NaN, the schema describes MessagePack data
+        if let Packet::SnapshotBatch(_) = packet {
+            let batch = SnapshotBatch::new(vec![
+                sample_snapshot(1_000, 21.5),
+                sample_snapshot(2_000, 22.0),
+            ]);
+            assert_valid(&schema, &Packet::SnapshotBatch(batch));
+            continue;
+        }
+        assert_valid(&schema, &packet);
+    }
+}
+
+#[test]
+fn schema_client_settings() {
+    let schema = schema_of("ClientSettings");
+    assert_valid(&schema, &ClientSettings::new(Duration::from_millis(100)));
+    assert_valid(
+        &schema,
+        &ClientSettings::new(Duration::from_millis(100))
+            .with_timestamp_unit(crate::TimestampUnit::Micros)
+            .with_adaptive_sampling(Duration::from_secs(1), 0.5)
+            .with_filter("^temp")
+            .unwrap()
+            .with_patterns(["temp*", "hum?"])
+            .with_bandwidth_limit(10_000)
+            .with_max_metrics(10)
+            .with_delta(true)
+            .with_batch(5)
+            .with_metric_interval("temp", Duration::from_secs(1), Duration::from_millis(10)),
+    );
+}
+
+#[test]
+fn auth_token() {
+    let recorder = start(ScopeBuilder::new().with_auth_token("secret"));
+    set_gauge(&recorder, "~temp", 21.5);
+    let settings = ClientSettings::new(Duration::from_millis(10));
+    let read_temp = |token| {
+        try_connect(&recorder, token, &settings).and_then(|mut s| read_metric(&mut s, "temp"))
+    };
+    assert!(read_temp(Some("wrong")).is_err());
+    assert!(read_temp(None).is_err());
+    assert_eq!(read_temp(Some("secret")).unwrap(), 21.5);
+    recorder.inner.shutdown();
+}
+
+/// Encodes a token frame, compressed the same way as packets
+#[cfg(feature = "compression")]
+fn compressed_token_frame(token: &str) -> Vec<u8> {
+    let data = zstd::bulk::compress(&rmp_serde::to_vec(token).unwrap(), 0).unwrap();
+    let mut frame = (u32::try_from(data.len()).unwrap() | 1 << 31)
+        .to_le_bytes()
+        .to_vec();
+    frame.extend(data);
+    frame
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compressed_token_limit() {
+    let token = protocol::read_token(&compressed_token_frame("secret")[..]).unwrap();
+    assert_eq!(token, "secret");
+    // a small compressed frame is not decompressed over the token limit
+    let frame = compressed_token_frame(&"x".repeat(1 << 20));
+    assert!(frame.len() < 1024);
+    assert!(protocol::read_token(&frame[..]).is_err());
+}
+
+#[test]
+fn auth_token_openmetrics() {
+    let recorder = start(
+        ScopeBuilder::new()
+            .with_auth_token("secret")
+            .with_openmetrics(true),
+    );
+    set_gauge(&recorder, "~temp", 21.5);
+    // HTTP requests can not bypass the authentication
+    let response = http_get(recorder.local_addr().unwrap(), "/metrics");
+    assert!(response.starts_with("HTTP/1.0 403 "), "{}", response);
+    assert!(!response.contains("temp"));
+    recorder.inner.shutdown();
+}
+
+#[test]
+fn http_request_limit() {
+    let request = b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n";
+    assert_eq!(
+        read_http_request(&request[..]).unwrap(),
+        ("GET".to_owned(), "/metrics".to_owned())
+    );
+    // an endless request line
+    let line = format!("GET /{}", "x".repeat(10_000));
+    assert!(read_http_request(line.as_bytes()).is_err());
+    // endless headers
+    let headers = format!("GET / HTTP/1.0\r\n{}", "X-Pad: x\r\n".repeat(1000));
+    assert!(read_http_request(headers.as_bytes()).is_err());
+}
+
+#[test]
+fn openmetrics() {
+    let recorder = start(ScopeBuilder::new().with_openmetrics(true));
+    set_gauge(&recorder, "~a-b", 1.0);
+    // converted to the same name
+    set_gauge(&recorder, "~a.b", 2.0);
+    set_gauge(&recorder, "~temp", 21.5);
+    recorder.describe_gauge("~temp".into(), None, "Temperature".into());
+    for (name, value) in [("~hits", 2), ("~req_total", 3)] {
+        recorder
+            .register_counter(&Key::from_static_name(name), &METADATA)
+            .increment(value);
+    }
+    let addr = recorder.local_addr().unwrap();
+    let response = http_get(addr, "/metrics");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.0 200 "), "{}", head);
+    assert_eq!(
+        body,
+        "# TYPE a_b gauge\n\
+        a_b 1\n\
+        # TYPE hits counter\n\
+        hits_total 2\n\
+        # TYPE req counter\n\
+        req_total 3\n\
+        # TYPE temp gauge\n\
+        # HELP temp Temperature\n\
+        temp 21.5\n\
+        # EOF\n"
+    );
+    assert!(http_get(addr, "/other").starts_with("HTTP/1.0 404 "));
+    // native clients are served on the same port
+    let settings = ClientSettings::new(Duration::from_millis(10));
+    let mut stream = connect(&recorder, None, &settings);
+    assert_eq!(read_metric(&mut stream, "temp").unwrap(), 21.5);
+    recorder.inner.shutdown();
+}
+
+#[cfg(feature = "prometheus-endpoint")]
+#[test]
+fn prometheus_endpoint() {
+    let recorder = start(ScopeBuilder::new().with_prometheus_addr(([127, 0, 0, 1], 0)));
+    let labels = vec![metrics::Label::new("plot", "a\"b\\c\nd")];
+    recorder
+        .register_gauge(&Key::from_parts("~temp", labels), &METADATA)
+        .set(21.5);
+    recorder.describe_gauge("~temp".into(), None, "Motor\\temp\nC".into());
+    recorder
+        .register_counter(&Key::from_static_name("~hits"), &METADATA)
+        .increment(2);
+    // the endpoint is started after the scope server
+    let addr = {
+        let listeners = recorder.inner.listeners.lock().unwrap();
+        match listeners.last().unwrap().addr {
+            crate::ListenerAddr::Tcp(addr) => addr,
+            #[cfg(all(unix, feature = "uds"))]
+            crate::ListenerAddr::Unix(_) => unreachable!(),
+        }
+    };
+    let response = http_get(addr, "/metrics");
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.0 200 "), "{}", head);
+    assert_eq!(
+        body,
+        "# TYPE hits counter\n\
+        hits 2\n\
+        # HELP temp Motor\\\\temp\\nC\n\
+        # TYPE temp gauge\n\
+        temp{plot=\"a\\\"b\\\\c\\nd\"} 21.5\n"
+    );
+    assert!(http_get(addr, "/other").starts_with("HTTP/1.0 404 "));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"POST /metrics HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 405 "), "{}", response);
+    recorder.inner.shutdown();
+}
+
+#[test]
+fn max_clients() {
+    let recorder = start(ScopeBuilder::new().with_max_clients(2));
+    set_gauge(&recorder, "~temp", 21.5);
+    let settings = ClientSettings::new(Duration::from_millis(10));
+    let mut clients: Vec<TcpStream> = (0..2)
+        .map(|_| connect(&recorder, None, &settings))
+        .collect();
+    for stream in &mut clients {
+        assert_eq!(read_metric(stream, "temp").unwrap(), 21.5);
+    }
+    // the version is still sent to the rejected client, so it does not wait for it
+    let rejected =
+        try_connect(&recorder, None, &settings).and_then(|mut s| read_metric(&mut s, "temp"));
+    assert!(rejected.is_err());
+    recorder.inner.shutdown();
+}
+
+#[test]
+fn idle_client() {
+    // the settings are read within a short timeout, even if the chat timeout is disabled
+    let recorder = start(
+        ScopeBuilder::new()
+            .with_max_clients(1)
+            .with_chat_timeout(Duration::ZERO),
+    );
+    set_gauge(&recorder, "~temp", 21.5);
+    let mut idle = TcpStream::connect(recorder.local_addr().unwrap()).unwrap();
+    idle.set_read_timeout(Some(CLIENT_TIMEOUT * 2)).unwrap();
+    protocol::read_version(&mut idle).unwrap();
+    let started = Instant::now();
+    assert!(protocol::read_packet(&mut idle).is_err());
+    assert!(started.elapsed() < CLIENT_TIMEOUT * 2);
+    // the slot of the idle client is free
+    let settings = ClientSettings::new(Duration::from_millis(10));
+    let mut stream = connect(&recorder, None, &settings);
+    assert_eq!(read_metric(&mut stream, "temp").unwrap(), 21.5);
+    recorder.inner.shutdown();
+}
+
+#[test]
+fn server_metric_filter() {
+    let recorder = ScopeBuilder::new()
+        .with_metric_filter(Some("^(temp|hum)"), Some("_raw$"))
+        .unwrap()
+        .build();
+    for name in ["~temp", "~temp_raw", "~hum", "~pressure"] {
+        set_gauge(&recorder, name, 1.0);
+    }
+    let snapshot = recorder.current_snapshot();
+    assert_eq!(snapshot.data().keys().collect::<Vec<_>>(), ["hum", "temp"]);
+    let info = recorder.inner.info(TimestampUnit::Nanos, None, None);
+    assert_eq!(info.metrics().keys().collect::<Vec<_>>(), ["hum", "temp"]);
+    // exclude-only filters export everything else
+    let recorder = ScopeBuilder::new()
+        .with_metric_filter(None, Some("_raw$"))
+        .unwrap()
+        .build();
+    for name in ["~temp", "~temp_raw", "~pressure"] {
+        set_gauge(&recorder, name, 1.0);
+    }
+    let snapshot = recorder.current_snapshot();
+    assert_eq!(
+        snapshot.data().keys().collect::<Vec<_>>(),
+        ["pressure", "temp"]
+    );
+    assert!(ScopeBuilder::new()
+        .with_metric_filter(Some("("), None)
+        .is_err());
+}
+
+#[test]
+fn metric_patterns() {
+    let patterns =
+        compile_patterns(&["temp*".to_owned(), "hum?".to_owned(), "a.b".to_owned()]).unwrap();
+    for name in ["temp", "temperature", "hum1", "a.b"] {
+        assert!(patterns.is_match(name), "{} does not match", name);
+    }
+    // patterns are anchored and regex characters are literal
+    for name in ["hum", "hum12", "xtemp", "axb"] {
+        assert!(!patterns.is_match(name), "{} matches", name);
+    }
+    // a client metric must match both the filter and the patterns
+    let filter = MetricFilter::new(vec![compile_filter("ure$").unwrap(), patterns]);
+    assert!(filter.is_match("temperature"));
+    assert!(!filter.is_match("temp"));
+    assert!(!filter.is_match("pressure"));
+}
+
+#[test]
+fn subscriber() {
+    let recorder = ScopeBuilder::new().build();
+    assert!(recorder.subscribe(Duration::ZERO).is_err());
+    let snapshots = recorder.subscribe(Duration::from_nanos(20)).unwrap();
+    for (tick, value) in [(0, 1.0), (10, 2.0), (20, 3.0)] {
+        set_gauge(&recorder, "~temp", value);
+        sampler_tick(&recorder, tick);
+    }
+    let received: Vec<(u128, f64)> = snapshots
+        .try_iter()
+        .map(|s| (s.ts().as_nanos(), s.data()["temp"]))
+        .collect();
+    assert_eq!(received, [(0, 1.0), (20, 3.0)]);
+    // a dropped receiver is unsubscribed at the next delivery
+    drop(snapshots);
+    assert_eq!(recorder.inner.subscribers.lock().unwrap().len(), 1);
+    sampler_tick(&recorder, 40);
+    assert!(recorder.inner.subscribers.lock().unwrap().is_empty());
+    // the shutdown closes subscriptions, the ones made after it are closed at once
+    let snapshots = recorder.subscribe(Duration::from_nanos(20)).unwrap();
+    recorder.inner.shutdown();
+    assert!(snapshots.recv().is_err());
+    assert!(recorder
+        .subscribe(Duration::from_nanos(20))
+        .unwrap()
+        .recv()
+        .is_err());
+}
+
+#[test]
+fn current_snapshot() {
+    let recorder = ScopeBuilder::new().build();
+    let labels = vec![metrics::Label::new("plot", "env")];
+    recorder
+        .register_gauge(&Key::from_parts("~temp", labels), &METADATA)
+        .set(21.5);
+    set_gauge(&recorder, "~hum", 40.0);
+    // not a scope metric
+    set_gauge(&recorder, "pressure", 1013.0);
+    let data = |recorder: &ScopeRecorder| {
+        let snapshot = recorder.current_snapshot();
+        snapshot.data().clone().into_iter().collect::<Vec<_>>()
+    };
+    assert_eq!(
+        data(&recorder),
+        [("hum".to_owned(), 40.0), ("temp".to_owned(), 21.5)]
+    );
+    set_gauge(&recorder, "~hum", 45.0);
+    assert_eq!(
+        data(&recorder),
+        [("hum".to_owned(), 45.0), ("temp".to_owned(), 21.5)]
+    );
+    let info = recorder.current_info();
+    assert_eq!(info.metrics().keys().collect::<Vec<_>>(), ["hum", "temp"]);
+    assert_eq!(info.metrics()["temp"].labels()["plot"], "env");
+}
+
+#[test]
+fn counters() {
+    let recorder = ScopeBuilder::new().build();
+    let counter = recorder.register_counter(&Key::from_static_name("~req_count"), &METADATA);
+    counter.increment(3);
+    counter.increment(4);
+    // not a scope metric
+    recorder
+        .register_counter(&Key::from_static_name("err_count"), &METADATA)
+        .increment(1);
+    let snapshot = recorder.current_snapshot();
+    assert_eq!(snapshot.data().keys().collect::<Vec<_>>(), ["req_count"]);
+    assert_eq!(snapshot.data()["req_count"], 7.0);
+    let info = recorder.current_info();
+    assert_eq!(info.metrics()["req_count"].kind(), MetricKind::Counter);
+}
+
+#[test]
+fn metric_prefix() {
+    let recorder = ScopeBuilder::new().with_prefix("scope_").build();
+    for name in ["scope_temp", "scope_hum", "~pressure", "temp", "scope_"] {
+        set_gauge(&recorder, name, 1.0);
+    }
+    // a gauge, named as the prefix, is not a scope metric
+    let snapshot = recorder.current_snapshot();
+    assert_eq!(snapshot.data().keys().collect::<Vec<_>>(), ["hum", "temp"]);
+    // an empty prefix captures every gauge
+    let recorder = ScopeBuilder::new().with_prefix("").build();
+    for name in ["~temp", "hum"] {
+        set_gauge(&recorder, name, 1.0);
+    }
+    let snapshot = recorder.current_snapshot();
+    assert_eq!(snapshot.data().keys().collect::<Vec<_>>(), ["hum", "~temp"]);
+}
+
+#[test]
+fn delta_snapshots() {
+    let mut delta = DeltaFilter::default();
+    let mut due = |value| delta.is_due("temp", value);
+    // the first sample is always sent
+    assert!(due(1.0));
+    assert!(!due(1.0));
+    assert!(due(2.0));
+    // a gap is sent once, the value after it is sent again
+    assert!(due(f64::NAN));
+    assert!(!due(f64::NAN));
+    assert!(due(2.0));
+    // removed metrics are sent again if re-created
+    delta.retain(&BTreeMap::new());
+    assert!(delta.is_due("temp", 2.0));
+}
+
+/// Accounts the bytes as written during the whole limiter window
+fn close_window(limiter: &mut BandwidthLimiter, bytes: u64) -> Option<u32> {
+    limiter.window_start = Instant::now() - BANDWIDTH_WINDOW;
+    limiter.account(bytes)
+}
+
+#[test]
+fn bandwidth_levels() {
+    let mut limiter = BandwidthLimiter::new(1000);
+    // the window is not over yet
+    assert_eq!(limiter.account(10_000), None);
+    assert!(limiter.is_due("temp", 1.0));
+    assert!(limiter.is_due("temp", 1.0));
+    // over the budget: changed metrics only
+    assert_eq!(close_window(&mut limiter, 2000), Some(1));
+    assert!(limiter.is_due("temp", 1.0));
+    assert!(!limiter.is_due("temp", 1.0));
+    assert!(limiter.is_due("temp", f64::NAN));
+    assert!(!limiter.is_due("temp", f64::NAN));
+    // still over the budget: every 2nd snapshot only
+    assert_eq!(close_window(&mut limiter, 2000), Some(2));
+    limiter.next_snapshot();
+    assert!(!limiter.is_due("temp", 2.0));
+    limiter.next_snapshot();
+    assert!(limiter.is_due("temp", 2.0));
+    // within the budget, but not low enough to step down
+    assert_eq!(close_window(&mut limiter, 500), None);
+    // under a quarter of the budget: the level is decreased one by one
+    assert_eq!(close_window(&mut limiter, 100), Some(1));
+    assert_eq!(close_window(&mut limiter, 100), Some(0));
+    assert!(limiter.is_due("temp", 2.0));
+    assert!(limiter.is_due("temp", 2.0));
+    assert_eq!(close_window(&mut limiter, 0), None);
+}
+
+/// The C API works with the global recorder, so this must stay the only test installing it
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi() {
+    use crate::ffi::{scope_install, scope_set_gauge, scope_shutdown};
+    use std::ffi::CString;
+
+    assert_eq!(scope_install(0), 0);
+    assert_eq!(scope_install(0), -1);
+    unsafe {
+        assert_eq!(scope_set_gauge(std::ptr::null(), 1.0), -1);
+        let invalid = b"te\xffmp\0";
+        assert_eq!(scope_set_gauge(invalid.as_ptr().cast(), 1.0), -1);
+        let name = CString::new("temp").unwrap();
+        assert_eq!(scope_set_gauge(name.as_ptr(), 21.5), 0);
+        // the prefix is not added twice
+        let name = CString::new("~hum").unwrap();
+        assert_eq!(scope_set_gauge(name.as_ptr(), 40.0), 0);
+    }
+    let snapshot = crate::ffi::SCOPE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .unwrap()
+        .current_snapshot();
+    assert_eq!(
+        snapshot.data().clone().into_iter().collect::<Vec<_>>(),
+        [("hum".to_owned(), 40.0), ("temp".to_owned(), 21.5)]
+    );
+    scope_shutdown();
+    // the exporter can not be installed again
+    assert_eq!(scope_install(0), -1);
+}
+
+/// Simulates long sampler uptimes with late wake-ups, run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn sampler_ticks_soak() {
+    // a reproducible pseudo-random wake-up delay
+    let mut seed = 1u64;
+    let mut delay = |max: u64| {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (seed >> 33) % max
+    };
+    for interval_ns in [1, 999, 1_000_000, 1_000_000_000, u64::MAX / 3] {
+        for start in [
+            0,
+            u64::MAX / 2,
+            u64::MAX - interval_ns.saturating_mul(1_000),
+        ] {
+            let mut elapsed = start;
+            let mut last_tick: Option<u64> = None;
+            for _ in 0..1_000_000 {
+                let tick = next_tick(elapsed, interval_ns);
+                if tick == u64::MAX {
+                    // saturated, the sampler keeps running with the same timestamp
+                    assert_eq!(next_tick(u64::MAX, interval_ns), u64::MAX);
+                    break;
+                }
+                assert_eq!(tick % interval_ns, 0);
+                assert!(tick > elapsed && tick - elapsed <= interval_ns);
+                if let Some(last_tick) = last_tick {
+                    assert!(tick > last_tick);
+                }
+                last_tick = Some(tick);
+                // the sampler sleeps until the tick and sometimes wakes up late, skipping ticks
+                elapsed = tick.saturating_add(delay(interval_ns.saturating_mul(3)));
+            }
+        }
+    }
+    // a subscriber with a coarser interval, started after a year of uptime, gets the first
+    // snapshot at or after each of its interval multiples
+    let recorder = ScopeBuilder::new().build();
+    let interval_ns = 1_000_000;
+    let subscriber_interval_ns = interval_ns * 3;
+    let snapshots = recorder
+        .subscribe(Duration::from_nanos(subscriber_interval_ns))
+        .unwrap();
+    let mut elapsed = 365 * 24 * 3_600 * 1_000_000_000;
+    let mut next_due = 0;
+    for i in 0..1_000_000 {
+        set_gauge(&recorder, "~temp", f64::from(i));
+        let tick = next_tick(elapsed, interval_ns);
+        sampler_tick(&recorder, tick);
+        let received: Vec<(u128, f64)> = snapshots
+            .try_iter()
+            .map(|s| (s.ts().as_nanos(), s.data()["temp"]))
+            .collect();
+        if tick >= next_due {
+            assert_eq!(received, [(u128::from(tick), f64::from(i))]);
+            next_due = next_tick(tick, subscriber_interval_ns);
+        } else {
+            assert!(received.is_empty());
+        }
+        elapsed = tick + delay(interval_ns * 2);
+    }
+}